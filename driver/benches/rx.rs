@@ -0,0 +1,58 @@
+//! Compares the receive path of `Interface.poll`, which converts received
+//! host frames into newly allocated frames, with that of
+//! `Interface.poll_into`, which converts them into a reused frame. Both take
+//! each transfer from the receive channel as the interface does, with one
+//! frame per transfer.
+//!
+//! Run with `cargo bench -p cantact-driver --features cantact-driver/testing --bench rx`.
+
+use std::collections::VecDeque;
+use std::hint::black_box;
+use std::time;
+
+use crossbeam_channel::unbounded;
+
+use cantact::{Frame, HostFrame, HostFrameBatch, RxPath};
+
+const ITERATIONS: u32 = 1_000_000;
+
+fn main() {
+    let hf = HostFrame::from(&Frame::new_fd(0x123, &[0xAA; 64], true).unwrap());
+    let (send, recv) = unbounded();
+
+    // as Interface.poll
+    let mut rx = RxPath::new(1);
+    let start = time::Instant::now();
+    for _ in 0..ITERATIONS {
+        let batch = HostFrameBatch::from(black_box(hf.clone()));
+        send.send(batch).unwrap();
+        let mut frames = Vec::new();
+        while let Ok(batch) = recv.try_recv() {
+            frames.extend(batch.into_iter().filter_map(|hf| rx.process(hf)));
+        }
+        black_box(frames);
+    }
+    let allocating = start.elapsed();
+
+    // as Interface.poll_into
+    let mut rx = RxPath::new(1);
+    let mut pending = VecDeque::new();
+    let mut f = Frame::default();
+    let start = time::Instant::now();
+    for _ in 0..ITERATIONS {
+        let batch = HostFrameBatch::from(black_box(hf.clone()));
+        send.send(batch).unwrap();
+        if let Ok(batch) = recv.try_recv() {
+            pending.extend(batch);
+        }
+        while let Some(hf) = pending.pop_front() {
+            if rx.process_into(&hf, &mut f) {
+                black_box(&f);
+            }
+        }
+    }
+    let reusing = start.elapsed();
+
+    println!("allocating: {:?}/frame", allocating / ITERATIONS);
+    println!("reusing:    {:?}/frame", reusing / ITERATIONS);
+}
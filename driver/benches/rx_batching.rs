@@ -0,0 +1,87 @@
+//! Compares ways of passing the host frames of each bulk in transfer through
+//! the receive channel: one frame at a time, in an allocated vector, and in a
+//! `HostFrameBatch` as the driver does. Each is run with one frame per
+//! transfer, the common case, and with several frames packed into a transfer.
+//!
+//! Run with `cargo bench -p cantact-driver --bench rx_batching`.
+
+use std::hint::black_box;
+use std::time;
+
+use crossbeam_channel::unbounded;
+
+use cantact::{Frame, HostFrame, HostFrameBatch};
+
+const FRAMES: usize = 1_000_000;
+
+// the wire format of a transfer holding `count` classic frames
+fn transfer(count: usize) -> Vec<u8> {
+    (0..count)
+        .flat_map(|n| {
+            let f = Frame::new_standard(0x100 + n as u16, &[n as u8; 8]).unwrap();
+            HostFrame::from(&f).to_le_bytes()
+        })
+        .collect()
+}
+
+fn per_frame(buf: &[u8], transfers: usize) -> time::Duration {
+    let (send, recv) = unbounded();
+    let start = time::Instant::now();
+    for _ in 0..transfers {
+        for hf in HostFrameBatch::parse(black_box(buf), false) {
+            send.send(hf).unwrap();
+        }
+        while let Ok(hf) = recv.try_recv() {
+            black_box(hf);
+        }
+    }
+    start.elapsed()
+}
+
+fn vec_batch(buf: &[u8], transfers: usize) -> time::Duration {
+    let (send, recv) = unbounded();
+    let start = time::Instant::now();
+    for _ in 0..transfers {
+        let batch: Vec<HostFrame> = HostFrameBatch::parse(black_box(buf), false)
+            .into_iter()
+            .collect();
+        send.send(batch).unwrap();
+        while let Ok(batch) = recv.try_recv() {
+            for hf in batch {
+                black_box(hf);
+            }
+        }
+    }
+    start.elapsed()
+}
+
+fn host_frame_batch(buf: &[u8], transfers: usize) -> time::Duration {
+    let (send, recv) = unbounded();
+    let start = time::Instant::now();
+    for _ in 0..transfers {
+        let batch = HostFrameBatch::parse(black_box(buf), false);
+        send.send(batch).unwrap();
+        while let Ok(batch) = recv.try_recv() {
+            for hf in batch {
+                black_box(hf);
+            }
+        }
+    }
+    start.elapsed()
+}
+
+fn main() {
+    for &count in &[1, 4] {
+        let buf = transfer(count);
+        let transfers = FRAMES / count;
+        let frames = (transfers * count) as u32;
+
+        let per_frame = per_frame(&buf, transfers) / frames;
+        let vec_batch = vec_batch(&buf, transfers) / frames;
+        let host_frame_batch = host_frame_batch(&buf, transfers) / frames;
+        println!("{} frame(s) per transfer:", count);
+        println!("  per frame:        {:?}/frame", per_frame);
+        println!("  vec batch:        {:?}/frame", vec_batch);
+        println!("  host frame batch: {:?}/frame", host_frame_batch);
+    }
+}
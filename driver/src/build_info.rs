@@ -0,0 +1,100 @@
+//! Version and build configuration of the driver, for support requests.
+
+use std::ffi::CStr;
+use std::fmt;
+
+use libusb1_sys::constants::LIBUSB_CAP_HAS_HOTPLUG;
+use libusb1_sys::{libusb_get_version, libusb_has_capability};
+
+/// The driver's version, the optional features it was built with, and the
+/// libusb it runs on. Returned by `build_info`.
+///
+/// The `Display` impl formats a short record such as:
+///
+/// ```text
+/// cantact-driver 0.1.3, features: none
+/// libusb 1.0.26.11724, hotplug: yes
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildInfo {
+    /// Version of the driver crate.
+    pub version: &'static str,
+    /// Optional cargo features enabled in this build.
+    pub features: Vec<&'static str>,
+    /// Version of the libusb library in use, as major.minor.micro.nano
+    /// followed by any release candidate suffix.
+    pub libusb_version: String,
+    /// True if libusb supports hotplug notifications on this platform.
+    pub hotplug: bool,
+}
+
+// optional features of this crate that are enabled
+fn enabled_features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "python") {
+        features.push("python");
+    }
+    if cfg!(feature = "testing") {
+        features.push("testing");
+    }
+    features
+}
+
+/// Returns the driver's version and build configuration, and the version of
+/// libusb it is running on.
+pub fn build_info() -> BuildInfo {
+    let v = unsafe { &*libusb_get_version() };
+    let rc = if v.rc.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(v.rc) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        features: enabled_features(),
+        libusb_version: format!("{}.{}.{}.{}{}", v.major, v.minor, v.micro, v.nano, rc),
+        hotplug: unsafe { libusb_has_capability(LIBUSB_CAP_HAS_HOTPLUG) } != 0,
+    }
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let features = if self.features.is_empty() {
+            String::from("none")
+        } else {
+            self.features.join(", ")
+        };
+        writeln!(f, "cantact-driver {}, features: {}", self.version, features)?;
+        write!(
+            f,
+            "libusb {}, hotplug: {}",
+            self.libusb_version,
+            if self.hotplug { "yes" } else { "no" }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_info() {
+        let info = build_info();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert!(info.libusb_version.starts_with("1."));
+
+        let info = BuildInfo {
+            version: "0.1.3",
+            features: vec!["python"],
+            libusb_version: String::from("1.0.26.11724"),
+            hotplug: false,
+        };
+        assert_eq!(
+            info.to_string(),
+            "cantact-driver 0.1.3, features: python\nlibusb 1.0.26.11724, hotplug: no"
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! Declarative configuration of an interface before it is opened.
+
+use std::time;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Channel, Error, Interface};
+
+/// Selects which device is opened when several are connected.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DeviceSelector {
+    /// The first device found.
+    #[default]
+    First,
+    /// The nth device found, counting from zero in the order devices are
+    /// listed by libusb.
+    Index(usize),
+    /// The device at the given USB bus number and device address.
+    Address {
+        /// USB bus number.
+        bus: u8,
+        /// Device address on the bus.
+        address: u8,
+    },
+}
+
+/// Source of received frame timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TimestampSource {
+    /// Use the device's hardware timestamps when it supports them, otherwise
+    /// the host clock.
+    #[default]
+    Auto,
+    /// Always use the host clock, even if the device has hardware timestamps.
+    Host,
+    /// Use hardware timestamps from a counter running at the given frequency
+    /// in Hz. Opening fails if the device does not support them.
+    Hardware(u32),
+}
+
+/// Builds an `Interface` from a complete configuration.
+///
+/// All settings are applied and validated by `open`, which returns an
+/// interface that is ready to be started. The builder can be serialized, so
+/// it can be stored in a configuration file.
+///
+/// ```no_run
+/// use cantact::{Channel, InterfaceBuilder};
+///
+/// let mut i = InterfaceBuilder::new()
+///     .channel(0, Channel { bitrate: 500_000, enabled: true, ..Default::default() })
+///     .open()?;
+/// i.start(0, |f| println!("{:?}", f))?;
+/// # Ok::<(), cantact::Error>(())
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InterfaceBuilder {
+    selector: DeviceSelector,
+    detach_kernel_driver: bool,
+    channels: Vec<(usize, Channel)>,
+    timestamp_source: TimestampSource,
+    max_retries: u32,
+    retry_backoff: time::Duration,
+    tx_padding: u8,
+    auto_dlc: bool,
+    default_rx_timeout: Option<time::Duration>,
+    // missing from builders stored before it was added
+    #[serde(default)]
+    tx_window: usize,
+}
+
+impl InterfaceBuilder {
+    /// Create a builder that opens the first device, detaching any kernel
+    /// driver, with all channels left at their defaults.
+    pub fn new() -> InterfaceBuilder {
+        InterfaceBuilder {
+            detach_kernel_driver: true,
+            retry_backoff: time::Duration::from_millis(10),
+            ..Default::default()
+        }
+    }
+
+    /// Select the device to open.
+    pub fn device(mut self, selector: DeviceSelector) -> InterfaceBuilder {
+        self.selector = selector;
+        self
+    }
+
+    /// Choose whether a kernel driver bound to the device is detached. See
+    /// `Interface::new_with_options`.
+    pub fn detach_kernel_driver(mut self, detach: bool) -> InterfaceBuilder {
+        self.detach_kernel_driver = detach;
+        self
+    }
+
+    /// Configure a channel. Channels that are not configured keep the
+    /// defaults of `Interface::new`. The `started` field is ignored, and the
+    /// other settings of a disabled channel are not applied.
+    pub fn channel(mut self, channel: usize, config: Channel) -> InterfaceBuilder {
+        self.channels.retain(|(n, _)| *n != channel);
+        self.channels.push((channel, config));
+        self
+    }
+
+    /// Select the source of received frame timestamps.
+    pub fn timestamp_source(mut self, source: TimestampSource) -> InterfaceBuilder {
+        self.timestamp_source = source;
+        self
+    }
+
+    /// Retry control transfers that fail with a transient USB error. See
+    /// `Interface::set_retry_policy`.
+    pub fn retry_policy(mut self, max_retries: u32, backoff: time::Duration) -> InterfaceBuilder {
+        self.max_retries = max_retries;
+        self.retry_backoff = backoff;
+        self
+    }
+
+    /// Set the byte used to pad transmitted frames. See
+    /// `Interface::set_tx_padding_byte`.
+    pub fn tx_padding_byte(mut self, pad: u8) -> InterfaceBuilder {
+        self.tx_padding = pad;
+        self
+    }
+
+    /// Set the DLC of transmitted frames from their data. See
+    /// `Interface::set_auto_dlc`.
+    pub fn auto_dlc(mut self, enabled: bool) -> InterfaceBuilder {
+        self.auto_dlc = enabled;
+        self
+    }
+
+    /// Set how long `Interface::recv` waits for a frame. See
+    /// `Interface::set_default_rx_timeout`.
+    pub fn default_rx_timeout(mut self, timeout: Option<time::Duration>) -> InterfaceBuilder {
+        self.default_rx_timeout = timeout;
+        self
+    }
+
+    /// Limit the number of sent frames awaiting their transmit confirmation.
+    /// See `Interface::set_tx_window`.
+    pub fn tx_window(mut self, max_in_flight: usize) -> InterfaceBuilder {
+        self.tx_window = max_in_flight;
+        self
+    }
+
+    /// Open the device and apply the configuration.
+    ///
+    /// Returns `Error::InvalidChannel` if a configured channel does not exist
+    /// on the device, and `Error::UnsupportedFeature` if a channel enables
+    /// CAN-FD or hardware timestamps are requested on a device without them.
+    pub fn open(self) -> Result<Interface, Error> {
+        let mut i = Interface::open(self.selector, self.detach_kernel_driver)?;
+        i.set_retry_policy(self.max_retries, self.retry_backoff);
+        i.set_tx_padding_byte(self.tx_padding);
+        i.set_auto_dlc(self.auto_dlc);
+        i.set_default_rx_timeout(self.default_rx_timeout);
+        i.set_tx_window(self.tx_window);
+
+        match self.timestamp_source {
+            TimestampSource::Auto => {}
+            TimestampSource::Host => i.timestamp_frequency = None,
+            TimestampSource::Hardware(freq) => i.set_timestamp_frequency(freq)?,
+        }
+
+        for (n, ch) in self.channels.iter() {
+            if *n >= i.channels() {
+                return Err(Error::InvalidChannel);
+            }
+            i.set_enabled(*n, ch.enabled)?;
+            if !ch.enabled {
+                continue;
+            }
+            i.set_bitrate(*n, ch.bitrate)?;
+            i.set_loopback(*n, ch.loopback)?;
+            i.set_monitor(*n, ch.monitor)?;
+            if ch.fd {
+                i.set_fd(*n, true)?;
+                i.set_data_bitrate(*n, ch.data_bitrate)?;
+            }
+        }
+        Ok(i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder() {
+        let b = InterfaceBuilder::new()
+            .device(DeviceSelector::Index(1))
+            .channel(
+                0,
+                Channel {
+                    bitrate: 250_000,
+                    ..Default::default()
+                },
+            )
+            .channel(
+                0,
+                Channel {
+                    bitrate: 500_000,
+                    ..Default::default()
+                },
+            )
+            .timestamp_source(TimestampSource::Host)
+            .default_rx_timeout(Some(time::Duration::from_millis(100)))
+            .tx_window(4);
+        assert_eq!(b.selector, DeviceSelector::Index(1));
+        assert!(b.detach_kernel_driver);
+        // configuring a channel again replaces the earlier configuration
+        assert_eq!(b.channels.len(), 1);
+        assert_eq!(b.channels[0].1.bitrate, 500_000);
+        assert_eq!(b.default_rx_timeout, Some(time::Duration::from_millis(100)));
+        assert_eq!(b.tx_window, 4);
+    }
+}
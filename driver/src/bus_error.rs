@@ -0,0 +1,232 @@
+//! Decoding of error frames reported by the device.
+//!
+//! Error frames use the SocketCAN layout: the error class is carried in the
+//! CAN ID, with details in the data bytes.
+
+use serde::{Deserialize, Serialize};
+
+use crate::Frame;
+
+// error classes in the CAN ID
+const CAN_ERR_LOSTARB: u32 = 0x0002;
+const CAN_ERR_CRTL: u32 = 0x0004;
+const CAN_ERR_PROT: u32 = 0x0008;
+const CAN_ERR_ACK: u32 = 0x0020;
+const CAN_ERR_BUSOFF: u32 = 0x0040;
+const CAN_ERR_RESTARTED: u32 = 0x0100;
+
+// protocol error type in data[2]
+const CAN_ERR_PROT_BIT: u8 = 0x01;
+const CAN_ERR_PROT_FORM: u8 = 0x02;
+const CAN_ERR_PROT_STUFF: u8 = 0x04;
+const CAN_ERR_PROT_BIT0: u8 = 0x08;
+const CAN_ERR_PROT_BIT1: u8 = 0x10;
+const CAN_ERR_PROT_OVERLOAD: u8 = 0x20;
+
+// protocol error location in data[3]
+const CAN_ERR_PROT_LOC_CRC_SEQ: u8 = 0x08;
+const CAN_ERR_PROT_LOC_CRC_DEL: u8 = 0x18;
+
+// controller status in data[1]
+const CAN_ERR_CRTL_RX_WARNING: u8 = 0x04;
+const CAN_ERR_CRTL_TX_WARNING: u8 = 0x08;
+const CAN_ERR_CRTL_RX_PASSIVE: u8 = 0x10;
+const CAN_ERR_CRTL_TX_PASSIVE: u8 = 0x20;
+
+/// A bus error condition reported by an error frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusError {
+    /// The controller has gone bus-off.
+    BusOff,
+    /// The controller has entered the error passive state.
+    ErrorPassive,
+    /// An error counter has reached the warning level.
+    ErrorWarning,
+    /// A transmitted frame was not acknowledged.
+    NoAck,
+    /// Arbitration was lost while transmitting.
+    ArbitrationLost,
+    /// A protocol violation (bit, stuff, form or CRC error) was detected.
+    Protocol,
+    /// The controller was restarted after bus-off.
+    Restarted,
+}
+
+impl BusError {
+    /// Decode the error condition of an error frame. Returns `None` if the
+    /// frame is not an error frame or reports no known condition.
+    ///
+    /// When a frame reports several conditions, the most severe is returned.
+    pub fn from_frame(f: &Frame) -> Option<BusError> {
+        if !f.err {
+            return None;
+        }
+        let status = f.data.get(1).copied().unwrap_or(0);
+        let id = f.can_id;
+
+        if id & CAN_ERR_BUSOFF != 0 {
+            Some(BusError::BusOff)
+        } else if id & CAN_ERR_CRTL != 0
+            && status & (CAN_ERR_CRTL_RX_PASSIVE | CAN_ERR_CRTL_TX_PASSIVE) != 0
+        {
+            Some(BusError::ErrorPassive)
+        } else if id & CAN_ERR_CRTL != 0
+            && status & (CAN_ERR_CRTL_RX_WARNING | CAN_ERR_CRTL_TX_WARNING) != 0
+        {
+            Some(BusError::ErrorWarning)
+        } else if id & CAN_ERR_ACK != 0 {
+            Some(BusError::NoAck)
+        } else if id & CAN_ERR_PROT != 0 {
+            Some(BusError::Protocol)
+        } else if id & CAN_ERR_LOSTARB != 0 {
+            Some(BusError::ArbitrationLost)
+        } else if id & CAN_ERR_RESTARTED != 0 {
+            Some(BusError::Restarted)
+        } else {
+            None
+        }
+    }
+
+    /// Create an error frame reporting this condition on a channel.
+    pub fn to_frame(self, channel: u8) -> Frame {
+        let mut data = vec![0u8; 8];
+        let can_id = match self {
+            BusError::BusOff => CAN_ERR_BUSOFF,
+            BusError::ErrorPassive => {
+                data[1] = CAN_ERR_CRTL_RX_PASSIVE | CAN_ERR_CRTL_TX_PASSIVE;
+                CAN_ERR_CRTL
+            }
+            BusError::ErrorWarning => {
+                data[1] = CAN_ERR_CRTL_RX_WARNING | CAN_ERR_CRTL_TX_WARNING;
+                CAN_ERR_CRTL
+            }
+            BusError::NoAck => CAN_ERR_ACK,
+            BusError::ArbitrationLost => CAN_ERR_LOSTARB,
+            BusError::Protocol => CAN_ERR_PROT,
+            BusError::Restarted => CAN_ERR_RESTARTED,
+        };
+        Frame {
+            can_id,
+            can_dlc: 8,
+            channel,
+            data,
+            err: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// Counts of error conditions reported by error frames on a channel,
+/// returned by `Interface::error_counters`.
+///
+/// An error frame can report several conditions, and each one is counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorCounters {
+    /// Error frames received.
+    pub error_frames: u64,
+    /// Bit errors: a transmitted bit was read back with the other level.
+    pub bit: u64,
+    /// Stuff errors: more than five consecutive bits of the same level.
+    pub stuff: u64,
+    /// Form errors: a fixed format field had an illegal value.
+    pub form: u64,
+    /// CRC errors: the received CRC did not match.
+    pub crc: u64,
+    /// Transmitted frames that were not acknowledged.
+    pub ack: u64,
+    /// Overload frames.
+    pub overload: u64,
+}
+
+impl ErrorCounters {
+    /// Count the conditions reported by a frame. Frames that are not error
+    /// frames are ignored.
+    pub fn record(&mut self, f: &Frame) {
+        if !f.err {
+            return;
+        }
+        self.error_frames += 1;
+        if f.can_id & CAN_ERR_ACK != 0 {
+            self.ack += 1;
+        }
+        if f.can_id & CAN_ERR_PROT == 0 {
+            return;
+        }
+
+        let kind = f.data.get(2).copied().unwrap_or(0);
+        let location = f.data.get(3).copied().unwrap_or(0);
+        if kind & (CAN_ERR_PROT_BIT | CAN_ERR_PROT_BIT0 | CAN_ERR_PROT_BIT1) != 0 {
+            self.bit += 1;
+        }
+        if kind & CAN_ERR_PROT_STUFF != 0 {
+            self.stuff += 1;
+        }
+        if kind & CAN_ERR_PROT_FORM != 0 {
+            self.form += 1;
+        }
+        if kind & CAN_ERR_PROT_OVERLOAD != 0 {
+            self.overload += 1;
+        }
+        if location == CAN_ERR_PROT_LOC_CRC_SEQ || location == CAN_ERR_PROT_LOC_CRC_DEL {
+            self.crc += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bus_error() {
+        for e in [
+            BusError::BusOff,
+            BusError::ErrorPassive,
+            BusError::ErrorWarning,
+            BusError::NoAck,
+            BusError::ArbitrationLost,
+            BusError::Protocol,
+            BusError::Restarted,
+        ] {
+            assert_eq!(BusError::from_frame(&e.to_frame(1)), Some(e));
+        }
+
+        // bus-off takes precedence over other conditions
+        let mut f = BusError::NoAck.to_frame(0);
+        f.can_id |= CAN_ERR_BUSOFF;
+        assert_eq!(BusError::from_frame(&f), Some(BusError::BusOff));
+
+        let f = Frame::new_standard(0x40, &[]).unwrap();
+        assert_eq!(BusError::from_frame(&f), None);
+    }
+
+    #[test]
+    fn test_error_counters() {
+        let mut counters = ErrorCounters::default();
+        counters.record(&BusError::NoAck.to_frame(0));
+
+        let mut f = BusError::Protocol.to_frame(0);
+        f.data[2] = CAN_ERR_PROT_STUFF | CAN_ERR_PROT_BIT0;
+        counters.record(&f);
+        f.data[2] = CAN_ERR_PROT_FORM;
+        f.data[3] = CAN_ERR_PROT_LOC_CRC_DEL;
+        counters.record(&f);
+        f.data[2] = CAN_ERR_PROT_OVERLOAD;
+        f.data[3] = 0;
+        counters.record(&f);
+        counters.record(&Frame::new_standard(0x20, &[]).unwrap());
+
+        assert_eq!(
+            counters,
+            ErrorCounters {
+                error_frames: 4,
+                bit: 1,
+                stuff: 1,
+                form: 1,
+                crc: 1,
+                ack: 1,
+                overload: 1,
+            }
+        );
+    }
+}
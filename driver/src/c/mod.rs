@@ -8,7 +8,7 @@
 
 #![allow(clippy::missing_safety_doc)]
 
-use crate::{Frame, Interface};
+use crate::{Frame, FrameOrigin, Interface};
 
 /// A CAN frame in a C representation
 #[repr(C)]
@@ -34,7 +34,7 @@ impl CFrame {
             channel: f.channel,
             id: f.can_id,
             dlc: f.can_dlc,
-            data: f.data_as_array(),
+            data: f.data_as_array(0),
             ext: if f.ext { 1 } else { 0 },
             fd: if f.fd { 1 } else { 0 },
             brs: if f.brs { 1 } else { 0 },
@@ -166,9 +166,11 @@ pub unsafe extern "C" fn cantact_transmit(ptr: *mut CInterface, cf: CFrame) -> i
         brs: cf.brs > 0,
         esi: cf.esi > 0,
         loopback: false,
+        origin: FrameOrigin::Received,
         rtr: cf.rtr > 0,
         err: cf.err > 0,
         timestamp: None,
+        raw_can_id: None,
     };
     match &mut ci.i {
         Some(i) => i.send(f).expect("failed to transmit frame"),
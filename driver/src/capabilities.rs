@@ -0,0 +1,136 @@
+//! Human readable report of a device's capabilities.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(any(feature = "usb", test))]
+use crate::gsusb::*;
+
+/// The capabilities of a device, derived from the feature flags and versions
+/// it reports. Returned by `Interface::capabilities`.
+///
+/// The `Display` impl formats a short report suitable for bug reports.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapabilityReport {
+    /// CAN-FD is supported.
+    pub fd: bool,
+    /// Hardware timestamps are supported.
+    pub hw_timestamps: bool,
+    /// Listen only (monitor) mode is supported.
+    pub listen_only: bool,
+    /// One-shot mode, without automatic retransmission, is supported.
+    pub one_shot: bool,
+    /// Bus termination can be switched by the host.
+    pub termination: bool,
+    /// Hardware loopback mode is supported.
+    pub loopback: bool,
+    /// Triple sampling is supported.
+    pub triple_sample: bool,
+    /// The device LED can be blinked for identification.
+    pub identify: bool,
+    /// The bus state and error counters can be read.
+    pub bus_state: bool,
+    /// Protocol errors can be reported as error frames.
+    pub error_reporting: bool,
+    /// Number of channels.
+    pub channels: usize,
+    /// Frequency of the CAN controller clock in Hz.
+    pub can_clock: u32,
+    /// Firmware version.
+    pub sw_version: u32,
+    /// Hardware version.
+    pub hw_version: u32,
+    /// The raw gs_usb feature flags.
+    pub features: u32,
+}
+
+#[cfg(any(feature = "usb", test))]
+impl CapabilityReport {
+    pub(crate) fn new(
+        features: u32,
+        channels: usize,
+        can_clock: u32,
+        sw_version: u32,
+        hw_version: u32,
+    ) -> CapabilityReport {
+        let has = |flag| (features & flag) != 0;
+        CapabilityReport {
+            fd: has(GS_CAN_FEATURE_FD),
+            hw_timestamps: has(GS_CAN_FEATURE_HW_TIMESTAMP),
+            listen_only: has(GS_CAN_FEATURE_LISTEN_ONLY),
+            one_shot: has(GS_CAN_FEATURE_ONE_SHOT),
+            termination: has(GS_CAN_FEATURE_TERMINATION),
+            loopback: has(GS_CAN_FEATURE_LOOP_BACK),
+            triple_sample: has(GS_CAN_FEATURE_TRIPLE_SAMPLE),
+            identify: has(GS_CAN_FEATURE_IDENTIFY),
+            bus_state: has(GS_CAN_FEATURE_GET_STATE),
+            error_reporting: has(GS_CAN_FEATURE_BERR_REPORTING),
+            channels,
+            can_clock,
+            sw_version,
+            hw_version,
+            features,
+        }
+    }
+}
+
+fn yes_no(b: bool) -> &'static str {
+    if b {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+impl fmt::Display for CapabilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "FD: {}, hardware timestamps: {}, listen-only: {}, one-shot: {}, termination control: {}",
+            yes_no(self.fd),
+            yes_no(self.hw_timestamps),
+            yes_no(self.listen_only),
+            yes_no(self.one_shot),
+            yes_no(self.termination),
+        )?;
+        writeln!(
+            f,
+            "loopback: {}, triple sampling: {}, identify: {}, bus state: {}, error reporting: {}",
+            yes_no(self.loopback),
+            yes_no(self.triple_sample),
+            yes_no(self.identify),
+            yes_no(self.bus_state),
+            yes_no(self.error_reporting),
+        )?;
+        write!(
+            f,
+            "channels: {}, CAN clock: {} Hz, firmware version: {}, hardware version: {}, features: {:#010x}",
+            self.channels, self.can_clock, self.sw_version, self.hw_version, self.features,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let features = GS_CAN_FEATURE_FD
+            | GS_CAN_FEATURE_HW_TIMESTAMP
+            | GS_CAN_FEATURE_LISTEN_ONLY
+            | GS_CAN_FEATURE_LOOP_BACK;
+        let r = CapabilityReport::new(features, 2, 80_000_000, 2, 1);
+        assert!(r.fd && r.hw_timestamps && r.listen_only && r.loopback);
+        assert!(!r.one_shot && !r.termination && !r.error_reporting);
+
+        let s = r.to_string();
+        assert_eq!(
+            s.lines().next().unwrap(),
+            "FD: yes, hardware timestamps: yes, listen-only: yes, one-shot: no, termination control: no"
+        );
+        assert!(s.contains("channels: 2, CAN clock: 80000000 Hz"));
+        assert!(s.contains("features: 0x00000113"));
+    }
+}
@@ -0,0 +1,150 @@
+//! Channel settings of a device that can be stored, for example in a
+//! configuration file, and applied to an interface again later.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Channel;
+#[cfg(feature = "usb")]
+use crate::{Error, Interface};
+
+/// Settings of each channel of a device, numbered from 0.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// Version of the format the configuration was written in. Stored
+    /// configurations without a version predate versioning and are version 0.
+    #[serde(default)]
+    pub version: u32,
+    /// Settings of each channel.
+    #[serde(rename = "channel")]
+    pub channels: Vec<Channel>,
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Channels:")?;
+        for (n, ch) in self.channels.iter().enumerate() {
+            writeln!(f, "\t{} -> {:?}", n, ch)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    /// Two channels with the default settings.
+    fn default() -> Config {
+        Config::builder()
+            .channel(Config::DEFAULT_CHANNEL)
+            .channel(Config::DEFAULT_CHANNEL)
+            .build()
+    }
+}
+
+/// Assembles a configuration in code, without a file. Created with
+/// `Config::builder`.
+pub struct ConfigBuilder {
+    channels: Vec<Channel>,
+}
+
+impl ConfigBuilder {
+    /// Add a channel, numbered in the order channels are added.
+    pub fn channel(mut self, ch: Channel) -> ConfigBuilder {
+        self.channels.push(ch);
+        self
+    }
+
+    /// Add a channel with the default settings and the given bitrate.
+    pub fn bitrate(self, bitrate: u32) -> ConfigBuilder {
+        self.channel(Channel {
+            bitrate,
+            ..Config::DEFAULT_CHANNEL
+        })
+    }
+
+    /// Create the configuration, at the current version.
+    pub fn build(self) -> Config {
+        Config {
+            version: Config::VERSION,
+            channels: self.channels,
+        }
+    }
+}
+
+impl Config {
+    /// Version of the configuration format written by this release.
+    pub const VERSION: u32 = 1;
+
+    /// Settings of a channel that is not configured otherwise. Also used
+    /// for settings missing from configurations written by older releases.
+    pub const DEFAULT_CHANNEL: Channel = Channel {
+        bitrate: 500_000,
+        data_bitrate: 500_000,
+        loopback: false,
+        monitor: false,
+        fd: false,
+        enabled: true,
+        started: true,
+    };
+
+    /// Returns a builder for a configuration without channels.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder {
+            channels: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "usb")]
+impl Config {
+    /// Snapshot the channel settings of an interface, so they can be stored
+    /// and applied again later with `Config::apply_to_interface`.
+    pub fn from_interface(i: &Interface) -> Config {
+        Config {
+            version: Config::VERSION,
+            channels: i.channel_configs().to_vec(),
+        }
+    }
+
+    /// Apply the channel settings to an interface. Channels beyond those
+    /// of the device are ignored, as are the FD settings if the device does
+    /// not support CAN FD.
+    pub fn apply_to_interface(&self, i: &mut Interface) -> Result<(), Error> {
+        for (n, ch) in self.channels.iter().enumerate() {
+            if n >= i.channels() {
+                // device doesn't have as many channels as config, ignore the rest
+                break;
+            }
+            i.set_bitrate(n, ch.bitrate)?;
+            i.set_enabled(n, ch.enabled)?;
+            i.set_loopback(n, ch.loopback)?;
+            i.set_monitor(n, ch.monitor)?;
+            if i.supports_fd() {
+                i.set_fd(n, ch.fd)?;
+                i.set_data_bitrate(n, ch.data_bitrate)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder() {
+        let c = Config::builder()
+            .bitrate(250_000)
+            .channel(Channel {
+                fd: true,
+                ..Config::DEFAULT_CHANNEL
+            })
+            .build();
+        assert_eq!(c.version, Config::VERSION);
+        assert_eq!(c.channels.len(), 2);
+        assert_eq!(c.channels[0].bitrate, 250_000);
+        assert!(c.channels[0].enabled && !c.channels[0].fd);
+        assert!(c.channels[1].fd);
+    }
+}
@@ -0,0 +1,428 @@
+//! Minimal DBC database support for decoding frames into named signals.
+//!
+//! Messages (`BO_`), signals (`SG_`), including simple multiplexing, and
+//! value descriptions (`VAL_`) are read. All other DBC sections are ignored.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::Frame;
+
+// DBC message IDs have this bit set for extended identifiers
+const DBC_EXT_FLAG: u32 = 0x8000_0000;
+
+/// Errors generated while loading a DBC file
+#[derive(Debug)]
+pub enum DbcError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// A line could not be parsed.
+    Parse {
+        /// Line number, counting from one.
+        line: usize,
+        /// Description of the problem.
+        message: &'static str,
+    },
+}
+impl From<io::Error> for DbcError {
+    fn from(e: io::Error) -> DbcError {
+        DbcError::Io(e)
+    }
+}
+
+/// Byte order of a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// Intel byte order.
+    LittleEndian,
+    /// Motorola byte order.
+    BigEndian,
+}
+
+/// Multiplexing role of a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multiplex {
+    /// The signal is always present.
+    None,
+    /// The signal selects which multiplexed signals are present.
+    Multiplexor,
+    /// The signal is present when the multiplexor has the given value.
+    Multiplexed(u64),
+}
+
+/// A signal within a message.
+#[derive(Debug, Clone)]
+pub struct Signal {
+    /// Signal name.
+    pub name: String,
+    /// Start bit, as given in the DBC file.
+    pub start_bit: u32,
+    /// Length in bits.
+    pub size: u32,
+    /// Byte order.
+    pub byte_order: ByteOrder,
+    /// True if the raw value is two's complement signed.
+    pub signed: bool,
+    /// Scale applied to the raw value.
+    pub factor: f64,
+    /// Offset added after scaling.
+    pub offset: f64,
+    /// Unit of the physical value.
+    pub unit: String,
+    /// Multiplexing role.
+    pub multiplex: Multiplex,
+    /// Descriptions of raw values.
+    pub values: HashMap<i64, String>,
+}
+
+impl Signal {
+    /// Extract the raw value of the signal from frame data. Returns `None`
+    /// if the data is too short.
+    pub fn raw(&self, data: &[u8]) -> Option<i64> {
+        if self.size == 0 || self.size > 64 {
+            return None;
+        }
+        let bit = |n: u32| -> Option<u64> {
+            let byte = data.get((n / 8) as usize)?;
+            Some(((byte >> (n % 8)) & 1) as u64)
+        };
+
+        let mut value: u64 = 0;
+        match self.byte_order {
+            ByteOrder::LittleEndian => {
+                for i in 0..self.size {
+                    value |= bit(self.start_bit + i)? << i;
+                }
+            }
+            ByteOrder::BigEndian => {
+                // the start bit is the most significant bit. bits run down
+                // within a byte, then continue at the top of the next byte.
+                let mut n = self.start_bit;
+                for _ in 0..self.size {
+                    value = (value << 1) | bit(n)?;
+                    n = if n.is_multiple_of(8) { n + 15 } else { n - 1 };
+                }
+            }
+        }
+
+        if self.signed && self.size < 64 && (value >> (self.size - 1)) & 1 == 1 {
+            // sign extend
+            value |= !0u64 << self.size;
+        }
+        Some(value as i64)
+    }
+
+    /// Decode the physical value of the signal from frame data.
+    pub fn decode(&self, data: &[u8]) -> Option<f64> {
+        self.raw(data)
+            .map(|raw| raw as f64 * self.factor + self.offset)
+    }
+
+    /// Format the signal value as `Name=value`, using the value description
+    /// when one exists.
+    pub fn format(&self, data: &[u8]) -> Option<String> {
+        let raw = self.raw(data)?;
+        if let Some(desc) = self.values.get(&raw) {
+            return Some(format!("{}={}", self.name, desc));
+        }
+        let value = raw as f64 * self.factor + self.offset;
+        // avoid printing floating point noise from the scaling
+        let value = (value * 1e6).round() / 1e6;
+        Some(format!("{}={}", self.name, value))
+    }
+}
+
+/// A message in the database.
+#[derive(Debug, Clone)]
+pub struct Message {
+    /// CAN identifier, without the DBC extended flag.
+    pub id: u32,
+    /// True if the message uses an extended identifier.
+    pub ext: bool,
+    /// Message name.
+    pub name: String,
+    /// Data length in bytes.
+    pub size: u32,
+    /// Signals of the message.
+    pub signals: Vec<Signal>,
+}
+
+impl Message {
+    /// Returns the signals present in the data, taking multiplexing into
+    /// account.
+    pub fn active_signals<'a>(&'a self, data: &'a [u8]) -> impl Iterator<Item = &'a Signal> {
+        let mux = self
+            .signals
+            .iter()
+            .find(|s| s.multiplex == Multiplex::Multiplexor)
+            .and_then(|s| s.raw(data))
+            .map(|v| v as u64);
+        self.signals.iter().filter(move |s| match s.multiplex {
+            Multiplex::Multiplexed(v) => Some(v) == mux,
+            _ => true,
+        })
+    }
+}
+
+/// A loaded DBC database.
+#[derive(Debug, Clone, Default)]
+pub struct Database {
+    messages: HashMap<u32, Message>,
+}
+
+impl Database {
+    /// Load a database from a DBC file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Database, DbcError> {
+        let s = fs::read_to_string(path)?;
+        Database::parse(&s)
+    }
+
+    /// Parse a database from the contents of a DBC file.
+    pub fn parse(s: &str) -> Result<Database, DbcError> {
+        let mut db = Database::default();
+        // key of the message the following signals belong to
+        let mut current: Option<u32> = None;
+
+        for (n, line) in s.lines().enumerate() {
+            let err = |message| DbcError::Parse {
+                line: n + 1,
+                message,
+            };
+            let tokens = tokenize(line);
+            match tokens.first().map(|t| t.as_str()) {
+                Some("BO_") => {
+                    let m = parse_message(&tokens).ok_or_else(|| err("invalid message"))?;
+                    let key = key(m.id, m.ext);
+                    db.messages.insert(key, m);
+                    current = Some(key);
+                }
+                Some("SG_") => {
+                    let s = parse_signal(&tokens).ok_or_else(|| err("invalid signal"))?;
+                    match current.and_then(|k| db.messages.get_mut(&k)) {
+                        Some(m) => m.signals.push(s),
+                        None => return Err(err("signal outside of a message")),
+                    }
+                }
+                Some("VAL_") => {
+                    let (id, signal, values) =
+                        parse_values(&tokens).ok_or_else(|| err("invalid value description"))?;
+                    let signal = db
+                        .messages
+                        .get_mut(&id)
+                        .and_then(|m| m.signals.iter_mut().find(|s| s.name == signal));
+                    if let Some(s) = signal {
+                        s.values = values;
+                    }
+                }
+                Some(_) => current = None,
+                // blank lines do not end a message
+                None => {}
+            }
+        }
+        Ok(db)
+    }
+
+    /// Returns the message definition for a frame, if the database has one.
+    pub fn message(&self, f: &Frame) -> Option<&Message> {
+        self.messages.get(&key(f.can_id, f.ext))
+    }
+
+    /// Format a frame as `MsgName: SigA=12.3 SigB=on`. Returns `None` if the
+    /// frame's ID is not in the database.
+    pub fn format(&self, f: &Frame) -> Option<String> {
+        let m = self.message(f)?;
+        let data = f.payload();
+
+        let mut s = format!("{}:", m.name);
+        for sig in m.active_signals(data) {
+            if let Some(v) = sig.format(data) {
+                s.push(' ');
+                s.push_str(&v);
+            }
+        }
+        Some(s)
+    }
+}
+
+fn key(id: u32, ext: bool) -> u32 {
+    if ext {
+        id | DBC_EXT_FLAG
+    } else {
+        id
+    }
+}
+
+// split a line on whitespace, keeping quoted strings (without the quotes) as
+// single tokens
+fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut chars = line.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let mut t = String::new();
+        if c == '"' {
+            for c in chars.by_ref() {
+                if c == '"' {
+                    break;
+                }
+                t.push(c);
+            }
+        } else {
+            t.push(c);
+            while let Some(c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                t.push(*c);
+                chars.next();
+            }
+        }
+        tokens.push(t);
+    }
+    tokens
+}
+
+// BO_ <id> <name>: <size> <transmitter>
+fn parse_message(tokens: &[String]) -> Option<Message> {
+    let id: u32 = tokens.get(1)?.parse().ok()?;
+    let name = tokens.get(2)?.trim_end_matches(':').to_string();
+    let size = tokens.get(3)?.parse().ok()?;
+    Some(Message {
+        id: id & !DBC_EXT_FLAG,
+        ext: (id & DBC_EXT_FLAG) != 0,
+        name,
+        size,
+        signals: vec![],
+    })
+}
+
+// SG_ <name> [M|m<n>] : <start>|<size>@<order><sign> (<factor>,<offset>)
+//     [<min>|<max>] "<unit>" <receivers>
+fn parse_signal(tokens: &[String]) -> Option<Signal> {
+    let colon = tokens.iter().position(|t| t == ":" || t.ends_with(':'))?;
+    let name = tokens.get(1)?.trim_end_matches(':').to_string();
+    let multiplex = match tokens.get(2).filter(|_| colon == 3).map(|t| t.as_str()) {
+        None => Multiplex::None,
+        Some("M") => Multiplex::Multiplexor,
+        Some(m) => Multiplex::Multiplexed(m.strip_prefix('m')?.parse().ok()?),
+    };
+    let rest = &tokens[colon + 1..];
+
+    // <start>|<size>@<order><sign>
+    let layout = rest.first()?;
+    let (start, layout) = layout.split_once('|')?;
+    let (size, layout) = layout.split_once('@')?;
+    let byte_order = match layout.get(..1)? {
+        "1" => ByteOrder::LittleEndian,
+        "0" => ByteOrder::BigEndian,
+        _ => return None,
+    };
+    let signed = match layout.get(1..2)? {
+        "-" => true,
+        "+" => false,
+        _ => return None,
+    };
+
+    // (<factor>,<offset>)
+    let scale = rest.get(1)?.trim_start_matches('(').trim_end_matches(')');
+    let (factor, offset) = scale.split_once(',')?;
+
+    Some(Signal {
+        name,
+        start_bit: start.parse().ok()?,
+        size: size.parse().ok()?,
+        byte_order,
+        signed,
+        factor: factor.parse().ok()?,
+        offset: offset.parse().ok()?,
+        unit: rest.get(3).cloned().unwrap_or_default(),
+        multiplex,
+        values: HashMap::new(),
+    })
+}
+
+// VAL_ <id> <signal> <value> "<description>" ... ;
+fn parse_values(tokens: &[String]) -> Option<(u32, String, HashMap<i64, String>)> {
+    let id: u32 = tokens.get(1)?.parse().ok()?;
+    let signal = tokens.get(2)?.clone();
+    let mut values = HashMap::new();
+    let pairs = tokens[3..].iter().filter(|t| *t != ";").collect::<Vec<_>>();
+    for pair in pairs.chunks(2) {
+        if let [value, desc] = pair {
+            values.insert(value.parse().ok()?, desc.to_string());
+        }
+    }
+    Some((id, signal, values))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DBC: &str = r#"
+VERSION ""
+
+BO_ 256 Engine: 8 ECU
+ SG_ Speed : 0|16@1+ (0.1,0) [0|6553.5] "km/h" Vector__XXX
+ SG_ Temp : 16|8@1- (1,-40) [-40|215] "degC" Vector__XXX
+ SG_ Status : 24|1@1+ (1,0) [0|1] "" Vector__XXX
+
+BO_ 2147484672 Body: 4 BCM
+ SG_ Mux M : 7|8@0+ (1,0) [0|255] "" Vector__XXX
+ SG_ Door m0 : 15|16@0+ (1,0) [0|65535] "" Vector__XXX
+ SG_ Light m1 : 15|16@0+ (1,0) [0|65535] "" Vector__XXX
+
+VAL_ 256 Status 0 "off" 1 "on" ;
+"#;
+
+    #[test]
+    fn test_parse() {
+        let db = Database::parse(DBC).unwrap();
+        let f = Frame::new_standard(0x100, &[0x7B, 0x00, 0x32, 0x01]).unwrap();
+        let m = db.message(&f).unwrap();
+        assert_eq!(m.name, "Engine");
+        assert_eq!(m.signals.len(), 3);
+        assert_eq!(m.signals[0].unit, "km/h");
+        assert_eq!(
+            db.format(&f).unwrap(),
+            "Engine: Speed=12.3 Temp=10 Status=on"
+        );
+
+        // unknown IDs are not decoded
+        let f = Frame::new_standard(0x101, &[]).unwrap();
+        assert!(db.format(&f).is_none());
+
+        assert!(Database::parse(" SG_ Orphan : 0|8@1+ (1,0) [0|1] \"\" X").is_err());
+    }
+
+    #[test]
+    fn test_big_endian_multiplexed() {
+        let db = Database::parse(DBC).unwrap();
+        let f = Frame::new_extended(0x400, &[0x01, 0x12, 0x34, 0x00]).unwrap();
+        assert_eq!(db.format(&f).unwrap(), "Body: Mux=1 Light=4660");
+        let f = Frame::new_extended(0x400, &[0x00, 0x00, 0x02, 0x00]).unwrap();
+        assert_eq!(db.format(&f).unwrap(), "Body: Mux=0 Door=2");
+    }
+
+    #[test]
+    fn test_signed() {
+        let s = Signal {
+            name: String::from("S"),
+            start_bit: 0,
+            size: 8,
+            byte_order: ByteOrder::LittleEndian,
+            signed: true,
+            factor: 1.0,
+            offset: 0.0,
+            unit: String::new(),
+            multiplex: Multiplex::None,
+            values: HashMap::new(),
+        };
+        assert_eq!(s.raw(&[0xFF]), Some(-1));
+        assert_eq!(s.raw(&[]), None);
+    }
+}
@@ -6,35 +6,114 @@ use libusb1_sys::*;
 use std::mem;
 use std::mem::size_of;
 use std::ptr;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::sync::RwLock;
+use std::sync::{Mutex, RwLock};
 use std::thread;
+use std::time;
 
-pub mod gsusb;
-pub(crate) use gsusb::*;
+use crate::{DeviceInfo, DeviceSelector, UsbInfo};
+
+pub(crate) use crate::gsusb::*;
 
 // CANtact USB VID / PID
-const USB_VID: u16 = 0x1d50;
-const USB_PID: u16 = 0x606f;
+pub(crate) const USB_VID: u16 = 0x1d50;
+pub(crate) const USB_PID: u16 = 0x606f;
+
+// identities of listed devices, read once per device. an address is not
+// reused while a device stays connected, so entries are dropped once their
+// device is no longer listed.
+static IDENTITIES: Mutex<Vec<(DeviceInfo, UsbIdentity)>> = Mutex::new(Vec::new());
+
+// interface and endpoints of devices whose configuration descriptor has no
+// gs_usb interface, as used by single interface firmware
+const DEFAULT_USB_INFO: UsbInfo = UsbInfo {
+    interface: 0,
+    bulk_in: 0x81,
+    bulk_out: 0x02,
+};
 
 // buffer size for control in/out transfers
 const CTRL_BUF_SIZE: usize = 64;
 // number of bulk in transfers
 const BULK_IN_TRANSFER_COUNT: usize = 32;
-// buffer size for bulk in transfer
-const BULK_IN_BUF_SIZE: usize = 76;
+// buffer size for bulk in transfers, which may hold a batch of several
+// frames: up to 16 classic frames, or 3 FD frames with hardware timestamps
+const BULK_IN_BUF_SIZE: usize = 320;
 // timeout for bulk in transfers
 const BULK_IN_TIMEOUT_MS: u32 = 5000;
+// time allowed for cancelled bulk in transfers to complete
+const CANCEL_TIMEOUT: time::Duration = time::Duration::from_secs(1);
 
 #[derive(Debug)]
 pub enum Error {
     Libusb(&'static str, i32),
+    // a submitted transfer completed with a libusb_transfer_status other
+    // than LIBUSB_TRANSFER_COMPLETED
+    Transfer(&'static str, i32),
     DeviceNotFound,
+    // the device was found but the user may not open it
+    PermissionDenied,
+    // the interface is claimed by another process or a kernel driver
+    DeviceBusy,
     TransferAllocFailed,
     InvalidControlResponse,
 }
 
+impl Error {
+    // transient errors that may succeed if the transfer is retried. errors
+    // caused by a missing device are never retried.
+    fn is_retryable(&self) -> bool {
+        match self {
+            Error::Libusb(_, e) => matches!(
+                *e,
+                LIBUSB_ERROR_TIMEOUT
+                    | LIBUSB_ERROR_PIPE
+                    | LIBUSB_ERROR_BUSY
+                    | LIBUSB_ERROR_INTERRUPTED
+            ),
+            Error::Transfer(_, s) => {
+                matches!(*s, LIBUSB_TRANSFER_TIMED_OUT | LIBUSB_TRANSFER_STALL)
+            }
+            _ => false,
+        }
+    }
+}
+
+// retry policy for control transfers
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RetryPolicy {
+    pub max_retries: u32,
+    // delay before the first retry, doubled for each following retry
+    pub backoff: time::Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 0,
+            backoff: time::Duration::from_millis(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn run<T, F: FnMut() -> Result<T, Error>>(&self, mut f: F) -> Result<T, Error> {
+        let mut backoff = self.backoff;
+        let mut retries = 0;
+        loop {
+            match f() {
+                Err(e) if e.is_retryable() && retries < self.max_retries => {
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    retries += 1;
+                }
+                r => return r,
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct UsbContext {
     ctx: *mut libusb_context,
@@ -53,7 +132,7 @@ impl UsbContext {
             _ => panic!("could not initialize libusb context"),
         }
     }
-    fn as_ptr(&self) -> *mut libusb_context {
+    pub(crate) fn as_ptr(&self) -> *mut libusb_context {
         self.ctx
     }
 }
@@ -67,10 +146,13 @@ pub(crate) struct Device {
     ctx: Arc<UsbContext>,
     hnd: ptr::NonNull<libusb_device_handle>,
     running: Arc<AtomicBool>,
+    hw_timestamps: AtomicBool,
 
     ctrl_transfer: ptr::NonNull<libusb_transfer>,
     ctrl_buf: [u8; CTRL_BUF_SIZE],
     ctrl_transfer_pending: RwLock<bool>,
+    ctrl_transfer_status: AtomicI32,
+    retry_policy: RetryPolicy,
 
     out_transfer: ptr::NonNull<libusb_transfer>,
     out_buf: Vec<u8>,
@@ -78,16 +160,107 @@ pub(crate) struct Device {
 
     in_transfers: [*mut libusb_transfer; BULK_IN_TRANSFER_COUNT],
     in_bufs: [[u8; BULK_IN_BUF_SIZE]; BULK_IN_TRANSFER_COUNT],
+    // bulk in transfers submitted and not yet completed for good
+    in_pending: AtomicUsize,
+    // set while the bulk in transfers are being stopped, so completed
+    // transfers are not resubmitted
+    in_stopping: AtomicBool,
+    // interface and endpoints found in the configuration descriptor
+    usb_info: UsbInfo,
+
+    // number of bulk in transfers that delivered frames
+    pub(crate) rx_activity: Arc<AtomicU64>,
+    // set when a transfer reports that the device is gone
+    pub(crate) disconnected: Arc<AtomicBool>,
+
+    // frames are passed in batches of all frames from one transfer
+    can_rx_send: Sender<HostFrameBatch>,
+    pub can_rx_recv: Receiver<HostFrameBatch>,
+}
+
+// number, class and endpoints of an interface in a configuration descriptor
+struct InterfaceDesc {
+    number: u8,
+    class: u8,
+    // (bEndpointAddress, bmAttributes)
+    endpoints: Vec<(u8, u8)>,
+}
+
+// pick the first vendor specific interface with a bulk in and a bulk out
+// endpoint, which is the gs_usb interface of a composite device
+fn select_interface(interfaces: &[InterfaceDesc]) -> Option<UsbInfo> {
+    let bulk = |attributes: u8| attributes & LIBUSB_TRANSFER_TYPE_MASK == LIBUSB_TRANSFER_TYPE_BULK;
+    interfaces
+        .iter()
+        .filter(|i| i.class == LIBUSB_CLASS_VENDOR_SPEC)
+        .find_map(|i| {
+            let endpoint = |dir| {
+                i.endpoints
+                    .iter()
+                    .find(|(addr, attr)| bulk(*attr) && addr & LIBUSB_ENDPOINT_DIR_MASK == dir)
+                    .map(|(addr, _)| *addr)
+            };
+            Some(UsbInfo {
+                interface: i.number,
+                bulk_in: endpoint(LIBUSB_ENDPOINT_IN)?,
+                bulk_out: endpoint(LIBUSB_ENDPOINT_OUT)?,
+            })
+        })
+}
+
+// read the first alternate setting of each interface of the active
+// configuration
+fn read_interfaces(dev: *mut libusb_device) -> Result<Vec<InterfaceDesc>, Error> {
+    let mut config = ptr::null();
+    match unsafe { libusb_get_active_config_descriptor(dev, &mut config) } {
+        LIBUSB_SUCCESS => {}
+        e => return Err(Error::Libusb("libusb_get_active_config_descriptor", e)),
+    }
+
+    let mut interfaces = Vec::new();
+    let c = unsafe { &*config };
+    for n in 0..c.bNumInterfaces as usize {
+        let interface = unsafe { &*c.interface.add(n) };
+        if interface.num_altsetting < 1 {
+            continue;
+        }
+        let alt = unsafe { &*interface.altsetting };
+        let endpoints = (0..alt.bNumEndpoints as usize)
+            .map(|e| {
+                let ep = unsafe { &*alt.endpoint.add(e) };
+                (ep.bEndpointAddress, ep.bmAttributes)
+            })
+            .collect();
+        interfaces.push(InterfaceDesc {
+            number: alt.bInterfaceNumber,
+            class: alt.bInterfaceClass,
+            endpoints,
+        });
+    }
 
-    can_rx_send: Sender<HostFrame>,
-    pub can_rx_recv: Receiver<HostFrame>,
+    unsafe { libusb_free_config_descriptor(config) };
+    Ok(interfaces)
 }
 
+// USB descriptor fields identifying an open device
+#[derive(Debug, Clone)]
+pub(crate) struct UsbIdentity {
+    pub(crate) vendor_id: u16,
+    pub(crate) product_id: u16,
+    pub(crate) product: Option<String>,
+    pub(crate) serial: Option<String>,
+}
+
+// the device is shared between threads behind a mutex. the raw pointers it
+// holds are only dereferenced by libusb and the transfer callbacks.
+unsafe impl Send for Device {}
+
 extern "system" fn ctrl_cb(xfer: *mut libusb_transfer) {
     let dev_ptr = unsafe { (*xfer).user_data as *mut Device };
     let dev = unsafe { &mut *dev_ptr };
 
-    let _status = unsafe { (*xfer).status };
+    let status = unsafe { (*xfer).status };
+    dev.ctrl_transfer_status.store(status, Ordering::SeqCst);
 
     *dev.ctrl_transfer_pending.write().unwrap() = false;
 }
@@ -104,36 +277,277 @@ extern "system" fn bulk_in_cb(xfer: *mut libusb_transfer) {
     let dev = unsafe { &mut *dev_ptr };
     let status = unsafe { (*xfer).status };
 
-    if status == LIBUSB_TRANSFER_COMPLETED {
-        let frame_data = unsafe { std::slice::from_raw_parts((*xfer).buffer, BULK_IN_BUF_SIZE) };
-        let f = HostFrame::from_le_bytes(frame_data);
-        dev.can_rx_send.send(f).unwrap();
+    let len = unsafe { (*xfer).actual_length } as usize;
+    // ignore transfers too short to contain a frame header
+    if status == LIBUSB_TRANSFER_COMPLETED && len >= 12 {
+        let frame_data = unsafe { std::slice::from_raw_parts((*xfer).buffer, len) };
+        let hw_timestamps = dev.hw_timestamps.load(Ordering::SeqCst);
+        let frames = HostFrameBatch::parse(frame_data, hw_timestamps);
+        dev.can_rx_send.send(frames).unwrap();
+        dev.rx_activity.fetch_add(1, Ordering::SeqCst);
     }
-    if status != LIBUSB_TRANSFER_CANCELLED {
+    if status == LIBUSB_TRANSFER_NO_DEVICE {
+        dev.set_disconnected();
+    } else if status != LIBUSB_TRANSFER_CANCELLED && !dev.in_stopping.load(Ordering::SeqCst) {
         // resubmit the transfer unless it was cancelled
-        unsafe {
-            libusb_submit_transfer(xfer);
+        match unsafe { libusb_submit_transfer(xfer) } {
+            LIBUSB_SUCCESS => return,
+            LIBUSB_ERROR_NO_DEVICE => dev.set_disconnected(),
+            _ => {}
         }
     }
+    // the transfer is no longer submitted, and may be freed
+    dev.in_pending.fetch_sub(1, Ordering::SeqCst);
 }
 
-impl Device {
-    pub(crate) fn new(ctx: UsbContext) -> Result<Device, Error> {
-        let hnd = unsafe { libusb_open_device_with_vid_pid(ctx.as_ptr(), USB_VID, USB_PID) };
-        if hnd.is_null() {
-            return Err(Error::DeviceNotFound);
+// calls f with each CANtact device and its bus number and address, in the
+// order libusb lists them, until f returns a value
+fn find_device<T>(
+    ctx: &UsbContext,
+    mut f: impl FnMut(*mut libusb_device, u8, u8) -> Option<T>,
+) -> Result<Option<T>, Error> {
+    let mut list = mem::MaybeUninit::<*const *mut libusb_device>::uninit();
+    let count = unsafe { libusb_get_device_list(ctx.as_ptr(), list.as_mut_ptr()) };
+    if count < 0 {
+        return Err(Error::Libusb("libusb_get_device_list", count as i32));
+    }
+    let list = unsafe { list.assume_init() };
+    let devices = unsafe { std::slice::from_raw_parts(list, count as usize) };
+
+    let mut result = None;
+    for dev in devices {
+        let mut desc = mem::MaybeUninit::<libusb_device_descriptor>::uninit();
+        if unsafe { libusb_get_device_descriptor(*dev, desc.as_mut_ptr()) } != LIBUSB_SUCCESS {
+            continue;
+        }
+        let desc = unsafe { desc.assume_init() };
+        if desc.idVendor != USB_VID || desc.idProduct != USB_PID {
+            continue;
         }
 
-        match unsafe { libusb_detach_kernel_driver(hnd, 0) } {
-            LIBUSB_SUCCESS => {}
-            LIBUSB_ERROR_NOT_FOUND => { /* device already disconnected */ }
-            LIBUSB_ERROR_NOT_SUPPORTED => { /* can't detach on this system (not linux) */ }
-            e => return Err(Error::Libusb("libusb_detach_kernel_driver", e)),
+        let bus = unsafe { libusb_get_bus_number(*dev) };
+        let address = unsafe { libusb_get_device_address(*dev) };
+        result = f(*dev, bus, address);
+        if result.is_some() {
+            break;
         }
+    }
+
+    unsafe { libusb_free_device_list(list, 1) };
+    Ok(result)
+}
+
+// open the device matching the selector. devices are numbered in the order
+// libusb lists them.
+fn open_handle(
+    ctx: &UsbContext,
+    selector: &DeviceSelector,
+) -> Result<*mut libusb_device_handle, Error> {
+    let mut index = 0;
+    let found = find_device(ctx, |dev, bus, address| {
+        let selected = match selector {
+            DeviceSelector::First => true,
+            DeviceSelector::Index(n) => *n == index,
+            DeviceSelector::Address {
+                bus: b,
+                address: a,
+            } => *b == bus && *a == address,
+        };
+        index += 1;
+        if !selected {
+            return None;
+        }
+
+        let mut hnd = ptr::null_mut();
+        Some(match unsafe { libusb_open(dev, &mut hnd) } {
+            LIBUSB_SUCCESS => Ok(hnd),
+            LIBUSB_ERROR_ACCESS => Err(Error::PermissionDenied),
+            e => Err(Error::Libusb("libusb_open", e)),
+        })
+    })?;
+    found.unwrap_or(Err(Error::DeviceNotFound))
+}
+
+// list the connected CANtact devices
+pub(crate) fn list_devices(ctx: &UsbContext) -> Result<Vec<DeviceInfo>, Error> {
+    let mut devices = Vec::new();
+    find_device(ctx, |_, bus, address| {
+        devices.push(DeviceInfo { bus, address });
+        None::<()>
+    })?;
+    Ok(devices)
+}
+
+// list the connected CANtact devices with their identities. a device is
+// only opened if its identity is not cached, and it is never claimed, so
+// this does not disturb a process using it.
+pub(crate) fn list_identities(ctx: &UsbContext) -> Result<Vec<(DeviceInfo, UsbIdentity)>, Error> {
+    let mut devices = Vec::new();
+    find_device(ctx, |dev, bus, address| {
+        let info = DeviceInfo { bus, address };
+        let cached = IDENTITIES
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(i, _)| *i == info)
+            .map(|(_, identity)| identity.clone());
+        let identity = match cached {
+            Some(identity) => identity,
+            // opening a device another process is using could disturb it
+            None if claimed_by_process(dev, bus) => read_identity(ptr::null_mut()),
+            None => {
+                let mut hnd = ptr::null_mut();
+                if unsafe { libusb_open(dev, &mut hnd) } == LIBUSB_SUCCESS {
+                    let identity = read_identity(hnd);
+                    unsafe { libusb_close(hnd) };
+                    cache_identity(info, identity.clone());
+                    identity
+                } else {
+                    // without access the strings cannot be read. they are
+                    // tried again next time.
+                    read_identity(ptr::null_mut())
+                }
+            }
+        };
+        devices.push((info, identity));
+        None::<()>
+    })?;
+
+    IDENTITIES
+        .lock()
+        .unwrap()
+        .retain(|(info, _)| devices.iter().any(|(i, _)| i == info));
+    Ok(devices)
+}
+
+// true if an interface of the device is claimed by a process through usbfs,
+// read from sysfs without opening the device. linux binds claimed interfaces
+// to the usbfs driver; elsewhere claims cannot be seen without opening.
+#[cfg(target_os = "linux")]
+fn claimed_by_process(dev: *mut libusb_device, bus: u8) -> bool {
+    let mut ports = [0u8; 7];
+    let n = unsafe { libusb_get_port_numbers(dev, ports.as_mut_ptr(), ports.len() as i32) };
+    if n <= 0 {
+        return false;
+    }
+    sysfs_claimed(std::path::Path::new("/sys/bus/usb/devices"), bus, &ports[..n as usize])
+}
+
+#[cfg(not(target_os = "linux"))]
+fn claimed_by_process(_dev: *mut libusb_device, _bus: u8) -> bool {
+    false
+}
 
-        match unsafe { libusb_claim_interface(hnd, 0) } {
+// interfaces of a device are named <bus>-<port>.<port>...:<config>.<interface>
+#[cfg(target_os = "linux")]
+fn sysfs_claimed(devices: &std::path::Path, bus: u8, ports: &[u8]) -> bool {
+    let ports: Vec<String> = ports.iter().map(|p| p.to_string()).collect();
+    let prefix = format!("{}-{}:", bus, ports.join("."));
+    let entries = match std::fs::read_dir(devices) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+    entries.filter_map(|e| e.ok()).any(|e| {
+        e.file_name().to_string_lossy().starts_with(&prefix)
+            && std::fs::read_link(e.path().join("driver"))
+                .map(|driver| driver.file_name() == Some(std::ffi::OsStr::new("usbfs")))
+                .unwrap_or(false)
+    })
+}
+
+fn cache_identity(info: DeviceInfo, identity: UsbIdentity) {
+    let mut identities = IDENTITIES.lock().unwrap();
+    identities.retain(|(i, _)| *i != info);
+    identities.push((info, identity));
+}
+
+// read the device descriptor of an open handle and its product and serial
+// number strings. strings that cannot be read are left out, as they are
+// without a handle.
+fn read_identity(hnd: *mut libusb_device_handle) -> UsbIdentity {
+    let mut identity = UsbIdentity {
+        vendor_id: USB_VID,
+        product_id: USB_PID,
+        product: None,
+        serial: None,
+    };
+    if hnd.is_null() {
+        return identity;
+    }
+    let dev = unsafe { libusb_get_device(hnd) };
+    let mut desc = mem::MaybeUninit::<libusb_device_descriptor>::uninit();
+    if unsafe { libusb_get_device_descriptor(dev, desc.as_mut_ptr()) } != LIBUSB_SUCCESS {
+        return identity;
+    }
+    let desc = unsafe { desc.assume_init() };
+    identity.vendor_id = desc.idVendor;
+    identity.product_id = desc.idProduct;
+    identity.product = read_string(hnd, desc.iProduct);
+    identity.serial = read_string(hnd, desc.iSerialNumber);
+    identity
+}
+
+// read an ASCII string descriptor. index 0 means there is no string.
+fn read_string(hnd: *mut libusb_device_handle, index: u8) -> Option<String> {
+    if index == 0 {
+        return None;
+    }
+    let mut buf = [0u8; 256];
+    let n = unsafe {
+        libusb_get_string_descriptor_ascii(hnd, index, buf.as_mut_ptr(), buf.len() as i32)
+    };
+    if n < 0 {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&buf[..n as usize]).into_owned())
+}
+
+impl Device {
+    pub(crate) fn new(
+        ctx: UsbContext,
+        selector: &DeviceSelector,
+        detach_kernel_driver: bool,
+    ) -> Result<Device, Error> {
+        let hnd = open_handle(&ctx, selector)?;
+
+        let dev = unsafe { libusb_get_device(hnd) };
+        let usb_info = match read_interfaces(dev) {
+            Ok(interfaces) => select_interface(&interfaces).unwrap_or(DEFAULT_USB_INFO),
+            Err(e) => {
+                unsafe { libusb_close(hnd) };
+                return Err(e);
+            }
+        };
+        let interface = usb_info.interface as i32;
+
+        if detach_kernel_driver {
+            match unsafe { libusb_detach_kernel_driver(hnd, interface) } {
+                LIBUSB_SUCCESS => {}
+                LIBUSB_ERROR_NOT_FOUND => { /* no kernel driver attached */ }
+                LIBUSB_ERROR_NOT_SUPPORTED => { /* can't detach on this system (not linux) */ }
+                LIBUSB_ERROR_ACCESS => {
+                    unsafe { libusb_close(hnd) };
+                    return Err(Error::PermissionDenied);
+                }
+                e => {
+                    unsafe { libusb_close(hnd) };
+                    return Err(Error::Libusb("libusb_detach_kernel_driver", e));
+                }
+            }
+        }
+
+        // claiming is exclusive: this fails if another process, or a kernel
+        // driver that was not detached, holds the interface
+        match unsafe { libusb_claim_interface(hnd, interface) } {
             LIBUSB_SUCCESS => {}
-            e => return Err(Error::Libusb("libusb_claim_interface", e)),
+            LIBUSB_ERROR_BUSY => {
+                unsafe { libusb_close(hnd) };
+                return Err(Error::DeviceBusy);
+            }
+            e => {
+                unsafe { libusb_close(hnd) };
+                return Err(Error::Libusb("libusb_claim_interface", e));
+            }
         }
 
         let ctrl_transfer = unsafe { libusb_alloc_transfer(0) };
@@ -150,10 +564,13 @@ impl Device {
             ctx: Arc::new(ctx),
             hnd: unsafe { ptr::NonNull::new_unchecked(hnd) },
             running: Arc::new(AtomicBool::new(true)),
+            hw_timestamps: AtomicBool::new(false),
 
             ctrl_transfer: unsafe { ptr::NonNull::new_unchecked(ctrl_transfer) },
             ctrl_buf: [0u8; CTRL_BUF_SIZE],
             ctrl_transfer_pending: RwLock::from(false),
+            ctrl_transfer_status: AtomicI32::new(LIBUSB_TRANSFER_COMPLETED),
+            retry_policy: RetryPolicy::default(),
 
             out_transfer: unsafe { ptr::NonNull::new_unchecked(ctrl_transfer) },
             out_buf: vec![],
@@ -161,6 +578,11 @@ impl Device {
 
             in_transfers: [ptr::null_mut(); BULK_IN_TRANSFER_COUNT],
             in_bufs,
+            in_pending: AtomicUsize::new(0),
+            in_stopping: AtomicBool::new(false),
+            usb_info,
+            rx_activity: Arc::new(AtomicU64::new(0)),
+            disconnected: Arc::new(AtomicBool::new(false)),
 
             can_rx_send: send,
             can_rx_recv: recv,
@@ -180,7 +602,28 @@ impl Device {
         Ok(d)
     }
 
+    pub(crate) fn usb_info(&self) -> UsbInfo {
+        self.usb_info
+    }
+
+    pub(crate) fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+
+    pub(crate) fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    pub(crate) fn set_hw_timestamps(&self, enabled: bool) {
+        self.hw_timestamps.store(enabled, Ordering::SeqCst);
+    }
+
     pub(crate) fn start_transfers(&mut self) -> Result<(), Error> {
+        // transfers of a previous session are freed before their buffers
+        // are used again
+        self.stop_transfers()?;
+        self.in_stopping.store(false, Ordering::SeqCst);
+
         // create the in transfers, fill the transfers, and submit them
         for i in 0..BULK_IN_TRANSFER_COUNT {
             let xfer = unsafe { libusb_alloc_transfer(0) };
@@ -190,30 +633,80 @@ impl Device {
             self.in_transfers[i] = xfer;
             self.fill_bulk_in_transfer(i);
 
+            // counted before submitting, as the callback may run at once
+            self.in_pending.fetch_add(1, Ordering::SeqCst);
             match unsafe { libusb_submit_transfer(self.in_transfers[i]) } {
                 LIBUSB_SUCCESS => {}
-                e => return Err(Error::Libusb("start_transfers: libusb_submit_transfer", e)),
+                e => {
+                    self.in_pending.fetch_sub(1, Ordering::SeqCst);
+                    return Err(Error::Libusb("start_transfers: libusb_submit_transfer", e));
+                }
             };
         }
         Ok(())
     }
 
-    pub(crate) fn stop_transfers(&self) -> Result<(), Error> {
-        // cancel all bulk in transfers
-        for xfer in self.in_transfers.iter() {
-            if xfer.is_null() {
-                // ignore null transfers
-                continue;
+    // cancel all bulk in transfers, wait for their callbacks on the event
+    // thread, and free them. transfers that do not complete in time are not
+    // freed, since libusb may still write to their buffers.
+    pub(crate) fn stop_transfers(&mut self) -> Result<(), Error> {
+        self.in_stopping.store(true, Ordering::SeqCst);
+        let deadline = time::Instant::now() + CANCEL_TIMEOUT;
+        while self.in_pending.load(Ordering::SeqCst) > 0 {
+            if time::Instant::now() >= deadline {
+                let e = LIBUSB_ERROR_TIMEOUT;
+                return Err(Error::Libusb("libusb_cancel_transfer", e));
             }
-            match unsafe { libusb_cancel_transfer(*xfer) } {
-                LIBUSB_SUCCESS => {}
-                LIBUSB_ERROR_NOT_FOUND => { /* already destroyed */ }
-                e => return Err(Error::Libusb("libusb_cancel_transfer", e)),
+            // cancelled again each time, in case a callback resubmitted its
+            // transfer before seeing in_stopping
+            for xfer in self.in_transfers.iter().filter(|x| !x.is_null()) {
+                match unsafe { libusb_cancel_transfer(*xfer) } {
+                    LIBUSB_SUCCESS => {}
+                    LIBUSB_ERROR_NOT_FOUND => { /* not submitted */ }
+                    e => return Err(Error::Libusb("libusb_cancel_transfer", e)),
+                }
             }
+            thread::sleep(time::Duration::from_millis(1));
+        }
+
+        for xfer in self.in_transfers.iter_mut().filter(|x| !x.is_null()) {
+            unsafe { libusb_free_transfer(*xfer) };
+            *xfer = ptr::null_mut();
         }
         Ok(())
     }
 
+    // read the device descriptor and its product and serial number strings,
+    // and cache them so listing devices does not open this one again
+    pub(crate) fn usb_identity(&self) -> UsbIdentity {
+        let identity = read_identity(self.hnd.as_ptr());
+        let dev = unsafe { libusb_get_device(self.hnd.as_ptr()) };
+        let info = DeviceInfo {
+            bus: unsafe { libusb_get_bus_number(dev) },
+            address: unsafe { libusb_get_device_address(dev) },
+        };
+        cache_identity(info, identity.clone());
+        identity
+    }
+
+    // cancel the bulk in transfers and submit new ones
+    pub(crate) fn restart_transfers(&mut self) -> Result<(), Error> {
+        self.stop_transfers()?;
+        self.start_transfers()
+    }
+
+    // issue a USB port reset. if the device re-enumerates, the handle is no
+    // longer valid and Error::DeviceNotFound is returned; the caller must reopen.
+    pub(crate) fn reset(&mut self) -> Result<(), Error> {
+        self.stop_transfers()?;
+
+        match unsafe { libusb_reset_device(self.hnd.as_ptr()) } {
+            LIBUSB_SUCCESS => Ok(()),
+            LIBUSB_ERROR_NOT_FOUND => Err(Error::DeviceNotFound),
+            e => Err(Error::Libusb("libusb_reset_device", e)),
+        }
+    }
+
     fn fill_control_transfer(
         &mut self,
         request_type: u8,
@@ -254,7 +747,7 @@ impl Device {
         let buf = &mut self.out_buf;
 
         transfer.dev_handle = self.hnd.as_ptr();
-        transfer.endpoint = 0x02; // bulk out ep
+        transfer.endpoint = self.usb_info.bulk_out;
         transfer.transfer_type = LIBUSB_TRANSFER_TYPE_BULK;
         transfer.timeout = 1000;
         transfer.buffer = buf.as_mut_ptr();
@@ -268,7 +761,7 @@ impl Device {
         let buf = &mut self.in_bufs[idx];
 
         transfer.dev_handle = self.hnd.as_ptr();
-        transfer.endpoint = 0x81; // bulk in ep
+        transfer.endpoint = self.usb_info.bulk_in;
         transfer.transfer_type = LIBUSB_TRANSFER_TYPE_BULK;
         transfer.timeout = BULK_IN_TIMEOUT_MS;
         transfer.buffer = buf.as_mut_ptr();
@@ -278,33 +771,57 @@ impl Device {
     }
 
     fn control_out(&mut self, req: UsbBreq, channel: u16, data: &[u8]) -> Result<(), Error> {
+        let policy = self.retry_policy;
+        policy.run(|| self.control_out_once(req, channel, data))
+    }
+
+    fn control_out_once(&mut self, req: UsbBreq, channel: u16, data: &[u8]) -> Result<(), Error> {
         // bmRequestType: direction = out, type = vendor, recipient = interface
         let rt = 0b0100_0001;
-        self.fill_control_transfer(rt, req as u8, channel, 0, data);
+        let interface = self.usb_info.interface as u16;
+        self.fill_control_transfer(rt, req as u8, channel, interface, data);
         *self.ctrl_transfer_pending.write().unwrap() = true;
         match unsafe { libusb_submit_transfer(self.ctrl_transfer.as_ptr()) } {
             LIBUSB_SUCCESS => {}
-            e => return Err(Error::Libusb("control_out: libusb_submit_transfer", e)),
+            e => {
+                *self.ctrl_transfer_pending.write().unwrap() = false;
+                return Err(Error::Libusb("control_out: libusb_submit_transfer", e));
+            }
         }
 
         // wait for transfer to complete
         while *self.ctrl_transfer_pending.read().unwrap() {}
-
-        Ok(())
+        match self.ctrl_transfer_status.load(Ordering::SeqCst) {
+            LIBUSB_TRANSFER_COMPLETED => Ok(()),
+            s => Err(Error::Transfer("control_out", s)),
+        }
     }
 
     fn control_in(&mut self, req: UsbBreq, channel: u16, len: usize) -> Result<Vec<u8>, Error> {
+        let policy = self.retry_policy;
+        policy.run(|| self.control_in_once(req, channel, len))
+    }
+
+    fn control_in_once(&mut self, req: UsbBreq, channel: u16, len: usize) -> Result<Vec<u8>, Error> {
         // bmRequestType: direction = in, type = vendor, recipient = interface
         let rt = 0b1100_0001;
-        self.fill_control_transfer(rt, req as u8, channel, 0, vec![0u8; len].as_slice());
+        let interface = self.usb_info.interface as u16;
+        self.fill_control_transfer(rt, req as u8, channel, interface, vec![0u8; len].as_slice());
         *self.ctrl_transfer_pending.write().unwrap() = true;
         match unsafe { libusb_submit_transfer(self.ctrl_transfer.as_ptr()) } {
             LIBUSB_SUCCESS => {}
-            e => return Err(Error::Libusb("control_in: libusb_submit_transfer", e)),
+            e => {
+                *self.ctrl_transfer_pending.write().unwrap() = false;
+                return Err(Error::Libusb("control_in: libusb_submit_transfer", e));
+            }
         }
 
         // wait for transfer to complete
         while *self.ctrl_transfer_pending.read().unwrap() {}
+        match self.ctrl_transfer_status.load(Ordering::SeqCst) {
+            LIBUSB_TRANSFER_COMPLETED => {}
+            s => return Err(Error::Transfer("control_in", s)),
+        }
         let xfer_len = unsafe { (*self.ctrl_transfer.as_ptr()).actual_length } as usize;
         if xfer_len < len {
             // we didn't get the full struct we asked for
@@ -314,6 +831,37 @@ impl Device {
         Ok(self.ctrl_buf[8..8 + xfer_len].to_vec())
     }
 
+    // perform a caller defined control transfer synchronously. the direction
+    // comes from bit 7 of request_type; data is written for out transfers
+    // and filled for in transfers, and must fit in a u16 length. returns the
+    // number of bytes transferred.
+    pub(crate) fn raw_control_transfer(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &mut [u8],
+        timeout: time::Duration,
+    ) -> Result<usize, Error> {
+        let n = unsafe {
+            libusb_control_transfer(
+                self.hnd.as_ptr(),
+                request_type,
+                request,
+                value,
+                index,
+                data.as_mut_ptr(),
+                data.len() as u16,
+                timeout.as_millis().min(u32::MAX as u128) as u32,
+            )
+        };
+        if n < 0 {
+            return Err(Error::Libusb("libusb_control_transfer", n));
+        }
+        Ok(n as usize)
+    }
+
     pub(crate) fn set_host_format(&mut self, val: u32) -> Result<(), Error> {
         let channel = 0;
         self.control_out(UsbBreq::HostFormat, channel, &val.to_le_bytes())
@@ -361,6 +909,17 @@ impl Device {
         )?;
         Ok(BitTimingConsts::from_le_bytes(&data))
     }
+    // data phase limits, on devices with GS_CAN_FEATURE_BT_CONST_EXT
+    pub(crate) fn get_data_bit_timing_consts(&mut self) -> Result<BitTimingConsts, Error> {
+        let channel = 0;
+        let data = self.control_in(UsbBreq::BitTimingConstsExt, channel, 72)?;
+        Ok(BitTimingConsts::data_from_ext_le_bytes(&data))
+    }
+
+    pub(crate) fn get_state(&mut self, channel: u16) -> Result<DeviceState, Error> {
+        let data = self.control_in(UsbBreq::GetState, channel, size_of::<DeviceState>())?;
+        Ok(DeviceState::from_le_bytes(&data))
+    }
 
     pub(crate) fn get_timestamp(&mut self) -> Result<u32, Error> {
         let channel = 0;
@@ -387,14 +946,33 @@ impl Device {
         Ok(())
     }
 
-    pub(crate) fn try_recv(&self) -> Option<HostFrame> {
+    // flag the device as gone, waking the receiver with an empty batch so it
+    // notices without waiting for a frame that will never come
+    fn set_disconnected(&self) {
+        if !self.disconnected.swap(true, Ordering::SeqCst) {
+            let _ = self.can_rx_send.send(HostFrameBatch::default());
+        }
+    }
+
+    // wake the receiver with an empty batch
+    pub(crate) fn wake_receiver(&self) {
+        let _ = self.can_rx_send.send(HostFrameBatch::default());
+    }
+
+    // queue a frame as if it was received from the device
+    #[cfg(feature = "testing")]
+    pub(crate) fn inject(&self, frame: HostFrame) {
+        let _ = self.can_rx_send.send(HostFrameBatch::from(frame));
+    }
+
+    pub(crate) fn try_recv(&self) -> Option<HostFrameBatch> {
         match self.can_rx_recv.try_recv() {
             Ok(f) => Some(f),
             Err(TryRecvError::Empty) => None,
             Err(TryRecvError::Disconnected) => None,
         }
     }
-    pub(crate) fn recv(&self) -> HostFrame {
+    pub(crate) fn recv(&self) -> HostFrameBatch {
         match self.can_rx_recv.recv() {
             Ok(f) => f,
             Err(e) => panic!("{}", e),
@@ -404,13 +982,114 @@ impl Device {
 
 impl Drop for Device {
     fn drop(&mut self) {
-        // stop the threau
+        // the event thread completes the cancelled transfers, so it is
+        // stopped afterwards
+        let _ = self.stop_transfers();
         self.running.store(false, Ordering::SeqCst);
 
-        self.stop_transfers().unwrap();
         unsafe {
-            libusb_release_interface(self.hnd.as_ptr(), 0);
+            libusb_release_interface(self.hnd.as_ptr(), self.usb_info.interface as i32);
             libusb_close(self.hnd.as_ptr());
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_interface() {
+        let bulk = LIBUSB_TRANSFER_TYPE_BULK;
+        let interrupt = 3;
+        let dfu = InterfaceDesc {
+            number: 0,
+            class: 0xFE,
+            endpoints: vec![],
+        };
+        let cdc = InterfaceDesc {
+            number: 1,
+            class: 0x0A,
+            endpoints: vec![(0x81, bulk), (0x01, bulk)],
+        };
+        let gs_usb = InterfaceDesc {
+            number: 2,
+            class: LIBUSB_CLASS_VENDOR_SPEC,
+            endpoints: vec![(0x83, interrupt), (0x82, bulk), (0x03, bulk)],
+        };
+        assert_eq!(
+            select_interface(&[dfu, cdc, gs_usb]),
+            Some(UsbInfo {
+                interface: 2,
+                bulk_in: 0x82,
+                bulk_out: 0x03,
+            })
+        );
+
+        // a vendor interface without a bulk out endpoint is skipped
+        let incomplete = InterfaceDesc {
+            number: 0,
+            class: LIBUSB_CLASS_VENDOR_SPEC,
+            endpoints: vec![(0x81, bulk)],
+        };
+        assert_eq!(select_interface(&[incomplete]), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sysfs_claimed() {
+        use std::os::unix::fs::symlink;
+        let dir = std::env::temp_dir().join(format!("cantact-sysfs-{}", std::process::id()));
+        let interface = |name: &str, driver: &str| {
+            let path = dir.join(name);
+            std::fs::create_dir_all(&path).unwrap();
+            symlink(dir.join("drivers").join(driver), path.join("driver")).unwrap();
+        };
+        interface("1-2:1.0", "gs_usb");
+        interface("1-3.1:1.0", "usbfs");
+
+        // a kernel driver does not count as a claim by a process
+        assert!(!sysfs_claimed(&dir, 1, &[2]));
+        assert!(sysfs_claimed(&dir, 1, &[3, 1]));
+        assert!(!sysfs_claimed(&dir, 1, &[3]));
+        assert!(!sysfs_claimed(&dir, 2, &[3, 1]));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RetryPolicy {
+            max_retries: 2,
+            backoff: time::Duration::from_millis(1),
+        };
+
+        // transient errors are retried until the limit is reached
+        let mut attempts = 0;
+        let r: Result<(), Error> = policy.run(|| {
+            attempts += 1;
+            Err(Error::Libusb("test", LIBUSB_ERROR_PIPE))
+        });
+        assert!(r.is_err());
+        assert_eq!(attempts, 3);
+
+        attempts = 0;
+        let r = policy.run(|| {
+            attempts += 1;
+            match attempts {
+                1 => Err(Error::Transfer("test", LIBUSB_TRANSFER_TIMED_OUT)),
+                _ => Ok(attempts),
+            }
+        });
+        assert_eq!(r.unwrap(), 2);
+
+        // a missing device is not retried
+        attempts = 0;
+        let r: Result<(), Error> = policy.run(|| {
+            attempts += 1;
+            Err(Error::Libusb("test", LIBUSB_ERROR_NO_DEVICE))
+        });
+        assert!(r.is_err());
+        assert_eq!(attempts, 1);
+    }
+}
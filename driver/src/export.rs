@@ -0,0 +1,308 @@
+//! CSV and newline delimited JSON export of frames.
+//!
+//! Both formats have one frame per line with the same fields, so captures
+//! can be loaded into spreadsheets or processed by scripts in any language.
+//! Timestamps are in seconds, IDs and data are hexadecimal in CSV, and data
+//! is limited to the length given by the DLC. CSV files can be read back
+//! with `read_csv`.
+//!
+//! Lines in the candump log and Vector ASC formats can also be written, for
+//! use with existing CAN analysis tools.
+
+use std::io;
+use std::io::{BufRead, Write};
+use std::time;
+
+use crate::frame::CAN_ERR_FLAG;
+use crate::{Direction, Frame};
+
+/// Header written at the start of ASC files, before lines from
+/// `format_asc`.
+pub const ASC_HEADER: &str = "base hex  timestamps absolute";
+
+/// Header line written by `write_csv`.
+pub const CSV_HEADER: &str = "timestamp,channel,id,ext,rtr,fd,brs,dlc,data";
+
+/// Errors generated when reading exported frames
+#[derive(Debug)]
+pub enum ExportError {
+    /// Error from the underlying reader.
+    Io(io::Error),
+    /// A line could not be decoded as a frame. Contains the line number,
+    /// counting from 1.
+    InvalidLine(usize),
+}
+impl From<io::Error> for ExportError {
+    fn from(e: io::Error) -> ExportError {
+        ExportError::Io(e)
+    }
+}
+
+fn flag(b: bool) -> u8 {
+    b as u8
+}
+
+// data bytes covered by the DLC, as hex
+fn data_hex(f: &Frame) -> String {
+    f.payload().iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// Format a frame as a CSV line, without the line terminator. A frame
+/// without a timestamp has an empty timestamp column.
+pub fn format_csv(f: &Frame) -> String {
+    let ts = match f.timestamp {
+        Some(t) => format!("{:.6}", t.as_secs_f64()),
+        None => String::new(),
+    };
+    format!(
+        "{},{},{:X},{},{},{},{},{},{}",
+        ts,
+        f.channel,
+        f.can_id,
+        flag(f.ext),
+        flag(f.rtr),
+        flag(f.fd),
+        flag(f.brs),
+        f.can_dlc,
+        data_hex(f),
+    )
+}
+
+/// Parse a CSV line written by `format_csv`.
+pub fn parse_csv(line: &str) -> Option<Frame> {
+    let cols: Vec<&str> = line.trim_end().split(',').collect();
+    if cols.len() != 9 {
+        return None;
+    }
+    let parse_flag = |s: &str| match s {
+        "0" => Some(false),
+        "1" => Some(true),
+        _ => None,
+    };
+
+    let timestamp = match cols[0] {
+        "" => None,
+        s => Some(time::Duration::try_from_secs_f64(s.parse::<f64>().ok()?).ok()?),
+    };
+    let mut f = Frame {
+        timestamp,
+        channel: cols[1].parse().ok()?,
+        can_id: u32::from_str_radix(cols[2], 16).ok()?,
+        ext: parse_flag(cols[3])?,
+        rtr: parse_flag(cols[4])?,
+        fd: parse_flag(cols[5])?,
+        brs: parse_flag(cols[6])?,
+        can_dlc: cols[7].parse().ok()?,
+        ..Default::default()
+    };
+
+    let hex = cols[8];
+    if !hex.len().is_multiple_of(2) || hex.len() / 2 > f.data_len() {
+        return None;
+    }
+    f.data = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Some(f)
+}
+
+/// Write frames as CSV, starting with `CSV_HEADER`.
+///
+/// Any iterator of frames can be written, including a live stream such as
+/// the receiver from `Interface::channel_receiver`.
+pub fn write_csv<'a, W: Write>(
+    w: &mut W,
+    frames: impl IntoIterator<Item = &'a Frame>,
+) -> io::Result<()> {
+    writeln!(w, "{}", CSV_HEADER)?;
+    for f in frames {
+        writeln!(w, "{}", format_csv(f))?;
+    }
+    Ok(())
+}
+
+/// Read frames from CSV written by `write_csv`. The header line and empty
+/// lines are skipped.
+pub fn read_csv<R: BufRead>(r: R) -> Result<Vec<Frame>, ExportError> {
+    let mut frames = vec![];
+    for (n, line) in r.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.trim_end() == CSV_HEADER {
+            continue;
+        }
+        frames.push(parse_csv(&line).ok_or(ExportError::InvalidLine(n + 1))?);
+    }
+    Ok(frames)
+}
+
+/// Format a frame as a JSON object on one line, with the same fields as
+/// CSV. The ID is a number, the data a hex string, and a missing timestamp
+/// is `null`.
+pub fn format_json(f: &Frame) -> String {
+    let ts = match f.timestamp {
+        Some(t) => format!("{:.6}", t.as_secs_f64()),
+        None => String::from("null"),
+    };
+    format!(
+        "{{\"timestamp\":{},\"channel\":{},\"id\":{},\"ext\":{},\"rtr\":{},\"fd\":{},\"brs\":{},\"dlc\":{},\"data\":\"{}\"}}",
+        ts,
+        f.channel,
+        f.can_id,
+        f.ext,
+        f.rtr,
+        f.fd,
+        f.brs,
+        f.can_dlc,
+        data_hex(f),
+    )
+}
+
+// frame timestamp in seconds, or zero if it has none
+fn seconds(f: &Frame) -> f64 {
+    f.timestamp.map(|t| t.as_secs_f64()).unwrap_or(0.0)
+}
+
+/// Format a frame as a candump log line, as written by `candump -L`, without
+/// the line terminator. Channel n is named `canN`.
+///
+/// For example `(1.500250) can0 18DAF110#0210AB`. CAN-FD frames use `##`
+/// followed by the flags digit, and remote frames `#R`.
+pub fn format_candump(f: &Frame) -> String {
+    let id = if f.err {
+        format!("{:08X}", f.can_id | CAN_ERR_FLAG)
+    } else if f.ext {
+        format!("{:08X}", f.can_id)
+    } else {
+        format!("{:03X}", f.can_id)
+    };
+    let body = if f.fd {
+        let flags = f.brs as u8 | (f.esi as u8) << 1;
+        format!("#{:X}{}", flags, data_hex(f))
+    } else if f.rtr {
+        String::from("R")
+    } else {
+        data_hex(f)
+    };
+    format!("({:.6}) can{} {}#{}", seconds(f), f.channel, id, body)
+}
+
+/// Format a frame as a Vector ASC line, without the line terminator.
+/// Channels are numbered from 1, as in ASC files, and extended IDs end in
+/// `x`.
+///
+/// For example `1.500250 2 18DAF110x Rx d 3 02 10 AB`. Files should start
+/// with `ASC_HEADER`.
+pub fn format_asc(f: &Frame) -> String {
+    let ts = seconds(f);
+    let ch = f.channel as u32 + 1;
+    if f.err {
+        return format!("{:.6} {} ErrorFrame", ts, ch);
+    }
+    let id = format!("{:X}{}", f.can_id, if f.ext { "x" } else { "" });
+    let dir = match f.direction() {
+        Direction::Rx => "Rx",
+        Direction::Tx => "Tx",
+    };
+    let data: Vec<String> = f.payload().iter().map(|b| format!("{:02X}", b)).collect();
+    if f.fd {
+        format!(
+            "{:.6} CANFD {} {} {} {} {} {:x} {} {}",
+            ts,
+            ch,
+            dir,
+            id,
+            f.brs as u8,
+            f.esi as u8,
+            f.can_dlc,
+            data.len(),
+            data.join(" ")
+        )
+    } else if f.rtr {
+        format!("{:.6} {} {} {} r {}", ts, ch, id, dir, f.can_dlc)
+    } else {
+        format!("{:.6} {} {} {} d {} {}", ts, ch, id, dir, f.can_dlc, data.join(" "))
+    }
+}
+
+/// Write frames as newline delimited JSON, one object per line.
+pub fn write_ndjson<'a, W: Write>(
+    w: &mut W,
+    frames: impl IntoIterator<Item = &'a Frame>,
+) -> io::Result<()> {
+    for f in frames {
+        writeln!(w, "{}", format_json(f))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv() {
+        let mut f = Frame::new_extended(0x18DAF110, &[0x02, 0x10, 0xAB]).unwrap();
+        f.channel = 1;
+        f.timestamp = Some(time::Duration::from_micros(1_500_250));
+        let mut fd = Frame::new_fd(0x123, &[0xCC; 12], true).unwrap();
+        fd.timestamp = None;
+        let frames = vec![f, fd];
+
+        let mut out = vec![];
+        write_csv(&mut out, &frames).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(
+            text.lines().nth(1).unwrap(),
+            "1.500250,1,18DAF110,1,0,0,0,3,0210AB"
+        );
+
+        let read = read_csv(text.as_bytes()).unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[0].can_id, 0x18DAF110);
+        assert_eq!(read[0].timestamp, frames[0].timestamp);
+        assert_eq!(read[0].data, vec![0x02, 0x10, 0xAB]);
+        assert!(read[1].fd && read[1].brs && !read[1].ext);
+        assert_eq!(read[1].data, vec![0xCC; 12]);
+
+        assert!(matches!(
+            read_csv("1.0,0,123,0,0,0,0,1,0102\n".as_bytes()),
+            Err(ExportError::InvalidLine(1))
+        ));
+        // timestamps that are not a valid duration are rejected, not a panic
+        for ts in &["-1.0", "NaN", "inf"] {
+            let line = format!("{},0,123,0,0,0,0,2,0102\n", ts);
+            assert!(matches!(
+                read_csv(line.as_bytes()),
+                Err(ExportError::InvalidLine(1))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_candump_asc() {
+        let mut f = Frame::new_extended(0x18DAF110, &[0x02, 0x10, 0xAB]).unwrap();
+        f.channel = 1;
+        f.timestamp = Some(time::Duration::from_micros(1_500_250));
+        assert_eq!(format_candump(&f), "(1.500250) can1 18DAF110#0210AB");
+        assert_eq!(format_asc(&f), "1.500250 2 18DAF110x Rx d 3 02 10 AB");
+
+        let fd = Frame::new_fd(0x123, &[0xCC; 2], true).unwrap();
+        assert_eq!(format_candump(&fd), "(0.000000) can0 123##1CCCC");
+        assert_eq!(format_asc(&fd), "0.000000 CANFD 1 Rx 123 1 0 2 2 CC CC");
+
+        let mut rtr = Frame::new_standard(0x7DF, &[]).unwrap();
+        rtr.rtr = true;
+        assert_eq!(format_candump(&rtr), "(0.000000) can0 7DF#R");
+        assert_eq!(format_asc(&rtr), "0.000000 1 7DF Rx r 0");
+    }
+
+    #[test]
+    fn test_json() {
+        let f = Frame::new_standard(0x7DF, &[0x02, 0x01, 0x0C]).unwrap();
+        assert_eq!(
+            format_json(&f),
+            r#"{"timestamp":null,"channel":0,"id":2015,"ext":false,"rtr":false,"fd":false,"brs":false,"dlc":3,"data":"02010C"}"#
+        );
+    }
+}
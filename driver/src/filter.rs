@@ -0,0 +1,114 @@
+//! Suppression of repeated frames.
+
+use std::collections::HashMap;
+use std::time;
+
+use crate::Frame;
+
+/// Forwards only frames whose data differs from the last frame with the same
+/// ID, channel and format.
+///
+/// Buses carry many periodic frames whose content rarely changes. Running
+/// received frames through a `ChangeFilter` leaves only the changes, which is
+/// useful for displays of the last value per ID.
+///
+/// ```
+/// use cantact::{ChangeFilter, Frame};
+///
+/// let mut filter = ChangeFilter::new();
+/// let f = Frame::new_standard(0x123, &[1, 2]).unwrap();
+/// assert!(filter.check(&f));
+/// assert!(!filter.check(&f));
+/// ```
+#[derive(Debug, Default)]
+pub struct ChangeFilter {
+    // key is (channel, extended, can_id)
+    last: HashMap<(u8, bool, u32), Entry>,
+    refresh: Option<time::Duration>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    data: Vec<u8>,
+    // time the frame was last forwarded
+    forwarded: time::Instant,
+}
+
+impl ChangeFilter {
+    /// Create a filter that forwards only changed frames.
+    pub fn new() -> ChangeFilter {
+        ChangeFilter::default()
+    }
+
+    /// Create a filter that also forwards an unchanged frame once `interval`
+    /// has passed since a frame with that ID was last forwarded, so IDs with
+    /// constant data are still refreshed periodically.
+    pub fn with_refresh(interval: time::Duration) -> ChangeFilter {
+        ChangeFilter {
+            last: HashMap::new(),
+            refresh: Some(interval),
+        }
+    }
+
+    /// Returns true if the frame should be forwarded, and records it as the
+    /// last frame for its ID.
+    pub fn check(&mut self, f: &Frame) -> bool {
+        self.check_at(f, time::Instant::now())
+    }
+
+    /// Forget all recorded frames, so the next frame for each ID is forwarded.
+    pub fn clear(&mut self) {
+        self.last.clear();
+    }
+
+    fn check_at(&mut self, f: &Frame, now: time::Instant) -> bool {
+        let data = f.payload();
+
+        let key = (f.channel, f.ext, f.can_id);
+        let forward = match self.last.get(&key) {
+            None => true,
+            Some(e) if e.data != data => true,
+            Some(e) => match self.refresh {
+                Some(interval) => now.duration_since(e.forwarded) >= interval,
+                None => false,
+            },
+        };
+        if forward {
+            self.last.insert(
+                key,
+                Entry {
+                    data: data.to_vec(),
+                    forwarded: now,
+                },
+            );
+        }
+        forward
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_change_filter() {
+        let now = time::Instant::now();
+        let mut filter = ChangeFilter::with_refresh(time::Duration::from_secs(1));
+        let a = Frame::new_standard(0x100, &[1, 2]).unwrap();
+        let b = Frame::new_standard(0x100, &[1, 3]).unwrap();
+        let c = Frame::new_extended(0x100, &[1, 2]).unwrap();
+
+        assert!(filter.check_at(&a, now));
+        assert!(!filter.check_at(&a, now));
+        assert!(filter.check_at(&b, now));
+        // same ID but a different format
+        assert!(filter.check_at(&c, now));
+
+        // unchanged frames are forwarded once the refresh interval passes
+        let later = now + time::Duration::from_millis(500);
+        assert!(!filter.check_at(&b, later));
+        let later = now + time::Duration::from_secs(1);
+        assert!(filter.check_at(&b, later));
+        assert!(!filter.check_at(&b, later));
+    }
+}
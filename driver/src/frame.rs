@@ -0,0 +1,770 @@
+//! CAN frame representation and its encoding to and from gs_usb host frames.
+//!
+//! This module only depends on the gs_usb protocol definitions and does not
+//! use the device or any threads, so the same encoding can be shared by code
+//! that does not talk to a USB device.
+
+use std::time;
+
+use crate::gsusb::*;
+use crate::Error;
+
+// largest standard and extended identifiers
+pub(crate) const MAX_STANDARD_ID: u16 = 0x7FF;
+pub(crate) const MAX_EXTENDED_ID: u32 = 0x1FFF_FFFF;
+
+/// SocketCAN extended frame format flag.
+pub const CAN_EFF_FLAG: u32 = 0x8000_0000;
+/// SocketCAN remote transmission request flag.
+pub const CAN_RTR_FLAG: u32 = 0x4000_0000;
+/// SocketCAN error frame flag.
+pub const CAN_ERR_FLAG: u32 = 0x2000_0000;
+
+/// Where a received frame came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameOrigin {
+    /// Frame was received from another node on the bus.
+    Received,
+    /// Frame is the device's confirmation that a frame sent by this
+    /// interface was transmitted on the bus.
+    TxEcho,
+    /// Frame was received on a channel in hardware loopback mode, where the
+    /// controller loops transmitted frames back internally.
+    Loopback,
+}
+
+/// Direction of a frame relative to this interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Frame was received by this interface.
+    Rx,
+    /// Frame was transmitted by this interface.
+    Tx,
+}
+
+/// Controller Area Network Frame
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// CAN frame arbitration ID.
+    pub can_id: u32,
+
+    /// CAN frame Data Length Code (DLC).
+    ///
+    /// The DLC determines how many bytes of `data` are transmitted. If `data`
+    /// is longer than the length given by the DLC, the extra bytes are not sent.
+    /// If it is shorter, the remaining bytes are sent as zero.
+    pub can_dlc: u8,
+
+    /// Device channel used to send or receive the frame.
+    pub channel: u8,
+
+    /// Frame data contents.
+    pub data: Vec<u8>,
+
+    /// Extended (29 bit) arbitration identifier if true,
+    /// standard (11 bit) arbitration identifer if false.
+    pub ext: bool,
+
+    /// CAN Flexible Data (CAN-FD) frame flag.
+    pub fd: bool,
+
+    /// CAN-FD Bit Rate Switch (BRS) flag.
+    pub brs: bool,
+
+    /// CAN-FD Error State Indicator (ESI) flag.
+    pub esi: bool,
+
+    /// Loopback flag. When true, frame was sent by this device/channel.
+    /// False for received frames.
+    ///
+    /// Received frames have this set exactly when `origin` is not
+    /// `FrameOrigin::Received`; `origin` tells the two kinds apart.
+    pub loopback: bool,
+
+    /// Origin of a received frame. Distinguishes transmit confirmations from
+    /// frames looped back by a channel in hardware loopback mode.
+    pub origin: FrameOrigin,
+
+    /// Error frame flag.
+    pub err: bool,
+
+    /// Remote Transmission Request (RTR) flag.
+    pub rtr: bool,
+
+    /// Timestamp when frame was received
+    ///
+    /// Received timestamps have a resolution of one microsecond, whether
+    /// they come from the device or the host clock; host timestamps are
+    /// truncated to whole microseconds. See `Frame.timestamp_micros`.
+    ///
+    /// Hardware timestamps come from a 32 bit device counter, which wraps
+    /// about every 71 minutes at 1 MHz. Wraps are detected and accumulated,
+    /// so timestamps keep increasing during long captures as long as at
+    /// least one frame is received per wrap period.
+    pub timestamp: Option<time::Duration>,
+
+    /// CAN ID exactly as delivered by the device, including the extended,
+    /// RTR and error flag bits. Only set on received frames when enabled with
+    /// `Interface.set_raw_can_ids`.
+    ///
+    /// This is informational: `Frame::to_host_frame` always builds the ID
+    /// from `can_id`, `ext`, `rtr` and `err`, so a received frame that is
+    /// edited and sent again goes out with the edited ID. An unedited frame
+    /// is sent with exactly this value, since those fields hold all its bits.
+    pub raw_can_id: Option<u32>,
+}
+
+impl Default for Frame {
+    /// Returns a default CAN frame with all values set to zero/false.
+    fn default() -> Frame {
+        Frame {
+            can_id: 0,
+            can_dlc: 0,
+            data: vec![0; 64],
+            channel: 0,
+            ext: false,
+            fd: false,
+            loopback: false,
+            origin: FrameOrigin::Received,
+            rtr: false,
+            brs: false,
+            esi: false,
+            err: false,
+            timestamp: None,
+            raw_can_id: None,
+        }
+    }
+}
+
+impl Frame {
+    /// Create a classic CAN frame with a standard (11 bit) identifier.
+    ///
+    /// Returns `Error::InvalidArgument` if the ID does not fit in 11 bits or
+    /// more than 8 data bytes are given.
+    pub fn new_standard(id: u16, data: &[u8]) -> Result<Frame, Error> {
+        if id > MAX_STANDARD_ID {
+            return Err(Error::InvalidArgument("standard ID must be at most 0x7FF"));
+        }
+        Frame::new_classic(id as u32, false, data)
+    }
+
+    /// Create a classic CAN frame with an extended (29 bit) identifier.
+    ///
+    /// Returns `Error::InvalidArgument` if the ID does not fit in 29 bits or
+    /// more than 8 data bytes are given.
+    pub fn new_extended(id: u32, data: &[u8]) -> Result<Frame, Error> {
+        if id > MAX_EXTENDED_ID {
+            return Err(Error::InvalidArgument("extended ID must be at most 0x1FFFFFFF"));
+        }
+        Frame::new_classic(id, true, data)
+    }
+
+    /// Create a CAN-FD frame. IDs above 0x7FF use an extended identifier.
+    ///
+    /// Data lengths that cannot be encoded in a DLC are zero-padded to the
+    /// next valid length. Returns `Error::InvalidArgument` if the ID does not
+    /// fit in 29 bits or more than 64 data bytes are given.
+    pub fn new_fd(id: u32, data: &[u8], brs: bool) -> Result<Frame, Error> {
+        if id > MAX_EXTENDED_ID {
+            return Err(Error::InvalidArgument("extended ID must be at most 0x1FFFFFFF"));
+        }
+        if data.len() > 64 {
+            return Err(Error::InvalidArgument("CAN-FD data must be at most 64 bytes"));
+        }
+        let mut f = Frame {
+            can_id: id,
+            can_dlc: 0,
+            ext: id > MAX_STANDARD_ID as u32,
+            fd: true,
+            brs,
+            data: data.to_vec(),
+            ..Default::default()
+        };
+        // smallest DLC covering the data
+        f.can_dlc = dlc_for_len(data.len());
+        f.data.resize(f.data_len(), 0);
+        Ok(f)
+    }
+
+    /// Create a classic CAN frame from a SocketCAN style identifier, with the
+    /// EFF, RTR and ERR flags packed into the top three bits.
+    ///
+    /// Returns `Error::InvalidArgument` if more than 8 data bytes are given.
+    pub fn from_socketcan_id(id: u32, data: &[u8]) -> Result<Frame, Error> {
+        let ext = (id & CAN_EFF_FLAG) != 0;
+        let can_id = if ext {
+            id & MAX_EXTENDED_ID
+        } else {
+            id & MAX_STANDARD_ID as u32
+        };
+        let mut f = Frame::new_classic(can_id, ext, data)?;
+        f.rtr = (id & CAN_RTR_FLAG) != 0;
+        f.err = (id & CAN_ERR_FLAG) != 0;
+        Ok(f)
+    }
+
+    /// Returns the frame with its channel set to `channel`, for building
+    /// frames fluently: `Frame::new_standard(0x123, &[1])?.with_channel(1)`.
+    pub fn with_channel(mut self, channel: u8) -> Frame {
+        self.channel = channel;
+        self
+    }
+
+    /// Returns the identifier in SocketCAN format, with the EFF, RTR and ERR
+    /// flags packed into the top three bits.
+    pub fn socketcan_id(&self) -> u32 {
+        let mut id = if self.ext {
+            (self.can_id & MAX_EXTENDED_ID) | CAN_EFF_FLAG
+        } else {
+            self.can_id & MAX_STANDARD_ID as u32
+        };
+        if self.rtr {
+            id |= CAN_RTR_FLAG;
+        }
+        if self.err {
+            id |= CAN_ERR_FLAG;
+        }
+        id
+    }
+
+    fn new_classic(id: u32, ext: bool, data: &[u8]) -> Result<Frame, Error> {
+        if data.len() > 8 {
+            return Err(Error::InvalidArgument("CAN data must be at most 8 bytes"));
+        }
+        Ok(Frame {
+            can_id: id,
+            can_dlc: data.len() as u8,
+            ext,
+            data: data.to_vec(),
+            ..Default::default()
+        })
+    }
+
+    /// Set `can_dlc` from the length of `data`.
+    ///
+    /// Classic frames get the data length as DLC, up to 8; bytes beyond the
+    /// eighth are not sent. CAN-FD frames get the smallest DLC covering the
+    /// data, and the bytes between the end of the data and the DLC length
+    /// are sent as padding. RTR and error frames are left unchanged, since
+    /// their DLC does not describe the data.
+    pub fn fit_dlc(&mut self) {
+        if self.rtr || self.err {
+            return;
+        }
+        self.can_dlc = if self.fd {
+            dlc_for_len(self.data.len())
+        } else {
+            std::cmp::min(self.data.len(), 8) as u8
+        };
+    }
+
+    // host frame for transmission, fitting the DLC to the data first when
+    // auto_dlc is set
+    #[cfg(any(feature = "usb", test))]
+    pub(crate) fn to_tx_host_frame(&self, pad: u8, auto_dlc: bool) -> HostFrame {
+        if !auto_dlc {
+            return self.to_host_frame(pad);
+        }
+        let mut f = self.clone();
+        f.fit_dlc();
+        f.to_host_frame(pad)
+    }
+
+    // copy the data bytes covered by the DLC. bytes up to the DLC length not
+    // present in data are filled with pad, the rest are zero.
+    pub(crate) fn data_as_array(&self, pad: u8) -> [u8; 64] {
+        let mut data = [0u8; 64];
+        let dlc_len = self.data_len();
+        let len = std::cmp::min(self.data.len(), dlc_len);
+        data[..len].copy_from_slice(&self.data[..len]);
+        data[len..dlc_len].iter_mut().for_each(|b| *b = pad);
+        data
+    }
+    /// Convert to the gs_usb host frame sent to the device, padding data
+    /// shorter than the DLC with `pad`.
+    ///
+    /// The host frame has an echo ID of 1 and no timestamp.
+    pub fn to_host_frame(&self, pad: u8) -> HostFrame {
+        // if frame is extended, set the extended bit in host frame CAN ID
+        let mut can_id = if self.ext {
+            self.can_id | GSUSB_EXT_FLAG
+        } else {
+            self.can_id
+        };
+        // apply RTR and ERR flags
+        can_id = if self.rtr {
+            can_id | GSUSB_RTR_FLAG
+        } else {
+            can_id
+        };
+        can_id = if self.err {
+            can_id | GSUSB_ERR_FLAG
+        } else {
+            can_id
+        };
+
+        HostFrame {
+            echo_id: 1,
+            flags: self.host_flags(),
+            reserved: 0,
+            can_id,
+            can_dlc: self.can_dlc,
+            channel: self.channel,
+            data: self.data_as_array(pad),
+            timestamp: None,
+        }
+    }
+
+    // host frame flags for this frame. BRS and ESI only have a meaning for
+    // FD frames, so they are not set on classic frames.
+    fn host_flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.fd {
+            flags |= GS_CAN_FLAG_FD;
+            if self.brs {
+                flags |= GS_CAN_FLAG_BRS;
+            }
+            if self.esi {
+                flags |= GS_CAN_FLAG_ESI;
+            }
+        }
+        flags
+    }
+    // decode host frame flags into (fd, brs, esi), ignoring unknown flags
+    fn from_host_flags(flags: u8) -> (bool, bool, bool) {
+        let fd = (flags & GS_CAN_FLAG_FD) > 0;
+        let brs = (flags & GS_CAN_FLAG_BRS) > 0;
+        let esi = (flags & GS_CAN_FLAG_ESI) > 0;
+        (fd, brs, esi)
+    }
+    /// Convert a gs_usb host frame received from a device. Host frames with an
+    /// echo ID other than the receive echo ID are treated as loopback frames.
+    ///
+    /// The timestamp is not converted, since its meaning depends on the
+    /// device's timestamp counter; the returned frame has no timestamp.
+    pub fn from_host_frame(hf: HostFrame) -> Frame {
+        let mut f = Frame::default();
+        f.set_from_host_frame(&hf);
+        f
+    }
+
+    /// Overwrite this frame with a gs_usb host frame, as
+    /// `Frame::from_host_frame` does, reusing the allocation of `data`.
+    pub fn set_from_host_frame(&mut self, hf: &HostFrame) {
+        // check the extended bit of host frame
+        // if set, frame is extended
+        let ext = (hf.can_id & GSUSB_EXT_FLAG) > 0;
+        // check the RTR and ERR bits of host frame ID
+        let rtr = (hf.can_id & GSUSB_RTR_FLAG) > 0;
+        let err = (hf.can_id & GSUSB_ERR_FLAG) > 0;
+        // remove flags from CAN ID
+        let can_id = hf.can_id & 0x1FFF_FFFF;
+        // loopback frame if echo_id is not -1
+        let loopback = hf.echo_id != GSUSB_RX_ECHO_ID;
+        let origin = if loopback {
+            FrameOrigin::TxEcho
+        } else {
+            FrameOrigin::Received
+        };
+        // apply FD flags
+        let (fd, brs, esi) = Frame::from_host_flags(hf.flags);
+
+        self.can_id = can_id;
+        self.can_dlc = hf.can_dlc;
+        self.data.clear();
+        self.data.extend_from_slice(&hf.data);
+        self.channel = hf.channel;
+        self.ext = ext;
+        self.loopback = loopback;
+        self.origin = origin;
+        self.rtr = rtr;
+        self.fd = fd;
+        self.brs = brs;
+        self.esi = esi;
+        self.raw_can_id = None;
+        self.err = err;
+        self.timestamp = None;
+    }
+
+    /// Returns the direction of the frame, as used in log formats such as ASC.
+    ///
+    /// Transmit confirmations are `Direction::Tx`. Frames looped back by a
+    /// channel in hardware loopback mode never reached the bus through this
+    /// interface's transmitter and are reported as `Direction::Rx`; the
+    /// transmission itself is reported by its confirmation.
+    pub fn direction(&self) -> Direction {
+        match self.origin {
+            FrameOrigin::TxEcho => Direction::Tx,
+            FrameOrigin::Received | FrameOrigin::Loopback => Direction::Rx,
+        }
+    }
+
+    /// Return the timestamp in whole microseconds, or `None` if the frame
+    /// has no timestamp.
+    pub fn timestamp_micros(&self) -> Option<u64> {
+        self.timestamp.map(|t| t.as_micros() as u64)
+    }
+
+    /// Return the timestamp in nanoseconds, or `None` if the frame has no
+    /// timestamp. Received timestamps are always a whole number of
+    /// microseconds.
+    pub fn timestamp_nanos(&self) -> Option<u64> {
+        self.timestamp.map(|t| t.as_nanos() as u64)
+    }
+
+    /// Return the length of data in this frame. This is the DLC for non-FD frames.
+    ///
+    /// DLC values above 15 are invalid. Rather than failing on a corrupt DLC
+    /// received from a device, they are treated as the maximum length of 64.
+    pub fn data_len(&self) -> usize {
+        match self.can_dlc {
+            0..=8 => self.can_dlc as usize,
+            9 => 12,
+            10 => 16,
+            11 => 20,
+            12 => 24,
+            13 => 32,
+            14 => 48,
+            15 => 64,
+            16..=u8::MAX => 64,
+        }
+    }
+
+    /// Return the valid data bytes of the frame, without padding.
+    ///
+    /// This is the first `Frame.data_len` bytes of `data`, or fewer if
+    /// `data` is shorter. Classic frames carry at most 8 bytes, so a classic
+    /// frame with a DLC above 8 has 8 bytes of payload.
+    pub fn payload(&self) -> &[u8] {
+        let len = self.payload_len();
+        &self.data[..len]
+    }
+
+    /// Return the valid data bytes of the frame for modification, as
+    /// `Frame.payload` does.
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        let len = self.payload_len();
+        &mut self.data[..len]
+    }
+
+    fn payload_len(&self) -> usize {
+        let len = if self.fd {
+            self.data_len()
+        } else {
+            std::cmp::min(self.data_len(), 8)
+        };
+        std::cmp::min(self.data.len(), len)
+    }
+
+    /// Return the worst case number of bits the frame occupies on the bus,
+    /// from the start of frame bit to the end of the interframe space, with
+    /// the largest possible number of stuff bits.
+    ///
+    /// For CAN-FD frames with the bit rate switch flag set, the bits from the
+    /// BRS bit to the CRC delimiter are sent at the data bitrate; the count
+    /// includes them at face value.
+    pub fn bit_length(&self) -> usize {
+        // remote frames carry no data
+        let data_bits = if self.rtr && !self.fd {
+            0
+        } else if self.fd {
+            8 * self.data_len()
+        } else {
+            8 * std::cmp::min(self.data_len(), 8)
+        };
+
+        // CRC delimiter, ACK slot and delimiter, end of frame and interframe
+        // space are never stuffed
+        const TRAILER: usize = 1 + 2 + 7 + 3;
+
+        if !self.fd {
+            // start of frame through data, and the 15 bit CRC, are stuffed
+            let stuffed = if self.ext { 39 } else { 19 } + data_bits + 15;
+            return stuffed + (stuffed - 1) / 4 + TRAILER;
+        }
+
+        // start of frame through data are stuffed dynamically
+        let stuffed = if self.ext { 41 } else { 22 } + data_bits;
+        // the stuff count and CRC have a fixed stuff bit before every 4 bits
+        let crc = if self.data_len() > 16 { 21 } else { 17 };
+        let crc_field = 4 + crc;
+        stuffed + (stuffed - 1) / 4 + crc_field + crc_field.div_ceil(4) + TRAILER
+    }
+}
+
+// smallest CAN-FD DLC whose length covers len bytes
+fn dlc_for_len(len: usize) -> u8 {
+    match len {
+        0..=8 => len as u8,
+        9..=12 => 9,
+        13..=16 => 10,
+        17..=20 => 11,
+        21..=24 => 12,
+        25..=32 => 13,
+        33..=48 => 14,
+        _ => 15,
+    }
+}
+
+impl From<HostFrame> for Frame {
+    fn from(hf: HostFrame) -> Frame {
+        Frame::from_host_frame(hf)
+    }
+}
+
+impl From<&Frame> for HostFrame {
+    /// Convert with zero padding. See `Frame::to_host_frame`.
+    fn from(f: &Frame) -> HostFrame {
+        f.to_host_frame(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constructors() {
+        let f = Frame::new_standard(0x123, &[1, 2, 3]).unwrap();
+        assert_eq!((f.can_id, f.can_dlc, f.ext, f.fd), (0x123, 3, false, false));
+        assert_eq!(f.data, vec![1, 2, 3]);
+        assert!(Frame::new_standard(0x800, &[]).is_err());
+        assert!(Frame::new_standard(0x123, &[0; 9]).is_err());
+
+        let f = Frame::new_extended(0x1234_5678, &[]).unwrap();
+        assert_eq!((f.can_id, f.can_dlc, f.ext), (0x1234_5678, 0, true));
+        assert!(Frame::new_extended(0x2000_0000, &[]).is_err());
+
+        // 13 bytes are padded to the 16 byte DLC
+        let f = Frame::new_fd(0x123, &[0xAA; 13], true).unwrap();
+        assert_eq!((f.can_dlc, f.data_len(), f.ext, f.fd, f.brs), (10, 16, false, true, true));
+        assert_eq!(&f.data[13..], &[0, 0, 0]);
+        assert!(Frame::new_fd(0x800, &[0; 64], false).unwrap().ext);
+        assert!(Frame::new_fd(0x123, &[0; 65], false).is_err());
+
+        let f = Frame::new_standard(0x123, &[]).unwrap().with_channel(2);
+        assert_eq!(f.channel, 2);
+    }
+
+    #[test]
+    fn test_socketcan_id() {
+        let f = Frame::from_socketcan_id(0xC000_1234, &[]).unwrap();
+        assert_eq!(f.can_id, 0x1234);
+        assert!(f.ext && f.rtr && !f.err);
+        assert_eq!(f.socketcan_id(), 0xC000_1234);
+
+        let f = Frame::from_socketcan_id(0x2000_0123, &[1]).unwrap();
+        assert_eq!(f.can_id, 0x123);
+        assert!(!f.ext && !f.rtr && f.err);
+        assert_eq!(f.socketcan_id(), 0x2000_0123);
+
+        assert!(Frame::from_socketcan_id(0x123, &[0; 9]).is_err());
+    }
+
+    #[test]
+    fn test_direction() {
+        let mut f = Frame::default();
+        assert_eq!(f.direction(), Direction::Rx);
+        f.origin = FrameOrigin::Loopback;
+        assert_eq!(f.direction(), Direction::Rx);
+        f.origin = FrameOrigin::TxEcho;
+        assert_eq!(f.direction(), Direction::Tx);
+    }
+
+    #[test]
+    fn test_host_frame_dlc_mismatch() {
+        // 64 bytes of data, but a DLC for 16 bytes
+        let f = Frame {
+            can_dlc: 10,
+            fd: true,
+            data: vec![0xAA; 64],
+            ..Default::default()
+        };
+        let hf = f.to_host_frame(0);
+        assert_eq!(hf.can_dlc, 10);
+        assert_eq!(&hf.data[..16], &[0xAA; 16]);
+        assert_eq!(&hf.data[16..], &[0u8; 48][..]);
+
+        // data shorter than the DLC is zero-padded
+        let f = Frame {
+            can_dlc: 8,
+            data: vec![1, 2, 3],
+            ..Default::default()
+        };
+        let hf = f.to_host_frame(0);
+        assert_eq!(&hf.data[..8], &[1, 2, 3, 0, 0, 0, 0, 0]);
+
+        // or filled with the padding byte, up to the DLC length only
+        let hf = f.to_host_frame(0xCC);
+        assert_eq!(&hf.data[..8], &[1, 2, 3, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC]);
+        assert_eq!(&hf.data[8..], &[0u8; 56][..]);
+    }
+
+    #[test]
+    fn test_host_frame_flags() {
+        let f = Frame::new_fd(0x123, &[0; 12], true).unwrap();
+        let hf = f.to_host_frame(0);
+        assert_eq!(hf.flags, GS_CAN_FLAG_FD | GS_CAN_FLAG_BRS);
+        let f = Frame::from(hf);
+        assert!(f.fd && f.brs && !f.esi);
+        assert_eq!(HostFrame::from(&f).flags, GS_CAN_FLAG_FD | GS_CAN_FLAG_BRS);
+
+        let f = Frame {
+            fd: true,
+            brs: true,
+            esi: true,
+            ..Default::default()
+        };
+        let hf = f.to_host_frame(0);
+        assert_eq!(hf.flags, GS_CAN_FLAG_FD | GS_CAN_FLAG_BRS | GS_CAN_FLAG_ESI);
+
+        // BRS and ESI are dropped from classic frames
+        let f = Frame {
+            brs: true,
+            esi: true,
+            ..Default::default()
+        };
+        assert_eq!(f.to_host_frame(0).flags, 0);
+    }
+
+    #[test]
+    fn test_raw_can_id() {
+        // a received frame with its raw ID preserved
+        let raw = 0x1234 | GSUSB_EXT_FLAG | GSUSB_RTR_FLAG;
+        let mut f = Frame::from_host_frame(HostFrame {
+            echo_id: GSUSB_RX_ECHO_ID,
+            can_id: raw,
+            can_dlc: 0,
+            channel: 0,
+            flags: 0,
+            reserved: 0,
+            data: [0; 64],
+            timestamp: None,
+        });
+        // the flags are still masked from can_id
+        assert_eq!(f.can_id, 0x1234);
+        f.raw_can_id = Some(raw);
+        assert_eq!(f.to_host_frame(0).can_id, raw);
+
+        // an edited frame is sent with the edited ID and flags
+        f.can_id = 0x123;
+        f.ext = false;
+        f.rtr = false;
+        assert_eq!(f.to_host_frame(0).can_id, 0x123);
+        f.err = true;
+        assert_eq!(f.to_host_frame(0).can_id, 0x123 | GSUSB_ERR_FLAG);
+    }
+
+    #[test]
+    fn test_bit_length() {
+        let f = Frame::new_standard(0x123, &[0; 8]).unwrap();
+        assert_eq!(f.bit_length(), 135);
+        let f = Frame::new_standard(0x123, &[]).unwrap();
+        assert_eq!(f.bit_length(), 55);
+        let f = Frame::new_extended(0x123, &[0; 8]).unwrap();
+        assert_eq!(f.bit_length(), 160);
+
+        let f = Frame::new_fd(0x123, &[], false).unwrap();
+        assert_eq!(f.bit_length(), 67);
+        let f = Frame::new_fd(0x123, &[0; 64], true).unwrap();
+        assert_eq!(f.bit_length(), 712);
+    }
+
+    #[test]
+    fn test_timestamp_units() {
+        let mut f = Frame::new_standard(0x100, &[]).unwrap();
+        assert_eq!(f.timestamp_micros(), None);
+        assert_eq!(f.timestamp_nanos(), None);
+        f.timestamp = Some(time::Duration::from_micros(1_500_002));
+        assert_eq!(f.timestamp_micros(), Some(1_500_002));
+        assert_eq!(f.timestamp_nanos(), Some(1_500_002_000));
+    }
+
+    #[test]
+    fn test_data_len() {
+        let mut f = Frame::default();
+        for (dlc, len) in [(0, 0), (8, 8), (9, 12), (13, 32), (15, 64), (16, 64), (255, 64)] {
+            f.can_dlc = dlc;
+            assert_eq!(f.data_len(), len);
+        }
+    }
+
+    #[test]
+    fn test_dlc_zero() {
+        let classic = Frame::new_standard(0x100, &[]).unwrap();
+        let fd = Frame::new_fd(0x100, &[], true).unwrap();
+        for f in [classic, fd] {
+            assert_eq!((f.can_dlc, f.data_len()), (0, 0));
+            // no data bytes, not even padding
+            let hf = f.to_host_frame(0xCC);
+            assert_eq!(hf.can_dlc, 0);
+            assert_eq!(hf.data, [0u8; 64]);
+
+            // through the wire format and back
+            let mut hf = HostFrame::from_le_bytes(&hf.to_le_bytes(), false);
+            hf.echo_id = GSUSB_RX_ECHO_ID;
+            let rx = Frame::from_host_frame(hf);
+            assert_eq!((rx.can_dlc, rx.data_len(), rx.fd), (0, 0, f.fd));
+            assert!(rx.payload().is_empty());
+            assert_eq!(rx.brs, f.brs);
+        }
+    }
+
+    #[test]
+    fn test_fit_dlc() {
+        let mut f = Frame::new_standard(0x100, &[1, 2, 3]).unwrap();
+        f.can_dlc = 8;
+        f.fit_dlc();
+        assert_eq!(f.can_dlc, 3);
+        f.data = vec![0; 10];
+        f.fit_dlc();
+        assert_eq!(f.can_dlc, 8);
+
+        // FD lengths round up, and the rest is padded on transmit
+        let mut f = Frame::new_fd(0x100, &[], false).unwrap();
+        f.data = vec![1; 13];
+        f.fit_dlc();
+        assert_eq!((f.can_dlc, f.data_len()), (10, 16));
+        let hf = f.to_tx_host_frame(0xCC, false);
+        assert_eq!(hf.can_dlc, 10);
+        assert_eq!(&hf.data[13..16], &[0xCC; 3]);
+        f.data = vec![1; 64];
+        f.fit_dlc();
+        assert_eq!(f.can_dlc, 15);
+
+        // RTR frames keep the requested length
+        let mut f = Frame::new_standard(0x100, &[]).unwrap();
+        f.rtr = true;
+        f.can_dlc = 4;
+        f.fit_dlc();
+        assert_eq!(f.can_dlc, 4);
+
+        let mut f = Frame::new_standard(0x100, &[1, 2]).unwrap();
+        f.can_dlc = 0;
+        assert_eq!(f.to_tx_host_frame(0, false).can_dlc, 0);
+        assert_eq!(f.to_tx_host_frame(0, true).can_dlc, 2);
+        // the frame itself is not changed
+        assert_eq!(f.can_dlc, 0);
+    }
+
+    #[test]
+    fn test_payload() {
+        let mut f = Frame::new_fd(0x100, &[1; 10], false).unwrap();
+        // padded to the DLC's length of 12
+        assert_eq!(f.data.len(), 12);
+        assert_eq!(f.payload().len(), 12);
+        f.payload_mut()[0] = 2;
+        assert_eq!(f.data[0], 2);
+
+        // a classic DLC above 8 still means 8 bytes
+        let mut f = Frame::new_standard(0x100, &[1; 8]).unwrap();
+        f.data.extend_from_slice(&[0; 4]);
+        f.can_dlc = 9;
+        assert_eq!(f.payload(), &[1; 8]);
+
+        // data shorter than the DLC
+        f.can_dlc = 8;
+        f.data.truncate(3);
+        assert_eq!(f.payload(), &[1; 3]);
+    }
+}
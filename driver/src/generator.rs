@@ -0,0 +1,209 @@
+//! Generation of random frames for stress testing.
+
+use std::ops::RangeInclusive;
+
+use crate::frame::{MAX_EXTENDED_ID, MAX_STANDARD_ID};
+use crate::Frame;
+
+// xorshift64* generator. not suitable for anything but test traffic, but
+// small, fast and reproducible from a seed.
+#[derive(Debug, Clone)]
+struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    fn new(seed: u64) -> XorShift {
+        const MIX: u64 = 0x9E37_79B9_7F4A_7C15;
+        // the state must never be zero
+        let state = match seed ^ MIX {
+            0 => MIX,
+            s => s,
+        };
+        XorShift { state }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        self.next_f64() < p
+    }
+
+    // uniform in the inclusive range, which must not be empty
+    fn range(&mut self, start: u64, end: u64) -> u64 {
+        start + self.next_u64() % (end - start + 1)
+    }
+}
+
+/// An endless source of random, valid frames.
+///
+/// Every generated frame has an ID that fits its format and a data length
+/// that can be encoded in its DLC, so the frames exercise receivers rather
+/// than the encoder. The same seed always produces the same sequence, so a
+/// failing sequence can be replayed.
+///
+/// ```
+/// use cantact::FrameGenerator;
+///
+/// let frames: Vec<_> = FrameGenerator::new(42)
+///     .ids(0x100..=0x1FF)
+///     .payload_lengths(0..=8)
+///     .take(100)
+///     .collect();
+/// assert!(frames.iter().all(|f| (0x100..=0x1FF).contains(&f.can_id)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct FrameGenerator {
+    rng: XorShift,
+    ids: RangeInclusive<u32>,
+    extended: f64,
+    fd: f64,
+    brs: f64,
+    lengths: RangeInclusive<usize>,
+    channel: u8,
+}
+
+impl FrameGenerator {
+    /// Create a generator of classic frames with any standard ID and 0 to 8
+    /// data bytes on channel 0.
+    pub fn new(seed: u64) -> FrameGenerator {
+        FrameGenerator {
+            rng: XorShift::new(seed),
+            ids: 0..=MAX_STANDARD_ID as u32,
+            extended: 0.0,
+            fd: 0.0,
+            brs: 0.0,
+            lengths: 0..=8,
+            channel: 0,
+        }
+    }
+
+    /// Set the range of IDs. Standard frames use the part of the range
+    /// below 0x800; if there is none, all frames are extended.
+    pub fn ids(mut self, ids: RangeInclusive<u32>) -> FrameGenerator {
+        let end = std::cmp::min(*ids.end(), MAX_EXTENDED_ID);
+        let start = std::cmp::min(*ids.start(), end);
+        self.ids = start..=end;
+        self
+    }
+
+    /// Set the probability, from 0 to 1, that a frame has an extended ID.
+    pub fn extended_probability(mut self, p: f64) -> FrameGenerator {
+        self.extended = p;
+        self
+    }
+
+    /// Set the probability, from 0 to 1, that a frame is a CAN-FD frame.
+    pub fn fd_probability(mut self, p: f64) -> FrameGenerator {
+        self.fd = p;
+        self
+    }
+
+    /// Set the probability, from 0 to 1, that a CAN-FD frame has the bit
+    /// rate switch flag set.
+    pub fn brs_probability(mut self, p: f64) -> FrameGenerator {
+        self.brs = p;
+        self
+    }
+
+    /// Set the range of data lengths, chosen uniformly. Lengths are limited
+    /// to 8 for classic frames and 64 for CAN-FD frames, and CAN-FD data is
+    /// padded up to the next length a DLC can encode.
+    pub fn payload_lengths(mut self, lengths: RangeInclusive<usize>) -> FrameGenerator {
+        self.lengths = lengths;
+        self
+    }
+
+    /// Set the channel of generated frames.
+    pub fn channel(mut self, channel: u8) -> FrameGenerator {
+        self.channel = channel;
+        self
+    }
+
+    fn generate(&mut self) -> Frame {
+        let (start, end) = (*self.ids.start(), *self.ids.end());
+        let standard_end = std::cmp::min(end, MAX_STANDARD_ID as u32);
+        let ext = start > standard_end || self.rng.chance(self.extended);
+        let id_end = if ext { end } else { standard_end };
+        let id = self.rng.range(start as u64, id_end as u64) as u32;
+
+        let fd = self.rng.chance(self.fd);
+        let max_len = if fd { 64 } else { 8 };
+        let len_start = std::cmp::min(*self.lengths.start(), max_len);
+        let len_end = std::cmp::min(*self.lengths.end(), max_len).max(len_start);
+        let len = self.rng.range(len_start as u64, len_end as u64) as usize;
+        let data: Vec<u8> = (0..len).map(|_| self.rng.next_u64() as u8).collect();
+
+        let mut f = if fd {
+            let brs = self.rng.chance(self.brs);
+            let mut f = Frame::new_fd(id, &data, brs).unwrap();
+            // new_fd picks the format from the ID
+            f.ext = ext;
+            f
+        } else if ext {
+            Frame::new_extended(id, &data).unwrap()
+        } else {
+            Frame::new_standard(id as u16, &data).unwrap()
+        };
+        f.channel = self.channel;
+        f
+    }
+}
+
+impl Iterator for FrameGenerator {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        Some(self.generate())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generator() {
+        let frames: Vec<Frame> = FrameGenerator::new(1)
+            .ids(0x700..=0x900)
+            .extended_probability(0.5)
+            .fd_probability(0.5)
+            .brs_probability(1.0)
+            .payload_lengths(0..=64)
+            .channel(1)
+            .take(1000)
+            .collect();
+        for f in frames.iter() {
+            assert!((0x700..=0x900).contains(&f.can_id));
+            assert!(f.ext || f.can_id <= MAX_STANDARD_ID as u32);
+            assert_eq!(f.data.len(), f.data_len());
+            assert!(f.fd || f.data.len() <= 8);
+            assert_eq!(f.brs, f.fd);
+            assert_eq!(f.channel, 1);
+        }
+        assert!(frames.iter().any(|f| f.ext) && frames.iter().any(|f| !f.ext));
+        assert!(frames.iter().any(|f| f.fd) && frames.iter().any(|f| !f.fd));
+
+        // the same seed gives the same frames
+        let a: Vec<u32> = FrameGenerator::new(7).take(10).map(|f| f.can_id).collect();
+        let b: Vec<u32> = FrameGenerator::new(7).take(10).map(|f| f.can_id).collect();
+        let c: Vec<u32> = FrameGenerator::new(8).take(10).map(|f| f.can_id).collect();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+
+        // no standard IDs in the range
+        assert!(FrameGenerator::new(3).ids(0x1000..=0x2000).take(10).all(|f| f.ext));
+    }
+}
@@ -0,0 +1,494 @@
+//! Structure declarations for the GSUSB protocol
+#![allow(dead_code)]
+
+use crate::BitTimingLimits;
+
+// can id is OR'd with flag when frame is extended
+pub(crate) const GSUSB_EXT_FLAG: u32 = 0x8000_0000;
+// can id is OR'd with flag when frame is RTR
+pub(crate) const GSUSB_RTR_FLAG: u32 = 0x4000_0000;
+// can id is OR'd with flag when frame is an error frame
+pub(crate) const GSUSB_ERR_FLAG: u32 = 0x2000_0000;
+// echo id for non-loopback frames
+pub(crate) const GSUSB_RX_ECHO_ID: u32 = 0xFFFF_FFFF;
+// hardware timestamps are reported by the device in microseconds
+pub(crate) const GSUSB_TIMESTAMP_FREQ_HZ: u32 = 1_000_000;
+
+// device features bit map
+pub(crate) const GS_CAN_FEATURE_NORMAL: u32 = 0;
+pub(crate) const GS_CAN_FEATURE_LISTEN_ONLY: u32 = 1;
+pub(crate) const GS_CAN_FEATURE_LOOP_BACK: u32 = 1 << 1;
+pub(crate) const GS_CAN_FEATURE_TRIPLE_SAMPLE: u32 = 1 << 2;
+pub(crate) const GS_CAN_FEATURE_ONE_SHOT: u32 = 1 << 3;
+pub(crate) const GS_CAN_FEATURE_HW_TIMESTAMP: u32 = 1 << 4;
+pub(crate) const GS_CAN_FEATURE_IDENTIFY: u32 = 1 << 5;
+pub(crate) const GS_CAN_FEATURE_USER_ID: u32 = 1 << 6;
+pub(crate) const GS_CAN_FEATURE_PAD_PKTS_TO_MAX_PKT_SIZE: u32 = 1 << 7;
+pub(crate) const GS_CAN_FEATURE_FD: u32 = 1 << 8;
+pub(crate) const GS_CAN_FEATURE_BT_CONST_EXT: u32 = 1 << 10;
+pub(crate) const GS_CAN_FEATURE_TERMINATION: u32 = 1 << 11;
+pub(crate) const GS_CAN_FEATURE_BERR_REPORTING: u32 = 1 << 12;
+pub(crate) const GS_CAN_FEATURE_GET_STATE: u32 = 1 << 13;
+
+// device mode bit map
+pub(crate) const GS_CAN_MODE_NORMAL: u32 = 0;
+pub(crate) const GS_CAN_MODE_LISTEN_ONLY: u32 = 1;
+pub(crate) const GS_CAN_MODE_LOOP_BACK: u32 = 1 << 1;
+pub(crate) const GS_CAN_MODE_TRIPLE_SAMPLE: u32 = 1 << 2;
+pub(crate) const GS_CAN_MODE_ONE_SHOT: u32 = 1 << 3;
+pub(crate) const GS_CAN_MODE_HW_TIMESTAMP: u32 = 1 << 4;
+pub(crate) const GS_CAN_MODE_PAD_PKTS_TO_MAX_PKT_SIZE: u32 = 1 << 7;
+pub(crate) const GS_CAN_MODE_FD: u32 = 1 << 8;
+pub(crate) const GS_CAN_MODE_BERR_REPORTING: u32 = 1 << 12;
+
+// frame flags bit map
+pub(crate) const GS_CAN_FLAG_OVERFLOW: u8 = 1;
+pub(crate) const GS_CAN_FLAG_FD: u8 = 1 << 1;
+pub(crate) const GS_CAN_FLAG_BRS: u8 = 1 << 2;
+pub(crate) const GS_CAN_FLAG_ESI: u8 = 1 << 3;
+
+// identify request values
+pub(crate) const GS_CAN_IDENTIFY_OFF: u32 = 0;
+pub(crate) const GS_CAN_IDENTIFY_ON: u32 = 1;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum UsbBreq {
+    HostFormat = 0,
+    BitTiming,
+    Mode,
+    Berr,
+    BitTimingConsts,
+    DeviceConfig,
+    Timestamp,
+    Identify,
+    DataBitTiming,
+    BitTimingConstsExt = 11,
+    GetState = 14,
+}
+
+#[repr(u8)]
+pub(crate) enum CanMode {
+    Reset = 0,
+    Start,
+}
+
+#[repr(u8)]
+pub(crate) enum CanState {
+    ErrorActive = 0,
+    ErrorWarning,
+    ErrorPassive,
+    BusOff,
+    Stopped,
+    Sleeping,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub(crate) struct DeviceState {
+    pub(crate) state: u32,
+    pub(crate) rxerr: u32,
+    pub(crate) txerr: u32,
+}
+impl DeviceState {
+    pub(crate) fn from_le_bytes(bs: &[u8]) -> DeviceState {
+        DeviceState {
+            state: u32_from_le_bytes(&bs[0..4]),
+            rxerr: u32_from_le_bytes(&bs[4..8]),
+            txerr: u32_from_le_bytes(&bs[8..12]),
+        }
+    }
+
+    // true if the controller is participating in bus traffic
+    pub(crate) fn is_active(&self) -> bool {
+        self.state == CanState::ErrorActive as u32
+            || self.state == CanState::ErrorWarning as u32
+            || self.state == CanState::ErrorPassive as u32
+    }
+}
+
+fn u32_from_le_bytes(bs: &[u8]) -> u32 {
+    let arr: [u8; 4] = [bs[0], bs[1], bs[2], bs[3]];
+    u32::from_le_bytes(arr)
+}
+
+#[repr(C)]
+pub(crate) struct Mode {
+    pub mode: u32,
+    pub flags: u32,
+}
+impl Mode {
+    pub(crate) fn to_le_bytes(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = vec![];
+        data.extend_from_slice(&self.mode.to_le_bytes());
+        data.extend_from_slice(&self.flags.to_le_bytes());
+        data
+    }
+}
+
+/// Bit timing segments of a CAN channel, in time quanta.
+///
+/// A bit is one synchronization quantum followed by `prop_seg`,
+/// `phase_seg1` and `phase_seg2`, and each quantum is `brp` cycles of the
+/// CAN clock. See `BitTiming::describe` for the resulting bitrate and
+/// sample point.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitTiming {
+    /// Propagation segment.
+    pub prop_seg: u32,
+    /// Phase segment 1, before the sample point.
+    pub phase_seg1: u32,
+    /// Phase segment 2, after the sample point.
+    pub phase_seg2: u32,
+    /// Synchronization jump width.
+    pub sjw: u32,
+    /// Bitrate prescaler: CAN clock cycles per time quantum.
+    pub brp: u32,
+}
+impl BitTiming {
+    pub(crate) fn to_le_bytes(self) -> Vec<u8> {
+        let mut data: Vec<u8> = vec![];
+        data.extend_from_slice(&self.prop_seg.to_le_bytes());
+        data.extend_from_slice(&self.phase_seg1.to_le_bytes());
+        data.extend_from_slice(&self.phase_seg2.to_le_bytes());
+        data.extend_from_slice(&self.sjw.to_le_bytes());
+        data.extend_from_slice(&self.brp.to_le_bytes());
+        data
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub(crate) struct BitTimingConsts {
+    pub(crate) feature: u32,
+    pub(crate) fclk_can: u32,
+    tseg1_min: u32,
+    tseg1_max: u32,
+    tseg2_min: u32,
+    tseg2_max: u32,
+    sjw_max: u32,
+    brp_min: u32,
+    brp_max: u32,
+    brp_inc: u32,
+}
+impl BitTimingConsts {
+    pub(crate) fn from_le_bytes(bs: &[u8]) -> BitTimingConsts {
+        BitTimingConsts {
+            feature: u32_from_le_bytes(&bs[0..4]),
+            fclk_can: u32_from_le_bytes(&bs[4..8]),
+            tseg1_min: u32_from_le_bytes(&bs[8..12]),
+            tseg1_max: u32_from_le_bytes(&bs[12..16]),
+            tseg2_min: u32_from_le_bytes(&bs[16..20]),
+            tseg2_max: u32_from_le_bytes(&bs[20..24]),
+            sjw_max: u32_from_le_bytes(&bs[24..28]),
+            brp_min: u32_from_le_bytes(&bs[28..32]),
+            brp_max: u32_from_le_bytes(&bs[32..36]),
+            brp_inc: u32_from_le_bytes(&bs[36..40]),
+        }
+    }
+    // the extended constants repeat the nominal limits, followed by the same
+    // fields for the data phase. returns the data phase limits.
+    pub(crate) fn data_from_ext_le_bytes(bs: &[u8]) -> BitTimingConsts {
+        let mut data = bs[0..8].to_vec();
+        data.extend_from_slice(&bs[40..72]);
+        BitTimingConsts::from_le_bytes(&data)
+    }
+    pub(crate) fn limits(&self) -> BitTimingLimits {
+        BitTimingLimits {
+            tseg1_min: self.tseg1_min,
+            tseg1_max: self.tseg1_max,
+            tseg2_min: self.tseg2_min,
+            tseg2_max: self.tseg2_max,
+            sjw_max: self.sjw_max,
+            brp_min: self.brp_min,
+            brp_max: self.brp_max,
+            brp_inc: self.brp_inc,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub(crate) struct DeviceConfig {
+    reserved1: u8,
+    reserved2: u8,
+    reserved3: u8,
+    pub(crate) icount: u8,
+    pub(crate) sw_version: u32,
+    pub(crate) hw_version: u32,
+}
+impl DeviceConfig {
+    pub(crate) fn from_le_bytes(bs: &[u8]) -> DeviceConfig {
+        DeviceConfig {
+            reserved1: bs[0],
+            reserved2: bs[1],
+            reserved3: bs[2],
+            icount: bs[3],
+            sw_version: u32_from_le_bytes(&bs[4..8]),
+            hw_version: u32_from_le_bytes(&bs[8..12]),
+        }
+    }
+}
+
+/// A frame in the gs_usb wire format, as exchanged with the device.
+///
+/// Most applications should use `Frame`. Host frames are useful for custom
+/// transports that need the same encoding as the driver; convert with
+/// `Frame::to_host_frame` and `Frame::from_host_frame`, or the `From` impls.
+///
+/// The wire format is encoded field by field rather than from the struct's
+/// memory layout, so it is the same on big-endian hosts. All multi-byte
+/// fields are little-endian:
+///
+/// | offset | size     | field                            |
+/// |--------|----------|----------------------------------|
+/// | 0      | 4        | `echo_id`                        |
+/// | 4      | 4        | `can_id`                         |
+/// | 8      | 1        | `can_dlc`                        |
+/// | 9      | 1        | `channel`                        |
+/// | 10     | 1        | `flags`                          |
+/// | 11     | 1        | `reserved`                       |
+/// | 12     | 8 or 64  | `data`, 64 bytes for CAN-FD      |
+/// | 20/76  | 4        | `timestamp`, in timestamp mode   |
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostFrame {
+    /// Echo ID, `0xFFFFFFFF` for frames received from the bus.
+    pub echo_id: u32,
+    /// CAN ID, with the extended, RTR and error flags in the top three bits.
+    pub can_id: u32,
+
+    /// Data length code.
+    pub can_dlc: u8,
+    /// Channel of the frame.
+    pub channel: u8,
+    /// gs_usb frame flags (FD, BRS, ESI).
+    pub flags: u8,
+    /// Reserved, zero.
+    pub reserved: u8,
+
+    /// Frame data. Classic frames use only the first 8 bytes.
+    pub data: [u8; 64],
+
+    /// Device timestamp in ticks, only present when the device is started in
+    /// hardware timestamp mode.
+    pub timestamp: Option<u32>,
+}
+impl HostFrame {
+    // size of a host frame on the wire, given its flags
+    pub(crate) fn wire_size(flags: u8, hw_timestamp: bool) -> usize {
+        let data_size = if (flags & GS_CAN_FLAG_FD) != 0 { 64 } else { 8 };
+        let timestamp_size = if hw_timestamp { 4 } else { 0 };
+        12 + data_size + timestamp_size
+    }
+    /// Decode a host frame from its wire format. `hw_timestamp` selects
+    /// whether a timestamp follows the data. Missing data bytes are zeroed.
+    ///
+    /// Panics if `bs` is shorter than the 12 byte header.
+    pub fn from_le_bytes(bs: &[u8], hw_timestamp: bool) -> HostFrame {
+        let flags = bs[10];
+        // legacy gs_host_frame is limited to 8 bytes of data
+        let data_size = if (flags & GS_CAN_FLAG_FD) != 0 { 64 } else { 8 };
+        let data_end = std::cmp::min(bs.len(), 12 + data_size);
+
+        let mut data: [u8; 64] = [0u8; 64];
+        // copy data bytes to array
+        data[..(data_end - 12)].clone_from_slice(&bs[12..data_end]);
+
+        // the timestamp follows the data field
+        let ts_start = 12 + data_size;
+        let timestamp = if hw_timestamp && bs.len() >= ts_start + 4 {
+            Some(u32_from_le_bytes(&bs[ts_start..ts_start + 4]))
+        } else {
+            None
+        };
+
+        HostFrame {
+            echo_id: u32_from_le_bytes(&bs[0..4]),
+            can_id: u32_from_le_bytes(&bs[4..8]),
+            can_dlc: bs[8],
+            channel: bs[9],
+            flags,
+            reserved: bs[11],
+            data,
+            timestamp,
+        }
+    }
+    /// Encode the host frame in its wire format, without a timestamp.
+    pub fn to_le_bytes(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = vec![];
+        data.extend_from_slice(&self.echo_id.to_le_bytes());
+        data.extend_from_slice(&self.can_id.to_le_bytes());
+        data.push(self.can_dlc);
+        data.push(self.channel);
+        data.push(self.flags);
+        data.push(self.reserved);
+        if (self.flags & GS_CAN_FLAG_FD) != 0 || self.can_dlc > 8 {
+            data.extend_from_slice(&self.data);
+        } else { // legacy gs_host_frame is limited to 8 bytes of data
+            data.extend_from_slice(&self.data[..8]);
+        }
+        data
+    }
+}
+
+/// The host frames of one bulk in transfer. The first frame is stored
+/// inline, so passing the usual transfer of a single frame from the USB
+/// callback to the receiver does not allocate.
+#[derive(Debug, Clone, Default)]
+pub struct HostFrameBatch {
+    first: Option<HostFrame>,
+    rest: Vec<HostFrame>,
+}
+impl HostFrameBatch {
+    /// Parse all host frames packed into one transfer buffer. A trailing
+    /// frame shorter than its wire size is parsed with the missing bytes
+    /// zeroed, like a single short frame.
+    pub fn parse(bs: &[u8], hw_timestamp: bool) -> HostFrameBatch {
+        let mut batch = HostFrameBatch::default();
+        let mut rest = bs;
+        // stop once there is not enough left for a frame header
+        while rest.len() >= 12 {
+            let size = std::cmp::min(rest.len(), HostFrame::wire_size(rest[10], hw_timestamp));
+            batch.push(HostFrame::from_le_bytes(&rest[..size], hw_timestamp));
+            rest = &rest[size..];
+        }
+        batch
+    }
+
+    fn push(&mut self, hf: HostFrame) {
+        if self.first.is_none() {
+            self.first = Some(hf);
+        } else {
+            self.rest.push(hf);
+        }
+    }
+
+    /// Returns the number of frames in the batch.
+    pub fn len(&self) -> usize {
+        self.first.iter().count() + self.rest.len()
+    }
+
+    /// Returns true if the batch holds no frames.
+    pub fn is_empty(&self) -> bool {
+        self.first.is_none()
+    }
+
+    /// Returns an iterator over the frames in the batch.
+    pub fn iter(&self) -> impl Iterator<Item = &HostFrame> {
+        self.first.iter().chain(self.rest.iter())
+    }
+}
+impl From<HostFrame> for HostFrameBatch {
+    fn from(hf: HostFrame) -> HostFrameBatch {
+        HostFrameBatch {
+            first: Some(hf),
+            rest: Vec::new(),
+        }
+    }
+}
+impl IntoIterator for HostFrameBatch {
+    type Item = HostFrame;
+    type IntoIter =
+        std::iter::Chain<std::option::IntoIter<HostFrame>, std::vec::IntoIter<HostFrame>>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.first.into_iter().chain(self.rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classic(can_id: u32) -> HostFrame {
+        HostFrame {
+            echo_id: GSUSB_RX_ECHO_ID,
+            can_id,
+            can_dlc: 8,
+            channel: 0,
+            flags: 0,
+            reserved: 0,
+            data: [0u8; 64],
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_data_bit_timing_limits() {
+        let words: [u32; 18] = [
+            GS_CAN_FEATURE_FD | GS_CAN_FEATURE_BT_CONST_EXT,
+            80_000_000,
+            // nominal
+            1, 256, 1, 128, 128, 1, 512, 1,
+            // data phase
+            1, 32, 1, 16, 16, 1, 4, 1,
+        ];
+        let bs: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        let consts = BitTimingConsts::data_from_ext_le_bytes(&bs);
+        assert_eq!(consts.fclk_can, 80_000_000);
+        assert_eq!(consts.tseg1_max, 32);
+        assert_eq!(consts.brp_max, 4);
+
+        let mut bt = BitTiming {
+            prop_seg: 0,
+            phase_seg1: 13,
+            phase_seg2: 2,
+            sjw: 1,
+            brp: 2,
+        };
+        assert!(consts.limits().accepts(&bt));
+        // a prescaler the data phase cannot use
+        bt.brp = 20;
+        assert!(!consts.limits().accepts(&bt));
+    }
+
+    #[test]
+    fn test_host_frame_wire_format() {
+        #[rustfmt::skip]
+        let bs: Vec<u8> = vec![
+            0x01, 0x02, 0x03, 0x04, // echo_id
+            0x23, 0x01, 0x00, 0x80, // can_id, extended
+            0x03, 0x01, 0x00, 0x00, // dlc, channel, flags, reserved
+            0xAA, 0xBB, 0xCC, 0x00, 0x00, 0x00, 0x00, 0x00, // data
+            0x78, 0x56, 0x34, 0x12, // timestamp
+        ];
+        let hf = HostFrame::from_le_bytes(&bs, true);
+        assert_eq!(hf.echo_id, 0x0403_0201);
+        assert_eq!(hf.can_id, GSUSB_EXT_FLAG | 0x123);
+        assert_eq!((hf.can_dlc, hf.channel, hf.flags), (3, 1, 0));
+        assert_eq!(&hf.data[..4], &[0xAA, 0xBB, 0xCC, 0x00]);
+        assert_eq!(hf.timestamp, Some(0x1234_5678));
+        // the timestamp is never sent to the device
+        assert_eq!(hf.to_le_bytes(), &bs[..20]);
+
+        let mut fd = bs[..12].to_vec();
+        fd[10] = GS_CAN_FLAG_FD;
+        fd.extend((0..64).map(|n| n as u8));
+        let hf = HostFrame::from_le_bytes(&fd, false);
+        assert_eq!(hf.data[63], 63);
+        assert_eq!(hf.timestamp, None);
+        assert_eq!(hf.to_le_bytes(), fd);
+    }
+
+    #[test]
+    fn test_parse_batch() {
+        let mut fd = classic(0x200);
+        fd.flags = GS_CAN_FLAG_FD;
+        fd.can_dlc = 15;
+
+        let mut buf = classic(0x100).to_le_bytes();
+        buf.extend(fd.to_le_bytes());
+        buf.extend(classic(0x300).to_le_bytes());
+        let frames: Vec<HostFrame> = HostFrameBatch::parse(&buf, false).into_iter().collect();
+        let ids: Vec<u32> = frames.iter().map(|f| f.can_id).collect();
+        assert_eq!(ids, vec![0x100, 0x200, 0x300]);
+        assert_eq!(frames[1].can_dlc, 15);
+
+        // a truncated trailing frame is still parsed, a partial header is not
+        let frames = HostFrameBatch::parse(&buf[..20 + 76 + 12], false);
+        assert_eq!(frames.len(), 3);
+        let frames = HostFrameBatch::parse(&buf[..20 + 11], false);
+        assert_eq!(frames.len(), 1);
+        assert!(HostFrameBatch::parse(&buf[..11], false).is_empty());
+    }
+}
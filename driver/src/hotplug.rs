@@ -0,0 +1,254 @@
+//! Notification of devices being connected and disconnected.
+
+use std::collections::HashSet;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+use libc::{c_int, c_void};
+use libusb1_sys::constants::*;
+use libusb1_sys::*;
+
+use crate::device::{self, UsbContext, USB_PID, USB_VID};
+use crate::{DeviceSelector, Error};
+
+// how often devices are listed when libusb has no hotplug support
+const POLL_INTERVAL: time::Duration = time::Duration::from_secs(1);
+// how long the event thread waits for libusb events before checking for a
+// stop request
+const EVENT_TIMEOUT_US: libc::suseconds_t = 100_000;
+
+/// A connected CANtact device, identified by its USB bus and address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DeviceInfo {
+    /// USB bus number.
+    pub bus: u8,
+    /// Device address on the bus.
+    pub address: u8,
+}
+
+impl DeviceInfo {
+    /// Returns a selector that opens this device, for use with
+    /// `InterfaceBuilder::device`.
+    pub fn selector(&self) -> DeviceSelector {
+        DeviceSelector::Address {
+            bus: self.bus,
+            address: self.address,
+        }
+    }
+}
+
+/// A connected CANtact device with its USB strings, returned by
+/// `describe_devices`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceDescription {
+    /// USB bus and address of the device.
+    pub info: DeviceInfo,
+    /// USB product string, if it could be read.
+    pub product: Option<String>,
+    /// USB serial number string, if it could be read.
+    pub serial: Option<String>,
+}
+
+/// A device being connected or disconnected, reported by `watch_hotplug`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotplugEvent {
+    /// The device was connected.
+    Arrived(DeviceInfo),
+    /// The device was disconnected.
+    Left(DeviceInfo),
+}
+
+/// List the connected CANtact devices, in the order counted by
+/// `DeviceSelector::Index`.
+pub fn list_devices() -> Result<Vec<DeviceInfo>, Error> {
+    Ok(device::list_devices(&UsbContext::new())?)
+}
+
+/// List the connected CANtact devices with their product and serial number
+/// strings, in the order counted by `DeviceSelector::Index`.
+///
+/// The strings are read once per connected device and cached for the life
+/// of the process. Reading them opens the device briefly but does not claim
+/// it or detach a kernel driver. Devices opened as an `Interface` by this
+/// process are not opened again. On Linux, a device another process has
+/// claimed is not opened at all, and its strings are `None` until it is
+/// released. Strings of a device the user has no permission to open are
+/// also `None`.
+pub fn describe_devices() -> Result<Vec<DeviceDescription>, Error> {
+    let devices = device::list_identities(&UsbContext::new())?;
+    Ok(devices
+        .into_iter()
+        .map(|(info, identity)| DeviceDescription {
+            info,
+            product: identity.product,
+            serial: identity.serial,
+        })
+        .collect())
+}
+
+type Callback = Box<dyn Fn(HotplugEvent) + Send>;
+
+/// Watches for devices being connected and disconnected until it is dropped.
+/// Returned by `watch_hotplug`.
+pub struct HotplugWatch {
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for HotplugWatch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(t) = self.thread.take() {
+            t.thread().unpark();
+            let _ = t.join();
+        }
+    }
+}
+
+/// Call `callback` whenever a CANtact device is connected or disconnected.
+///
+/// Devices that are already connected are reported as arrived first, so a
+/// service can connect to them the same way as to devices plugged in later.
+/// Libusb hotplug notifications are used where libusb supports them (Linux
+/// and macOS); elsewhere the connected devices are listed once per second
+/// and changes are reported.
+///
+/// The callback runs on a background thread, and may be called before this
+/// function returns. It should not block for long, since events are not
+/// reported while it runs. Watching stops when the returned `HotplugWatch`
+/// is dropped.
+pub fn watch_hotplug(
+    callback: impl Fn(HotplugEvent) + Send + 'static,
+) -> Result<HotplugWatch, Error> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let callback: Callback = Box::new(callback);
+    let thread = if unsafe { libusb_has_capability(LIBUSB_CAP_HAS_HOTPLUG) } != 0 {
+        watch_libusb(callback, Arc::clone(&stop))?
+    } else {
+        watch_polling(callback, Arc::clone(&stop))
+    };
+    Ok(HotplugWatch {
+        stop,
+        thread: Some(thread),
+    })
+}
+
+extern "system" fn hotplug_cb(
+    _ctx: *mut libusb_context,
+    dev: *mut libusb_device,
+    event: libusb_hotplug_event,
+    user_data: *mut c_void,
+) -> c_int {
+    let callback = unsafe { &*(user_data as *const Callback) };
+    let info = DeviceInfo {
+        bus: unsafe { libusb_get_bus_number(dev) },
+        address: unsafe { libusb_get_device_address(dev) },
+    };
+    match event {
+        LIBUSB_HOTPLUG_EVENT_DEVICE_ARRIVED => callback(HotplugEvent::Arrived(info)),
+        LIBUSB_HOTPLUG_EVENT_DEVICE_LEFT => callback(HotplugEvent::Left(info)),
+        _ => {}
+    }
+    // zero keeps the callback registered
+    0
+}
+
+fn watch_libusb(
+    callback: Callback,
+    stop: Arc<AtomicBool>,
+) -> Result<thread::JoinHandle<()>, Error> {
+    let ctx = UsbContext::new();
+    let data = Box::into_raw(Box::new(callback));
+    let mut handle: libusb_hotplug_callback_handle = 0;
+    let rc = unsafe {
+        libusb_hotplug_register_callback(
+            ctx.as_ptr(),
+            LIBUSB_HOTPLUG_EVENT_DEVICE_ARRIVED | LIBUSB_HOTPLUG_EVENT_DEVICE_LEFT,
+            LIBUSB_HOTPLUG_ENUMERATE,
+            USB_VID as c_int,
+            USB_PID as c_int,
+            LIBUSB_HOTPLUG_MATCH_ANY,
+            hotplug_cb,
+            data as *mut c_void,
+            &mut handle,
+        )
+    };
+    if rc != LIBUSB_SUCCESS {
+        drop(unsafe { Box::from_raw(data) });
+        return Err(device::Error::Libusb("libusb_hotplug_register_callback", rc).into());
+    }
+
+    // the callback is only used by the event thread from here on
+    let data = data as usize;
+    Ok(thread::spawn(move || {
+        let tv = libc::timeval {
+            tv_sec: 0,
+            tv_usec: EVENT_TIMEOUT_US,
+        };
+        while !stop.load(Ordering::SeqCst) {
+            unsafe {
+                libusb_handle_events_timeout_completed(ctx.as_ptr(), &tv, ptr::null_mut());
+            }
+        }
+        unsafe {
+            libusb_hotplug_deregister_callback(ctx.as_ptr(), handle);
+            drop(Box::from_raw(data as *mut Callback));
+        }
+    }))
+}
+
+fn watch_polling(callback: Callback, stop: Arc<AtomicBool>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let ctx = UsbContext::new();
+        let mut known = HashSet::new();
+        while !stop.load(Ordering::SeqCst) {
+            // listing can fail while a device is being enumerated by the
+            // system; it is tried again on the next poll
+            if let Ok(devices) = device::list_devices(&ctx) {
+                let current: HashSet<DeviceInfo> = devices.into_iter().collect();
+                for event in changes(&known, &current) {
+                    callback(event);
+                }
+                known = current;
+            }
+            // unparked when the watch is dropped
+            thread::park_timeout(POLL_INTERVAL);
+        }
+    })
+}
+
+// events for the difference between two sets of connected devices
+fn changes(known: &HashSet<DeviceInfo>, current: &HashSet<DeviceInfo>) -> Vec<HotplugEvent> {
+    let mut left: Vec<DeviceInfo> = known.difference(current).copied().collect();
+    let mut arrived: Vec<DeviceInfo> = current.difference(known).copied().collect();
+    left.sort();
+    arrived.sort();
+    left.into_iter()
+        .map(HotplugEvent::Left)
+        .chain(arrived.into_iter().map(HotplugEvent::Arrived))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes() {
+        let dev = |address| DeviceInfo { bus: 1, address };
+        let known: HashSet<DeviceInfo> = vec![dev(2), dev(3)].into_iter().collect();
+        let current: HashSet<DeviceInfo> = vec![dev(3), dev(5), dev(4)].into_iter().collect();
+        assert_eq!(
+            changes(&known, &current),
+            vec![
+                HotplugEvent::Left(dev(2)),
+                HotplugEvent::Arrived(dev(4)),
+                HotplugEvent::Arrived(dev(5)),
+            ]
+        );
+        assert!(changes(&current, &current).is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+//! Identity of a device, for support requests and inventory records.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Everything identifying a device and its firmware, returned by
+/// `Interface::device_identity`.
+///
+/// The `Display` impl formats a short record such as:
+///
+/// ```text
+/// cantact (1d50:606f), serial 003A00405734570920383535
+/// firmware version: 2, hardware version: 1, channels: 1, CAN clock: 48000000 Hz
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceIdentity {
+    /// USB vendor ID.
+    pub vendor_id: u16,
+    /// USB product ID.
+    pub product_id: u16,
+    /// USB product string, if the device has one.
+    pub product: Option<String>,
+    /// USB serial number string, if the device has one.
+    pub serial: Option<String>,
+    /// Firmware version.
+    pub sw_version: u32,
+    /// Hardware version.
+    pub hw_version: u32,
+    /// Number of channels.
+    pub channels: usize,
+    /// Frequency of the CAN controller clock in Hz.
+    pub can_clock: u32,
+}
+
+impl fmt::Display for DeviceIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} ({:04x}:{:04x}), serial {}",
+            self.product.as_deref().unwrap_or("unknown product"),
+            self.vendor_id,
+            self.product_id,
+            self.serial.as_deref().unwrap_or("unknown"),
+        )?;
+        write!(
+            f,
+            "firmware version: {}, hardware version: {}, channels: {}, CAN clock: {} Hz",
+            self.sw_version, self.hw_version, self.channels, self.can_clock,
+        )
+    }
+}
+
+/// The USB interface and bulk endpoints used to talk to a device, returned
+/// by `Interface::usb_info`.
+///
+/// They are found in the device's configuration descriptor, so composite
+/// devices whose CAN interface follows others, such as a CDC-ACM or DFU
+/// interface, are supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsbInfo {
+    /// Number of the gs_usb interface.
+    pub interface: u8,
+    /// Address of the bulk in endpoint receiving frames.
+    pub bulk_in: u8,
+    /// Address of the bulk out endpoint sending frames.
+    pub bulk_out: u8,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let mut id = DeviceIdentity {
+            vendor_id: 0x1d50,
+            product_id: 0x606f,
+            product: Some(String::from("cantact")),
+            serial: Some(String::from("0042")),
+            sw_version: 2,
+            hw_version: 1,
+            channels: 1,
+            can_clock: 48_000_000,
+        };
+        assert_eq!(
+            id.to_string(),
+            "cantact (1d50:606f), serial 0042\n\
+             firmware version: 2, hardware version: 1, channels: 1, CAN clock: 48000000 Hz"
+        );
+        id.product = None;
+        id.serial = None;
+        assert!(id
+            .to_string()
+            .starts_with("unknown product (1d50:606f), serial unknown\n"));
+    }
+}
@@ -0,0 +1,2286 @@
+//! The interface to a CANtact device, and the receive and transmit state
+//! it keeps while the device is running.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, Weak};
+use std::thread;
+use std::time;
+
+use crossbeam_channel::{unbounded, Receiver, RecvError, RecvTimeoutError};
+use log::warn;
+
+use crate::device::{self, *};
+use crate::logger::FrameLogger;
+use crate::rx::{RxConfig, RxPipeline, RxWatchdog, Tap};
+use crate::timing::{calculate_bit_timing_with_options, calculate_data_bit_timing};
+use crate::tx::{wait_until, Completions, Scheduler, TxPath, TxQueue, TX_WINDOW_TIMEOUT};
+use crate::{
+    BitTimingInfo, BitTimingLimits, BitTimingOptions, BusError, CapabilityReport, Channel,
+    ChannelResult, ChannelSnapshot, DeviceIdentity, DeviceSelector, Error, ErrorCounters, Frame,
+    FrameOrigin, FrameSender, InterfaceSnapshot, LatencyStats, LogFormat, LogRotation, PeriodicTx,
+    SendResult, UsbInfo,
+};
+
+/// Interface for interacting with CANtact devices
+pub struct Interface {
+    dev: Arc<Mutex<Device>>,
+    can_rx: Receiver<HostFrameBatch>,
+    running: Arc<RwLock<bool>>,
+
+    can_clock: u32,
+    // number of channels on the device
+    num_channels: usize,
+    sw_version: u32,
+    hw_version: u32,
+    features: u32,
+    // nominal limits, and data phase limits on CAN-FD devices
+    bit_timing_limits: BitTimingLimits,
+    data_bit_timing_limits: Option<BitTimingLimits>,
+    pub(crate) timestamp_frequency: Option<u32>,
+    // used when the device has to be opened again after a reset
+    selector: DeviceSelector,
+    detach_kernel_driver: bool,
+    usb_identity: UsbIdentity,
+
+    // when true, frames are collected with poll() instead of a receive thread
+    polling: bool,
+    // how long recv() waits for a frame, or forever when None
+    default_rx_timeout: Option<time::Duration>,
+    rx: Option<RxPipeline>,
+    // host frames received in a batch but not yet returned by poll_into()
+    rx_pending: VecDeque<HostFrame>,
+    rx_config: Arc<RwLock<RxConfig>>,
+    rx_taps: Arc<Mutex<Vec<Tap>>>,
+    // channels started with bus error reporting
+    berr_reporting: Vec<bool>,
+    // writes received frames to a log file
+    logger: Option<FrameLogger>,
+
+    // when set, receive transfers are restarted if none completes in time
+    rx_watchdog: Option<time::Duration>,
+    // incremented whenever transfers are started or stopped, ending the
+    // watchdog of the previous session
+    session: Arc<AtomicU64>,
+    // watchdog thread of the current or last session
+    watchdog: Option<thread::JoinHandle<()>>,
+    // set by the device when it is gone, and the callback to run then
+    disconnected: Arc<AtomicBool>,
+    on_disconnect: Arc<Mutex<Option<DisconnectCallback>>>,
+    // when set, the receive thread holds frames back from the callback
+    rx_paused: Arc<AtomicBool>,
+
+    // time the device was last started, used for timestamps and scheduling
+    start_time: time::Instant,
+    scheduler: Option<Scheduler>,
+    // frames sent with send_queued, and their pending confirmations
+    tx_queue: Option<TxQueue>,
+    completions: Arc<Mutex<Completions>>,
+    // transmit settings, window and buffer shared with FrameSender handles
+    tx: TxPath,
+
+    channels: Vec<Channel>,
+    // last nominal and data bit timings set on each channel, with the
+    // bitrate they were calculated for
+    bit_timings: Vec<Option<(BitTiming, Option<u32>)>>,
+    data_bit_timings: Vec<Option<(BitTiming, Option<u32>)>>,
+    // channels temporarily switched to listen only by pause()
+    paused: Vec<bool>,
+    // channel used by the single channel convenience methods
+    default_channel: usize,
+}
+
+impl fmt::Debug for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Interface")
+            .field("running", &(*self.running.read().unwrap()))
+            .field("can_clock", &self.can_clock)
+            .field("num_channels", &self.num_channels)
+            .field("sw_version", &self.sw_version)
+            .field("hw_version", &self.hw_version)
+            .field("channels", &self.channels)
+            .finish()
+    }
+}
+
+// ends the session, so the receive thread and the watchdog exit and the
+// device stops delivering frames nobody will read
+impl Drop for Interface {
+    fn drop(&mut self) {
+        self.session.fetch_add(1, Ordering::SeqCst);
+        let running = std::mem::replace(&mut *self.running.write().unwrap(), false);
+        if running {
+            if let Ok(mut dev) = self.dev.lock() {
+                let _ = dev.stop_transfers();
+            }
+        }
+        if let Some(w) = self.watchdog.take() {
+            let _ = w.join();
+        }
+    }
+}
+
+impl Interface {
+    /// Creates a new interface. This always selects the first device found by
+    /// libusb. If no device is found, Error::DeviceNotFound is returned. If
+    /// a device is found but cannot be opened for lack of permissions,
+    /// Error::PermissionDenied is returned.
+    ///
+    /// Any kernel driver bound to the device (such as the Linux gs_usb
+    /// driver) is detached. See `Interface::new_with_options`.
+    pub fn new() -> Result<Interface, Error> {
+        Interface::new_with_options(true)
+    }
+
+    /// Creates a new interface, choosing whether a kernel driver bound to the
+    /// device is detached.
+    ///
+    /// The USB interface is always claimed exclusively: only one process can
+    /// use a device at a time. If another process has the device open, or a
+    /// kernel driver holds it and `detach_kernel_driver` is false,
+    /// `Error::DeviceBusy` is returned rather than sharing the device and
+    /// splitting received frames between users unpredictably. Keeping the
+    /// kernel driver attached is useful when the device should stay
+    /// available as a SocketCAN interface unless it is free.
+    pub fn new_with_options(detach_kernel_driver: bool) -> Result<Interface, Error> {
+        Interface::open(DeviceSelector::First, detach_kernel_driver)
+    }
+
+    // open the selected device
+    pub(crate) fn open(
+        selector: DeviceSelector,
+        detach_kernel_driver: bool,
+    ) -> Result<Interface, Error> {
+        let mut dev = open_device(&selector, detach_kernel_driver)?;
+
+        let dev_config = dev.get_device_config()?;
+        let bt_consts = dev.get_bit_timing_consts()?;
+        let data_bit_timing_limits = read_data_bit_timing_limits(&mut dev, &bt_consts)?;
+
+        // icount is the highest channel index, so 0 means one channel
+        let num_channels = dev_config.icount as usize + 1;
+
+        let mut channels = Vec::new();
+        for _ in 0..num_channels {
+            channels.push(default_channel());
+        }
+
+        let can_rx = dev.can_rx_recv.clone();
+        let disconnected = Arc::clone(&dev.disconnected);
+        let usb_identity = dev.usb_identity();
+        let dev = Arc::new(Mutex::new(dev));
+        let running = Arc::new(RwLock::from(false));
+        let on_disconnect = Arc::new(Mutex::new(None));
+        let tx = TxPath::new(
+            Arc::clone(&dev),
+            Arc::clone(&running),
+            Arc::clone(&on_disconnect),
+            num_channels,
+        );
+        let i = Interface {
+            dev,
+            can_rx,
+            running,
+
+            num_channels,
+            can_clock: bt_consts.fclk_can,
+            sw_version: dev_config.sw_version,
+            hw_version: dev_config.hw_version,
+            features: bt_consts.feature,
+            bit_timing_limits: bt_consts.limits(),
+            data_bit_timing_limits,
+            timestamp_frequency: if (bt_consts.feature & GS_CAN_FEATURE_HW_TIMESTAMP) > 0 {
+                Some(GSUSB_TIMESTAMP_FREQ_HZ)
+            } else {
+                None
+            },
+
+            polling: false,
+            default_rx_timeout: None,
+            rx: None,
+            rx_pending: VecDeque::new(),
+            rx_config: Arc::new(RwLock::new(RxConfig::new(num_channels))),
+            rx_taps: Arc::new(Mutex::new(Vec::new())),
+            berr_reporting: vec![false; num_channels],
+            logger: None,
+
+            rx_watchdog: None,
+            session: Arc::new(AtomicU64::new(0)),
+            watchdog: None,
+            disconnected,
+            on_disconnect,
+            rx_paused: Arc::new(AtomicBool::new(false)),
+
+            start_time: time::Instant::now(),
+            scheduler: None,
+            tx_queue: None,
+            completions: Arc::new(Mutex::new(Completions::new())),
+            tx,
+            selector,
+            detach_kernel_driver,
+            usb_identity,
+
+            channels,
+            bit_timings: vec![None; num_channels],
+            data_bit_timings: vec![None; num_channels],
+            paused: vec![false; num_channels],
+            default_channel: 0,
+        };
+
+        Ok(i)
+    }
+
+    /// Perform a USB port reset of the device, then re-read the device
+    /// configuration and bit timing constants.
+    ///
+    /// This is intended to recover a device that has stopped responding
+    /// without physically reconnecting it. The reset invalidates the current
+    /// session: all channels are stopped, the receive thread exits, and the
+    /// device loses any bit timing or mode set before the reset. Channel
+    /// settings stored in the `Interface` are kept, so the bitrates must be
+    /// set again before calling `start`. Once the reset succeeds,
+    /// `Interface.is_disconnected` returns false again.
+    pub fn reset_device(&mut self) -> Result<(), Error> {
+        *self.running.write().unwrap() = false;
+        self.session.fetch_add(1, Ordering::SeqCst);
+        self.tx.window.reset();
+        for ch in self.channels.iter_mut() {
+            ch.started = false;
+        }
+        self.tx.config.lock().unwrap().started.fill(false);
+
+        let result = self.dev().reset();
+        match result {
+            Ok(_) => {}
+            Err(device::Error::DeviceNotFound) => {
+                // device re-enumerated, the handle must be opened again
+                let mut dev = open_device(&self.selector, self.detach_kernel_driver)?;
+                let policy = self.dev().retry_policy();
+                dev.set_retry_policy(policy);
+                self.can_rx = dev.can_rx_recv.clone();
+                self.disconnected = Arc::clone(&dev.disconnected);
+                self.usb_identity = dev.usb_identity();
+                *self.dev() = dev;
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        let dev_config = self.dev().get_device_config()?;
+        let bt_consts = self.dev().get_bit_timing_consts()?;
+        if dev_config.icount as usize + 1 != self.num_channels {
+            return Err(Error::InvalidChannel);
+        }
+        self.can_clock = bt_consts.fclk_can;
+        self.sw_version = dev_config.sw_version;
+        self.hw_version = dev_config.hw_version;
+        self.features = bt_consts.feature;
+        self.bit_timing_limits = bt_consts.limits();
+        let data_bit_timing_limits = read_data_bit_timing_limits(&mut self.dev(), &bt_consts)?;
+        self.data_bit_timing_limits = data_bit_timing_limits;
+        // the device answered again, so it is no longer considered lost
+        self.disconnected.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Start CAN communication on all configured channels.
+    ///
+    /// After starting the device, `Interface.send` can be used to send frames.
+    /// For every received frame, the `rx_callback` closure will be called.
+    ///
+    /// Frames are passed to the callback in the order the device reports
+    /// them. Transmit confirmations are reported once the frame has been
+    /// transmitted on the bus, so they are ordered with received frames by
+    /// bus time, and with hardware timestamps their timestamps are in order
+    /// with those of received frames.
+    pub fn start(
+        &mut self,
+        channel: usize,
+        mut rx_callback: impl FnMut(Frame) + Sync + Send + 'static,
+    ) -> Result<(), Error> {
+        self.start_channel(channel)?;
+
+        if !(*self.running.read().unwrap()) {
+
+            *self.running.write().unwrap() = true;
+            self.polling = false;
+            self.tx.config.lock().unwrap().polling = false;
+            self.start_time = time::Instant::now();
+
+            let can_rx = self.can_rx.clone();
+            let running = Arc::clone(&self.running);
+            let disconnected = Arc::clone(&self.disconnected);
+            let on_disconnect = Arc::clone(&self.on_disconnect);
+            let rx_paused = Arc::clone(&self.rx_paused);
+            let mut rx = self.rx_pipeline();
+            thread::spawn(move || {
+                let mut held = VecDeque::new();
+                while *running.read().unwrap() {
+                    match can_rx.recv() {
+                        Ok(batch) => {
+                            let frames = batch.into_iter().filter_map(|hf| rx.process(hf));
+                            deliver(
+                                &mut held,
+                                rx_paused.load(Ordering::SeqCst),
+                                frames,
+                                &mut rx_callback,
+                            );
+                            if disconnected.load(Ordering::SeqCst) {
+                                close_disconnected(&running, &on_disconnect);
+                                break;
+                            }
+                        }
+                        Err(RecvError) => {
+                            // channel disconnected
+                            break;
+                        }
+                    }
+                }
+            });
+            if let Err(e) = self.dev().start_transfers() {
+                *self.running.write().unwrap() = false;
+                return Err(e.into());
+            }
+            self.start_watchdog();
+        }
+        self.flush_tx_before_start()
+    }
+
+    /// Start CAN communication on several channels in order, waiting before
+    /// each one.
+    ///
+    /// Each entry of `sequence` is a channel and the delay before it is
+    /// started, so channels can be brought onto a power sensitive bus one at
+    /// a time rather than all at once. The receive thread and `rx_callback`
+    /// are set up with the first channel, as by `Interface.start`. All
+    /// channels are checked before any is started. If starting a channel
+    /// fails, the error is returned and the channels already started are
+    /// left running.
+    pub fn start_sequence(
+        &mut self,
+        sequence: &[(usize, time::Duration)],
+        rx_callback: impl FnMut(Frame) + Sync + Send + 'static,
+    ) -> Result<(), Error> {
+        for (n, (channel, _)) in sequence.iter().enumerate() {
+            self.check_channel(*channel)?;
+            if sequence[..n].iter().any(|(c, _)| c == channel) {
+                return Err(Error::InvalidArgument("channel appears twice in the sequence"));
+            }
+        }
+        let (first, rest) = match sequence.split_first() {
+            Some(s) => s,
+            None => return Err(Error::InvalidArgument("empty start sequence")),
+        };
+
+        thread::sleep(first.1);
+        self.start(first.0, rx_callback)?;
+        for (channel, delay) in rest {
+            thread::sleep(*delay);
+            self.start_channel(*channel)?;
+            self.flush_tx_before_start()?;
+        }
+        Ok(())
+    }
+
+    /// Start CAN communication without spawning a receive thread.
+    ///
+    /// Received frames are queued until they are collected by calling
+    /// `Interface.poll` from the application's own loop. This gives the
+    /// application full control of when frames are processed. USB events are
+    /// still handled by the driver's internal libusb event thread.
+    pub fn start_polled(&mut self, channel: usize) -> Result<(), Error> {
+        self.start_channel(channel)?;
+
+        if !(*self.running.read().unwrap()) {
+            *self.running.write().unwrap() = true;
+            self.polling = true;
+            self.tx.config.lock().unwrap().polling = true;
+            self.start_time = time::Instant::now();
+            self.rx = Some(self.rx_pipeline());
+            self.rx_pending.clear();
+            if let Err(e) = self.dev().start_transfers() {
+                *self.running.write().unwrap() = false;
+                return Err(e.into());
+            }
+            self.start_watchdog();
+        }
+        self.flush_tx_before_start()
+    }
+
+    /// Choose what `Interface.send` does while the interface is stopped. By
+    /// default it returns `Error::NotRunning`. When buffering is enabled,
+    /// frames are kept instead, along with frames sent while running to a
+    /// channel that is not started. Each frame is sent, in order, once its
+    /// channel is started by `Interface.start`, `Interface.start_polled` or
+    /// `Interface.start_sequence`; frames for other channels stay buffered.
+    ///
+    /// If sending a buffered frame fails, start returns the error and the
+    /// other frames buffered for the channels just started are dropped.
+    /// Disabling buffering drops any frames buffered so far.
+    pub fn set_buffer_before_start(&mut self, enabled: bool) {
+        let buffer = &mut self.tx.config.lock().unwrap().buffer;
+        if !enabled {
+            *buffer = None;
+        } else if buffer.is_none() {
+            *buffer = Some(Vec::new());
+        }
+    }
+
+    // send the buffered frames whose channel is started, keeping the rest
+    fn flush_tx_before_start(&self) -> Result<(), Error> {
+        let started: Vec<bool> = self.channels.iter().map(|ch| ch.started).collect();
+        let frames = match &mut self.tx.config.lock().unwrap().buffer {
+            Some(buffer) => take_started(buffer, &started),
+            None => return Ok(()),
+        };
+        for f in frames {
+            self.send(f)?;
+        }
+        Ok(())
+    }
+
+    /// Stop passing received frames to the receive callback, without
+    /// changing the device mode or stopping the USB transfers.
+    ///
+    /// Frames received while paused are held on the host and passed to the
+    /// callback, in order, by `Interface.resume_rx`, so none are lost; they
+    /// use memory until then. Receivers such as `Interface.channel_receiver`
+    /// and transmit confirmations are not affected. This has no effect on
+    /// `Interface.poll`.
+    pub fn pause_rx(&mut self) {
+        self.rx_paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Pass the frames held since `Interface.pause_rx` to the receive
+    /// callback, and resume passing received frames to it.
+    pub fn resume_rx(&mut self) {
+        if self.rx_paused.swap(false, Ordering::SeqCst) {
+            // the held frames are delivered on the next batch
+            self.dev().wake_receiver();
+        }
+    }
+
+    /// Set a callback run once when the device is disconnected or fails
+    /// while running.
+    ///
+    /// When the device is lost, the interface stops receiving, and
+    /// `Interface.send`, `Interface.start` and `Interface.poll` return
+    /// `Error::Disconnected` until the device is recovered with
+    /// `Interface.reset_device`. The callback runs on the
+    /// receive thread, or on the thread calling `Interface.poll` or
+    /// `Interface.send` when no receive thread is running.
+    pub fn on_disconnect(&mut self, callback: impl FnMut() + Send + 'static) {
+        *self.on_disconnect.lock().unwrap() = Some(Box::new(callback));
+    }
+
+    /// Returns true if the device was disconnected or failed while running.
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::SeqCst)
+    }
+
+    // stop the interface and return Error::Disconnected if the device is gone
+    fn check_disconnected(&self) -> Result<(), Error> {
+        if !self.disconnected.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        close_disconnected(&self.running, &self.on_disconnect);
+        Err(Error::Disconnected)
+    }
+
+    /// Restart the receive transfers if no frame is received for `timeout`.
+    /// Disabled by default, and disabled again with `None`.
+    ///
+    /// This recovers from USB or firmware glitches that silently stall
+    /// reception. A transmit confirmation counts as a received frame. Only
+    /// enable the watchdog on buses that are expected to carry traffic,
+    /// since on an idle bus the transfers are restarted every `timeout`.
+    /// Each restart is logged as a warning. Takes effect the next time the
+    /// device is started.
+    pub fn set_rx_watchdog(&mut self, timeout: Option<time::Duration>) {
+        self.rx_watchdog = timeout;
+    }
+
+    // start the watchdog for the session that was just started
+    fn start_watchdog(&mut self) {
+        // the previous session has ended, so its watchdog exits shortly
+        if let Some(w) = self.watchdog.take() {
+            let _ = w.join();
+        }
+        let timeout = match self.rx_watchdog {
+            Some(t) => t,
+            None => return,
+        };
+        // a weak reference, so the watchdog does not keep the device open
+        let dev: Weak<Mutex<Device>> = Arc::downgrade(&self.dev);
+        let running = Arc::clone(&self.running);
+        let session = Arc::clone(&self.session);
+        let id = self.session.load(Ordering::SeqCst);
+        let activity = Arc::clone(&self.dev().rx_activity);
+
+        self.watchdog = Some(thread::spawn(move || {
+            let mut watchdog =
+                RxWatchdog::new(timeout, activity.load(Ordering::SeqCst), time::Instant::now());
+            let interval = std::cmp::min(timeout / 4, time::Duration::from_millis(100));
+            loop {
+                thread::sleep(interval);
+                if session.load(Ordering::SeqCst) != id {
+                    break;
+                }
+                if !watchdog.expired(activity.load(Ordering::SeqCst), time::Instant::now()) {
+                    continue;
+                }
+                // the session is checked with the device locked, so the
+                // transfers are not restarted after stop() cancels them
+                let dev = match dev.upgrade() {
+                    Some(dev) => dev,
+                    None => break,
+                };
+                let mut dev = dev.lock().unwrap();
+                if !*running.read().unwrap() || session.load(Ordering::SeqCst) != id {
+                    break;
+                }
+                warn!(
+                    "no frames received for {:?}, restarting receive transfers",
+                    timeout
+                );
+                if let Err(e) = dev.restart_transfers() {
+                    warn!("failed to restart receive transfers: {:?}", e);
+                }
+            }
+        }));
+    }
+
+    /// Collect received frames when the device was started with
+    /// `Interface.start_polled`.
+    ///
+    /// Waits up to `timeout` for a frame to arrive, then returns all frames
+    /// that are available without waiting further. An empty vector is
+    /// returned if no frame arrived before the timeout.
+    pub fn poll(&mut self, timeout: time::Duration) -> Result<Vec<Frame>, Error> {
+        self.check_disconnected()?;
+        if !*self.running.read().unwrap() {
+            return Err(Error::NotRunning);
+        }
+        if !self.polling {
+            // frames are being consumed by the receive thread
+            return Err(Error::Running);
+        }
+        let rx = self.rx.as_mut().ok_or(Error::NotRunning)?;
+
+        // frames left over from poll_into() are returned first, without waiting
+        let mut frames: Vec<Frame> = self
+            .rx_pending
+            .drain(..)
+            .filter_map(|hf| rx.process(hf))
+            .collect();
+        if frames.is_empty() {
+            match self.can_rx.recv_timeout(timeout) {
+                Ok(batch) => frames.extend(batch.into_iter().filter_map(|hf| rx.process(hf))),
+                Err(RecvTimeoutError::Timeout) => return Ok(frames),
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::NotRunning),
+            }
+        }
+        while let Ok(batch) = self.can_rx.try_recv() {
+            frames.extend(batch.into_iter().filter_map(|hf| rx.process(hf)));
+        }
+        Ok(frames)
+    }
+
+    /// Receive a single frame into `frame` when the device was started with
+    /// `Interface.start_polled`.
+    ///
+    /// Unlike `Interface.poll`, this does not allocate for each received
+    /// frame: the data buffer of `frame` is reused, so the same `Frame` can be
+    /// passed on every call, and a transfer holding a single frame reaches
+    /// the receive path without allocating. A transfer packing several frames
+    /// allocates once for the frames after the first. Waits up to `timeout`
+    /// for a frame to arrive and returns false if none arrived.
+    pub fn poll_into(
+        &mut self,
+        timeout: time::Duration,
+        frame: &mut Frame,
+    ) -> Result<bool, Error> {
+        self.check_disconnected()?;
+        if !*self.running.read().unwrap() {
+            return Err(Error::NotRunning);
+        }
+        if !self.polling {
+            // frames are being consumed by the receive thread
+            return Err(Error::Running);
+        }
+        let rx = self.rx.as_mut().ok_or(Error::NotRunning)?;
+
+        let deadline = time::Instant::now() + timeout;
+        loop {
+            while let Some(hf) = self.rx_pending.pop_front() {
+                if rx.process_into(&hf, frame) {
+                    return Ok(true);
+                }
+            }
+            let remaining = deadline.saturating_duration_since(time::Instant::now());
+            match self.can_rx.recv_timeout(remaining) {
+                Ok(batch) => self.rx_pending.extend(batch),
+                Err(RecvTimeoutError::Timeout) => return Ok(false),
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::NotRunning),
+            }
+        }
+    }
+
+    /// Set how long `Interface.recv` waits for a frame before returning
+    /// `Error::Timeout`. `None`, the default, waits forever.
+    pub fn set_default_rx_timeout(&mut self, timeout: Option<time::Duration>) {
+        self.default_rx_timeout = timeout;
+    }
+
+    /// Receive a single frame when the device was started with
+    /// `Interface.start_polled`, waiting up to the timeout set with
+    /// `Interface.set_default_rx_timeout`.
+    ///
+    /// Returns `Error::Timeout` if no frame arrives in time, so a control
+    /// loop is not blocked by a quiet bus.
+    pub fn recv(&mut self) -> Result<Frame, Error> {
+        let mut f = Frame::default();
+        match self.default_rx_timeout {
+            Some(timeout) => match self.poll_into(timeout, &mut f)? {
+                true => Ok(f),
+                false => Err(Error::Timeout),
+            },
+            // wait in steps, so stopping or losing the device is noticed
+            None => loop {
+                if self.poll_into(time::Duration::from_secs(1), &mut f)? {
+                    return Ok(f);
+                }
+            },
+        }
+    }
+
+    // configure the device mode for a channel and mark it as started
+    fn start_channel(&mut self, channel: usize) -> Result<(), Error> {
+        self.check_disconnected()?;
+        self.check_channel(channel)?;
+        self.channels[channel].check_modes()?;
+
+        // tell the device to go on bus
+        let mut flags = 0;
+        // for each mode flag, check that the feature is supported before applying feature
+        // this is necessary since the feature flags are pub
+        if self.channels[channel].monitor || self.paused[channel] {
+            if (self.features & GS_CAN_FEATURE_LISTEN_ONLY) == 0 {
+                return Err(Error::UnsupportedFeature("Monitor"));
+            }
+            flags |= GS_CAN_MODE_LISTEN_ONLY;
+        }
+        if self.channels[channel].loopback {
+            if (self.features & GS_CAN_FEATURE_LOOP_BACK) == 0 {
+                return Err(Error::UnsupportedFeature("Loopback"));
+            }
+            flags |= GS_CAN_MODE_LOOP_BACK;
+        }
+        if self.channels[channel].fd {
+            if !self.supports_fd() {
+                return Err(Error::UnsupportedFeature("FD"));
+            }
+            flags |= GS_CAN_MODE_FD;
+        }
+        if self.timestamp_frequency.is_some() {
+            flags |= GS_CAN_MODE_HW_TIMESTAMP;
+        }
+        if self.berr_reporting[channel] {
+            flags |= GS_CAN_MODE_BERR_REPORTING;
+        }
+        self.dev().set_hw_timestamps(self.timestamp_frequency.is_some());
+
+        let mode = Mode {
+            mode: CanMode::Start as u32,
+            flags,
+        };
+
+        if self.channels[channel].enabled {
+            self.dev().set_mode(channel as u16, mode)?;
+        }
+
+        if !(self.channels[channel].started) {
+            self.channels[channel].started = true;
+            self.tx.config.lock().unwrap().started[channel] = true;
+        }
+        self.rx_config.write().unwrap().loopback[channel] = self.channels[channel].loopback;
+        Ok(())
+    }
+
+    // apply a mode change to a started channel by resetting it and starting
+    // it again with the new mode flags. gs_usb has no request to change the
+    // mode of a started channel.
+    fn apply_mode(&mut self, channel: usize) -> Result<(), Error> {
+        if !*self.running.read().unwrap()
+            || !self.channels[channel].started
+            || !self.channels[channel].enabled
+        {
+            // applied on the next start
+            return Ok(());
+        }
+        let reset = Mode {
+            mode: CanMode::Reset as u32,
+            flags: 0,
+        };
+        self.dev().set_mode(channel as u16, reset)?;
+        self.start_channel(channel)
+    }
+
+    fn check_channel(&self, channel: usize) -> Result<(), Error> {
+        check_channel(channel, self.num_channels)
+    }
+
+    fn dev(&self) -> MutexGuard<'_, Device> {
+        self.dev.lock().unwrap()
+    }
+
+    fn rx_pipeline(&self) -> RxPipeline {
+        RxPipeline::new(
+            self.start_time,
+            self.timestamp_frequency,
+            Arc::clone(&self.rx_config),
+            Arc::clone(&self.rx_taps),
+        )
+        .with_completions(Arc::clone(&self.completions))
+        .with_tx_window(Arc::clone(&self.tx.window))
+    }
+
+    // returns a receiver that gets a copy of every received frame matching
+    // the filter. the tap is removed once the receiver is dropped.
+    pub(crate) fn subscribe(
+        &self,
+        filter: impl Fn(&Frame) -> bool + Send + Sync + 'static,
+    ) -> Receiver<Frame> {
+        let (send, recv) = unbounded();
+        self.rx_taps.lock().unwrap().push(Tap::new(filter, send));
+        recv
+    }
+
+    /// Write every received frame, including transmit confirmations, to a
+    /// log file in `format`, replacing any log already enabled.
+    ///
+    /// The file is written by a background thread fed independently of the
+    /// receive callback, so frames are logged whatever the callback does
+    /// with them. It is created or truncated at once, flushed at least every
+    /// second and when the interface is stopped, and rotated as set by
+    /// `rotation`. Write errors are logged as warnings. When the device was
+    /// started with `Interface.start_polled`, frames are only logged while
+    /// `Interface.poll` is being called.
+    pub fn enable_logging(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+        format: LogFormat,
+        rotation: LogRotation,
+    ) -> std::io::Result<()> {
+        // the previous log is flushed and closed first
+        self.logger = None;
+        let frames = self.subscribe(|_| true);
+        self.logger = Some(FrameLogger::new(frames, path.as_ref(), format, rotation)?);
+        Ok(())
+    }
+
+    /// Stop logging started with `Interface.enable_logging`, flushing and
+    /// closing the log file.
+    pub fn disable_logging(&mut self) {
+        self.logger = None;
+    }
+
+    /// Returns a receiver that gets every frame received on `channel`.
+    ///
+    /// Frames are delivered from the time of this call, in addition to being
+    /// passed to the receive callback, so independent consumers can each
+    /// handle one channel. Dropping the receiver stops delivery. When the
+    /// device was started with `Interface.start_polled`, frames are only
+    /// delivered while `Interface.poll` is being called.
+    pub fn channel_receiver(&self, channel: usize) -> Result<Receiver<Frame>, Error> {
+        self.check_channel(channel)?;
+        Ok(self.subscribe(move |f| f.channel as usize == channel))
+    }
+
+    /// Returns a receiver that gets the bus error conditions reported by
+    /// error frames on all channels, decoded with `BusError::from_frame`.
+    /// Data frames are not delivered to it.
+    ///
+    /// Conditions are delivered from the time of this call. If the device
+    /// supports bus error reporting, it is enabled on every channel that is
+    /// not started, taking effect when the channel starts, so protocol
+    /// errors such as missing ACKs are reported as well as bus state
+    /// changes. Started channels are left as they are rather than restarted,
+    /// so traffic is not interrupted; use `Interface.set_error_reporting` to
+    /// enable reporting on them.
+    pub fn error_stream(&mut self) -> Receiver<BusError> {
+        if (self.features & GS_CAN_FEATURE_BERR_REPORTING) != 0 {
+            for (enabled, ch) in self.berr_reporting.iter_mut().zip(self.channels.iter()) {
+                if !ch.started {
+                    *enabled = true;
+                }
+            }
+        }
+        let (send, recv) = unbounded();
+        self.rx_taps
+            .lock()
+            .unwrap()
+            .push(Tap::map(BusError::from_frame, send));
+        recv
+    }
+
+    /// Wait for a received frame matching `predicate`, returning the first
+    /// matching frame or `Error::Timeout` if none arrives within `timeout`.
+    ///
+    /// Only frames received from the bus after this call are considered;
+    /// transmit echoes and loopback frames, including those of frames sent by
+    /// the caller, are not. All received frames, matching or not, are still
+    /// passed to the receive callback. When the device was started with
+    /// `Interface.start_polled`, frames are only seen while `Interface.poll`
+    /// is being called.
+    pub fn wait_for(
+        &self,
+        predicate: impl Fn(&Frame) -> bool,
+        timeout: time::Duration,
+    ) -> Result<Frame, Error> {
+        if !*self.running.read().unwrap() {
+            return Err(Error::NotRunning);
+        }
+
+        let rx = self.subscribe(|f| f.origin == FrameOrigin::Received);
+        wait_on(&rx, &predicate, timeout)
+    }
+
+    /// Send a request and wait for a response matching `match_response`,
+    /// retrying on timeout.
+    ///
+    /// The request is sent, and the first frame received afterwards for
+    /// which `match_response` returns true is returned. If none arrives
+    /// within `timeout`, the request is sent again, up to `retries` more
+    /// times, before `Error::Timeout` is returned. A late response to an
+    /// earlier attempt also completes the transaction. As with
+    /// `Interface.wait_for`, only frames received from the bus are matched,
+    /// so the echo of the request never completes it. Send errors are
+    /// returned immediately.
+    ///
+    /// Returns `Error::Running` when the device was started with
+    /// `Interface.start_polled`, since responses are only received while
+    /// `Interface.poll` is being called.
+    pub fn transaction(
+        &self,
+        request: Frame,
+        match_response: impl Fn(&Frame) -> bool,
+        timeout: time::Duration,
+        retries: u32,
+    ) -> Result<Frame, Error> {
+        if !*self.running.read().unwrap() {
+            return Err(Error::NotRunning);
+        }
+        if self.polling {
+            return Err(Error::Running);
+        }
+
+        // subscribe before sending, so a fast response is not missed
+        let rx = self.subscribe(|f| f.origin == FrameOrigin::Received);
+        for _ in 0..=retries {
+            self.send(request.clone())?;
+            match wait_on(&rx, &match_response, timeout) {
+                Err(Error::Timeout) => {}
+                result => return result,
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Measure the round trip latency of transmissions on a channel.
+    ///
+    /// Sends `count` frames with ID 0x7FF and `payload_len` data bytes, one at
+    /// a time, and times each from submission until its transmit confirmation
+    /// is received. A frame is only confirmed once it has been acknowledged,
+    /// so the channel must be in loopback mode or on a bus with another node.
+    /// Frames not confirmed within 100 ms are counted as lost.
+    ///
+    /// The first data bytes hold a sequence number, so a late confirmation is
+    /// not mistaken for the confirmation of a later frame. Payloads longer
+    /// than 8 bytes are sent as CAN-FD frames and need CAN-FD enabled on the
+    /// channel. When the device was started with `Interface.start_polled`,
+    /// frames received during the measurement are consumed by it.
+    pub fn measure_latency(
+        &mut self,
+        channel: usize,
+        count: usize,
+        payload_len: usize,
+    ) -> Result<LatencyStats, Error> {
+        const LATENCY_ID: u32 = 0x7FF;
+        const LATENCY_TIMEOUT: time::Duration = time::Duration::from_millis(100);
+
+        self.check_channel(channel)?;
+        if payload_len > 64 || (payload_len > 8 && !self.channels[channel].fd) {
+            return Err(Error::InvalidArgument("payload too long for the channel"));
+        }
+        if !*self.running.read().unwrap() {
+            return Err(Error::NotRunning);
+        }
+
+        let rx = self.tx_confirmations(channel)?;
+        let mut samples = Vec::with_capacity(count);
+        let mut lost = 0;
+        for seq in 0..count {
+            let mut data = vec![0u8; payload_len];
+            let n = std::cmp::min(payload_len, 4);
+            data[..n].copy_from_slice(&(seq as u32).to_le_bytes()[..n]);
+            let mut f = if payload_len > 8 {
+                Frame::new_fd(LATENCY_ID, &data, false)?
+            } else {
+                Frame::new_standard(LATENCY_ID as u16, &data)?
+            };
+            f.channel = channel as u8;
+
+            // discard confirmations of earlier frames that arrived late
+            while rx.try_recv().is_ok() {}
+
+            let sent = time::Instant::now();
+            self.send(f)?;
+            let deadline = sent + LATENCY_TIMEOUT;
+            let confirmed = loop {
+                let remaining = deadline.saturating_duration_since(time::Instant::now());
+                if self.polling {
+                    // frames only reach the receiver while polling
+                    self.poll(remaining)?;
+                }
+                let wait = if self.polling {
+                    time::Duration::ZERO
+                } else {
+                    remaining
+                };
+                match rx.recv_timeout(wait) {
+                    Ok(e) if e.can_id == LATENCY_ID && e.data.starts_with(&data) => {
+                        break Some(time::Instant::now() - sent)
+                    }
+                    Ok(_) => {}
+                    Err(RecvTimeoutError::Timeout) if remaining.is_zero() => break None,
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return Err(Error::NotRunning),
+                }
+            };
+            match confirmed {
+                Some(rtt) => samples.push(rtt),
+                None => lost += 1,
+            }
+        }
+        Ok(LatencyStats::from_samples(samples, lost))
+    }
+
+    /// Stop CAN communication on all channels.
+    pub fn stop(&mut self, channel: usize) -> Result<(), Error> {
+        // TODO multi-channel
+        self.check_channel(channel)?;
+
+        if self.channels[channel].enabled {
+            let mode = Mode {
+                mode: CanMode::Reset as u32,
+                flags: 0,
+            };
+            self.dev().set_mode(channel as u16, mode)?;
+            self.channels[channel].started = false;
+            self.tx.config.lock().unwrap().started[channel] = false;
+        }
+        self.paused[channel] = false;
+
+        for ch in self.channels.iter() {
+            if ch.started {
+               return Ok(());
+            }
+        }
+
+        self.session.fetch_add(1, Ordering::SeqCst);
+        self.dev().stop_transfers()?;
+        *self.running.write().unwrap() = false;
+        self.tx.window.reset();
+        if let Some(logger) = &self.logger {
+            logger.flush();
+        }
+
+        Ok(())
+    }
+
+    /// Pause transmission on a started channel by switching it to listen only
+    /// mode, without taking it off the bus as `Interface.stop` does.
+    ///
+    /// The channel keeps receiving frames, but no longer transmits frames,
+    /// acknowledgements or error frames; frames sent on it are not
+    /// transmitted. The channel is briefly reset to change mode, as with
+    /// `Interface.set_monitor`. Use `Interface.resume` to return to the
+    /// channel's configured mode. Channels in loopback mode cannot be paused.
+    pub fn pause(&mut self, channel: usize) -> Result<(), Error> {
+        if self.features & GS_CAN_FEATURE_LISTEN_ONLY == 0 {
+            return Err(Error::UnsupportedFeature("Monitor"));
+        }
+        self.check_channel(channel)?;
+        if !*self.running.read().unwrap() || !self.channels[channel].started {
+            return Err(Error::NotRunning);
+        }
+        if self.channels[channel].loopback {
+            return Err(Error::InvalidArgument(
+                "channels in loopback mode cannot be paused",
+            ));
+        }
+        if self.paused[channel] {
+            return Ok(());
+        }
+
+        self.paused[channel] = true;
+        self.apply_mode(channel).inspect_err(|_| {
+            self.paused[channel] = false;
+        })
+    }
+
+    /// Resume a channel paused with `Interface.pause`, returning it to its
+    /// configured mode. Does nothing if the channel is not paused.
+    pub fn resume(&mut self, channel: usize) -> Result<(), Error> {
+        self.check_channel(channel)?;
+        if !self.paused[channel] {
+            return Ok(());
+        }
+
+        self.paused[channel] = false;
+        self.apply_mode(channel).inspect_err(|_| {
+            self.paused[channel] = true;
+        })
+    }
+
+    /// Returns true if the channel is paused by `Interface.pause`.
+    pub fn is_paused(&self, channel: usize) -> Result<bool, Error> {
+        self.check_channel(channel)?;
+        Ok(self.paused[channel])
+    }
+
+    /// Set bitrate for specified channel to requested bitrate value in bits per second.
+    pub fn set_bitrate(&mut self, channel: usize, bitrate: u32) -> Result<(), Error> {
+        self.set_bitrate_with_options(channel, bitrate, BitTimingOptions::default())
+    }
+
+    /// Set bitrate for specified channel, overriding the SJW and propagation
+    /// segment used by the bit timing calculation.
+    ///
+    /// Returns `Error::InvalidArgument` if the SJW is zero, or
+    /// `Error::InvalidBitrate` if no timing satisfying SJW <= min(seg1, seg2)
+    /// can be found for the requested bitrate.
+    pub fn set_bitrate_with_options(
+        &mut self,
+        channel: usize,
+        bitrate: u32,
+        options: BitTimingOptions,
+    ) -> Result<(), Error> {
+        self.check_channel(channel)?;
+
+        let bt = calculate_bit_timing_with_options(self.can_clock, bitrate, options)?;
+        self.dev().set_bit_timing(channel as u16, bt)?;
+        self.bit_timings[channel] = Some((bt, Some(bitrate)));
+
+        self.channels[channel].bitrate = bitrate;
+        Ok(())
+    }
+
+    /// Detect the bitrate of a bus by listening at each candidate bitrate.
+    ///
+    /// For each candidate, the channel is started in listen only mode for
+    /// `per_rate`, and the number of valid frames and error frames received
+    /// is counted. The candidate that received the most valid frames without
+    /// any error frames is returned, or `None` if no candidate received a
+    /// valid frame without errors.
+    ///
+    /// The device must not be running. The channel's monitor setting and
+    /// bit timing, including a custom one, are restored before returning.
+    pub fn detect_bitrate(
+        &mut self,
+        channel: usize,
+        candidates: &[u32],
+        per_rate: time::Duration,
+    ) -> Result<Option<u32>, Error> {
+        if self.features & GS_CAN_FEATURE_LISTEN_ONLY == 0 {
+            return Err(Error::UnsupportedFeature("Monitor"));
+        }
+        self.check_channel(channel)?;
+        if *self.running.read().unwrap() {
+            return Err(Error::Running);
+        }
+
+        let monitor = self.channels[channel].monitor;
+        let bitrate = self.channels[channel].bitrate;
+        // restored as set, which may be a custom timing rather than one
+        // calculated for the bitrate
+        let bit_timing = self.bit_timings[channel];
+        self.channels[channel].monitor = true;
+
+        let mut result = Ok(None);
+        let mut best_count = 0;
+        for &candidate in candidates {
+            match self.listen(channel, candidate, per_rate) {
+                Ok((good, errors)) => {
+                    if errors == 0 && good > best_count {
+                        best_count = good;
+                        result = Ok(Some(candidate));
+                    }
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        self.channels[channel].monitor = monitor;
+        self.channels[channel].bitrate = bitrate;
+        self.bit_timings[channel] = bit_timing;
+        if let Some((bt, _)) = bit_timing {
+            self.dev().set_bit_timing(channel as u16, bt)?;
+        }
+        result
+    }
+
+    /// Check that the bitrate configured for a channel matches the bus by
+    /// listening in monitor mode for `window`.
+    ///
+    /// Returns true if at least one frame was received and no error frames
+    /// were seen, which catches a wrong bitrate before anything is
+    /// transmitted. Returns false on a silent bus, since nothing can be
+    /// verified. The device must be stopped, and the channel is left stopped
+    /// with its previous monitor setting.
+    pub fn verify_bitrate(&mut self, channel: usize, window: time::Duration) -> Result<bool, Error> {
+        if self.features & GS_CAN_FEATURE_LISTEN_ONLY == 0 {
+            return Err(Error::UnsupportedFeature("Monitor"));
+        }
+        self.check_channel(channel)?;
+        if *self.running.read().unwrap() {
+            return Err(Error::Running);
+        }
+        let bitrate = self.channels[channel].bitrate;
+        if bitrate == 0 {
+            return Err(Error::InvalidArgument("bitrate has not been set"));
+        }
+
+        let monitor = self.channels[channel].monitor;
+        self.channels[channel].monitor = true;
+        let result = self.listen(channel, bitrate, window);
+        self.channels[channel].monitor = monitor;
+
+        let (good, errors) = result?;
+        Ok(good > 0 && errors == 0)
+    }
+
+    // start a channel at the given bitrate and count the valid and error
+    // frames received during the window
+    fn listen(
+        &mut self,
+        channel: usize,
+        bitrate: u32,
+        window: time::Duration,
+    ) -> Result<(usize, usize), Error> {
+        self.set_bitrate(channel, bitrate)?;
+        self.start_polled(channel)?;
+
+        let mut good = 0;
+        let mut errors = 0;
+        let deadline = time::Instant::now() + window;
+        let mut result = Ok(());
+        loop {
+            let now = time::Instant::now();
+            if now >= deadline {
+                break;
+            }
+            match self.poll(deadline - now) {
+                Ok(frames) => {
+                    for f in frames {
+                        if f.err {
+                            errors += 1;
+                        } else {
+                            good += 1;
+                        }
+                    }
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        // always leave the channel stopped
+        self.stop(channel)?;
+        result.map(|_| (good, errors))
+    }
+
+    /// Set CAN FD data bitrate for specified channel to requested bitrate value in bits per second.
+    ///
+    /// The timing is calculated within the device's data phase limits, see
+    /// `Interface.data_bit_timing_limits`. Returns `Error::InvalidBitrate` if
+    /// no bit timing for the bitrate can be found within them.
+    pub fn set_data_bitrate(&mut self, channel: usize, bitrate: u32) -> Result<(), Error> {
+        let limits = match self.data_bit_timing_limits {
+            Some(l) => l,
+            None => return Err(Error::UnsupportedFeature("FD")),
+        };
+
+        self.check_channel(channel)?;
+
+        let bt = calculate_data_bit_timing(self.can_clock, bitrate, &limits)?;
+        self.dev().set_data_bit_timing(channel as u16, bt)?;
+        self.data_bit_timings[channel] = Some((bt, Some(bitrate)));
+
+        self.channels[channel].data_bitrate = bitrate;
+        Ok(())
+    }
+
+    /// Returns the nominal bit timing limits reported by the device.
+    pub fn bit_timing_limits(&self) -> BitTimingLimits {
+        self.bit_timing_limits
+    }
+
+    /// Returns the data phase bit timing limits of a CAN-FD device, or
+    /// `None` if the device does not support CAN-FD. Devices that do not
+    /// report separate data phase limits use their nominal limits.
+    pub fn data_bit_timing_limits(&self) -> Option<BitTimingLimits> {
+        self.data_bit_timing_limits
+    }
+
+    /// Set a custom bit timing for the specified channel.
+    pub fn set_bit_timing(
+        &mut self,
+        channel: usize,
+        brp: u32,
+        phase_seg1: u32,
+        phase_seg2: u32,
+        sjw: u32,
+    ) -> Result<(), Error> {
+        self.check_channel(channel)?;
+        let bt = BitTiming {
+            brp,
+            prop_seg: 0,
+            phase_seg1,
+            phase_seg2,
+            sjw,
+        };
+        self.dev().set_bit_timing(channel as u16, bt)?;
+        self.bit_timings[channel] = Some((bt, None));
+        Ok(())
+    }
+
+    /// Set a custom data bit timing for the specified channel.
+    pub fn set_data_bit_timing(
+        &mut self,
+        channel: usize,
+        brp: u32,
+        phase_seg1: u32,
+        phase_seg2: u32,
+        sjw: u32,
+    ) -> Result<(), Error> {
+        self.check_channel(channel)?;
+        let bt = BitTiming {
+            brp,
+            prop_seg: 0,
+            phase_seg1,
+            phase_seg2,
+            sjw,
+        };
+        self.dev().set_data_bit_timing(channel as u16, bt)?;
+        self.data_bit_timings[channel] = Some((bt, None));
+        Ok(())
+    }
+
+    /// Describe the nominal bit timing last set on a channel, or `None` if no
+    /// bitrate or bit timing has been set.
+    ///
+    /// The error is reported for timings set with `Interface.set_bitrate`,
+    /// but not for custom timings from `Interface.set_bit_timing`.
+    pub fn bit_timing(&self, channel: usize) -> Result<Option<BitTimingInfo>, Error> {
+        self.check_channel(channel)?;
+        Ok(self.bit_timings[channel].map(|t| self.describe_timing(t)))
+    }
+
+    /// Describe the data phase bit timing last set on a channel, as
+    /// `Interface.bit_timing` does for the nominal timing.
+    pub fn data_bit_timing(&self, channel: usize) -> Result<Option<BitTimingInfo>, Error> {
+        self.check_channel(channel)?;
+        Ok(self.data_bit_timings[channel].map(|t| self.describe_timing(t)))
+    }
+
+    fn describe_timing(&self, (bt, requested): (BitTiming, Option<u32>)) -> BitTimingInfo {
+        BitTimingInfo {
+            requested,
+            ..bt.describe(self.can_clock)
+        }
+    }
+
+    /// Enable or disable a channel's listen only mode. When this mode is enabled,
+    /// the device will not transmit any frames, errors, or acknowledgements.
+    ///
+    /// The mode can be changed while the channel is started. The channel is
+    /// then briefly reset and started again with the new mode, and frames on
+    /// the bus during the switch are missed.
+    pub fn set_monitor(&mut self, channel: usize, enabled: bool) -> Result<(), Error> {
+        if self.features & GS_CAN_FEATURE_LISTEN_ONLY == 0 {
+            return Err(Error::UnsupportedFeature("Monitor"));
+        }
+        self.check_channel(channel)?;
+
+        let previous = self.channels[channel].monitor;
+        self.channels[channel].monitor = enabled;
+        let result = match self.channels[channel].check_modes() {
+            Ok(()) => self.apply_mode(channel),
+            Err(e) => Err(e),
+        };
+        result.inspect_err(|_| {
+            self.channels[channel].monitor = previous;
+        })
+    }
+
+    /// Enable or disable a channel's listen only mode. When this mode is enabled,
+    /// the device will not transmit any frames, errors, or acknowledgements.
+    pub fn set_enabled(&mut self, channel: usize, enabled: bool) -> Result<(), Error> {
+        self.check_channel(channel)?;
+        if *self.running.read().unwrap() && self.channels[channel].started {
+            return Err(Error::Running);
+        }
+
+        self.channels[channel].enabled = enabled;
+        Ok(())
+    }
+
+    /// Return a channel's configuration to the defaults it has when the
+    /// interface is opened: bitrates of zero, FD, loopback and monitor modes
+    /// disabled, the channel enabled, error reporting disabled, and both ID
+    /// types accepted by the filter set with `Interface.set_id_type_filter`.
+    ///
+    /// Returns `Error::Running` if the channel is started.
+    pub fn reset_channel_config(&mut self, channel: usize) -> Result<(), Error> {
+        self.check_channel(channel)?;
+        if *self.running.read().unwrap() && self.channels[channel].started {
+            return Err(Error::Running);
+        }
+
+        self.channels[channel] = default_channel();
+        self.berr_reporting[channel] = false;
+        let mut rx_config = self.rx_config.write().unwrap();
+        rx_config.loopback[channel] = false;
+        rx_config.id_types[channel] = (true, true);
+        Ok(())
+    }
+
+    /// Enable or disable a channel's loopback mode. When this mode is enabled,
+    /// frames sent by the device will be received by the device
+    /// *as if they had been sent by another node on the bus*.
+    ///
+    /// This mode is primarily intended for device testing!
+    ///
+    /// As with `Interface.set_monitor`, the mode can be changed while the
+    /// channel is started.
+    pub fn set_loopback(&mut self, channel: usize, enabled: bool) -> Result<(), Error> {
+        if self.features & GS_CAN_FEATURE_LOOP_BACK == 0 {
+            return Err(Error::UnsupportedFeature("Loopback"));
+        }
+        self.check_channel(channel)?;
+
+        if enabled && self.paused[channel] {
+            return Err(Error::InvalidArgument(
+                "channels in loopback mode cannot be paused",
+            ));
+        }
+
+        let previous = self.channels[channel].loopback;
+        self.channels[channel].loopback = enabled;
+        let result = match self.channels[channel].check_modes() {
+            Ok(()) => self.apply_mode(channel),
+            Err(e) => Err(e),
+        };
+        result.inspect_err(|_| {
+            self.channels[channel].loopback = previous;
+        })
+    }
+
+    /// Enable or disable bus error reporting on a channel. When enabled,
+    /// the device sends an error frame for each protocol error it detects,
+    /// such as a bit, stuff or ACK error. It is disabled by default, and
+    /// without it many devices only report bus state changes, if anything.
+    ///
+    /// Error frames are passed to the receive callback with `Frame.err` set,
+    /// and can be decoded with `BusError::from_frame`. As with
+    /// `Interface.set_monitor`, the setting can be changed while the channel
+    /// is started.
+    pub fn set_error_reporting(&mut self, channel: usize, enabled: bool) -> Result<(), Error> {
+        if (self.features & GS_CAN_FEATURE_BERR_REPORTING) == 0 {
+            return Err(Error::UnsupportedFeature("Error reporting"));
+        }
+        self.check_channel(channel)?;
+
+        let previous = self.berr_reporting[channel];
+        self.berr_reporting[channel] = enabled;
+        self.apply_mode(channel).inspect_err(|_| {
+            self.berr_reporting[channel] = previous;
+        })
+    }
+
+    /// Enable or disable CAN FD support for a channel
+    pub fn set_fd(&mut self, channel: usize, enabled: bool) -> Result<(), Error> {
+        if !self.supports_fd() {
+            return Err(Error::UnsupportedFeature("FD"));
+        }
+        self.check_channel(channel)?;
+        if *self.running.read().unwrap() && self.channels[channel].started {
+            return Err(Error::Running);
+        }
+
+        self.channels[channel].fd = enabled;
+        Ok(())
+    }
+
+    /// Set the channel used by the single channel convenience methods, such
+    /// as `Interface.set_default_bitrate` and `Interface.send_default`.
+    /// Channel 0 is used unless this is called.
+    pub fn set_default_channel(&mut self, channel: usize) -> Result<(), Error> {
+        self.check_channel(channel)?;
+        self.default_channel = channel;
+        Ok(())
+    }
+
+    /// Returns the channel used by the single channel convenience methods.
+    pub fn default_channel(&self) -> usize {
+        self.default_channel
+    }
+
+    /// Start the default channel. See `Interface.start`.
+    pub fn start_default(
+        &mut self,
+        rx_callback: impl FnMut(Frame) + Sync + Send + 'static,
+    ) -> Result<(), Error> {
+        self.start(self.default_channel, rx_callback)
+    }
+
+    /// Stop the default channel. See `Interface.stop`.
+    pub fn stop_default(&mut self) -> Result<(), Error> {
+        self.stop(self.default_channel)
+    }
+
+    /// Set the bitrate of the default channel. See `Interface.set_bitrate`.
+    pub fn set_default_bitrate(&mut self, bitrate: u32) -> Result<(), Error> {
+        self.set_bitrate(self.default_channel, bitrate)
+    }
+
+    /// Set the data phase bitrate of the default channel. See
+    /// `Interface.set_data_bitrate`.
+    pub fn set_default_data_bitrate(&mut self, bitrate: u32) -> Result<(), Error> {
+        self.set_data_bitrate(self.default_channel, bitrate)
+    }
+
+    /// Enable or disable listen only mode on the default channel. See
+    /// `Interface.set_monitor`.
+    pub fn set_default_monitor(&mut self, enabled: bool) -> Result<(), Error> {
+        self.set_monitor(self.default_channel, enabled)
+    }
+
+    /// Enable or disable loopback mode on the default channel. See
+    /// `Interface.set_loopback`.
+    pub fn set_default_loopback(&mut self, enabled: bool) -> Result<(), Error> {
+        self.set_loopback(self.default_channel, enabled)
+    }
+
+    /// Enable or disable CAN FD on the default channel. See
+    /// `Interface.set_fd`.
+    pub fn set_default_fd(&mut self, enabled: bool) -> Result<(), Error> {
+        self.set_fd(self.default_channel, enabled)
+    }
+
+    /// Returns the frequency in Hz of the device's hardware timestamp counter,
+    /// or `None` if the device does not support hardware timestamps.
+    ///
+    /// When this is `None`, frame timestamps are taken from the host clock
+    /// when the frame is received.
+    pub fn timestamp_frequency(&self) -> Option<u32> {
+        self.timestamp_frequency
+    }
+
+    /// Set the frequency in Hz of the device's hardware timestamp counter.
+    ///
+    /// The gs_usb protocol has no request for the counter frequency and
+    /// defines timestamps as microseconds, so this defaults to 1 MHz. Firmware
+    /// with a counter running at a different rate can be accommodated here.
+    /// Takes effect the next time the device is started.
+    pub fn set_timestamp_frequency(&mut self, freq: u32) -> Result<(), Error> {
+        if (self.features & GS_CAN_FEATURE_HW_TIMESTAMP) == 0 {
+            return Err(Error::UnsupportedFeature("Hardware timestamps"));
+        }
+        if freq == 0 {
+            return Err(Error::InvalidArgument("timestamp frequency must be non-zero"));
+        }
+        if *self.running.read().unwrap() {
+            return Err(Error::Running);
+        }
+
+        self.timestamp_frequency = Some(freq);
+        Ok(())
+    }
+
+    /// Read the current value of the device's hardware timestamp counter.
+    ///
+    /// The counter runs at `Interface.timestamp_frequency` (1 MHz unless set
+    /// otherwise) and wraps every 2^32 ticks, about 71 minutes at 1 MHz.
+    /// Reading it at a known host time gives an offset between device ticks
+    /// and wall-clock time; reading it again periodically measures the drift
+    /// of the device's clock over long captures. The value includes the USB
+    /// round trip delay of the request.
+    ///
+    /// Returns `Error::UnsupportedFeature` if the device does not have
+    /// hardware timestamps.
+    pub fn read_device_timestamp(&self) -> Result<u32, Error> {
+        if (self.features & GS_CAN_FEATURE_HW_TIMESTAMP) == 0 {
+            return Err(Error::UnsupportedFeature("Hardware timestamps"));
+        }
+        Ok(self.dev().get_timestamp()?)
+    }
+
+    /// Enable or disable timestamping of received frames. Enabled by default.
+    ///
+    /// When disabled, `Frame::timestamp` is `None` and the host clock is not
+    /// read for each frame, which increases the receive rate on busy buses
+    /// for applications that do not need timing. Takes effect immediately.
+    pub fn set_timestamping(&mut self, enabled: bool) {
+        self.rx_config.write().unwrap().timestamping = enabled;
+    }
+
+    /// Choose whether transmit confirmations (frames with origin
+    /// `FrameOrigin::TxEcho`) are passed to the receive callback and returned
+    /// by `Interface.poll`. Enabled by default.
+    ///
+    /// When disabled, confirmations are only delivered to receivers from
+    /// `Interface.tx_confirmations`, keeping them apart from received
+    /// traffic. Disabling `Interface.set_deliver_echoes` also drops them,
+    /// whatever this setting is. Takes effect immediately.
+    pub fn set_tx_echoes(&mut self, enabled: bool) {
+        self.rx_config.write().unwrap().tx_echoes = enabled;
+    }
+
+    /// Choose whether frames this interface sent itself are passed to the
+    /// receive callback and returned by `Interface.poll`. Enabled by default.
+    ///
+    /// When disabled, both transmit confirmations (`FrameOrigin::TxEcho`) and
+    /// frames looped back by a channel in hardware loopback mode
+    /// (`FrameOrigin::Loopback`) are dropped before the callback, so it only
+    /// sees traffic from other nodes. Receivers from
+    /// `Interface.channel_receiver` and `Interface.tx_confirmations` still
+    /// get them. Takes effect immediately.
+    ///
+    /// The two settings combine: a confirmation reaches the callback only
+    /// when both this and `Interface.set_tx_echoes` are enabled, while a
+    /// looped back frame depends on this setting alone.
+    pub fn set_deliver_echoes(&mut self, enabled: bool) {
+        self.rx_config.write().unwrap().deliver_echoes = enabled;
+    }
+
+    /// Preserve the CAN ID of received frames exactly as the device sent it,
+    /// flag bits included, in `Frame::raw_can_id`. Disabled by default.
+    ///
+    /// `Frame::can_id` is masked to the arbitration ID either way. This is
+    /// meant for debugging the gs_usb protocol, and lets a received frame be
+    /// converted back with `Frame::to_host_frame` without losing bits.
+    /// Takes effect immediately.
+    pub fn set_raw_can_ids(&mut self, enabled: bool) {
+        self.rx_config.write().unwrap().raw_can_ids = enabled;
+    }
+
+    /// Choose which ID types are received on a channel. Frames with a
+    /// standard (11 bit) ID are dropped unless `accept_standard` is set, and
+    /// frames with an extended (29 bit) ID unless `accept_extended` is set.
+    /// Both are accepted by default.
+    ///
+    /// The filter is applied on the host, before frames reach the receive
+    /// callback, `Interface.poll` and receivers such as
+    /// `Interface.channel_receiver`. Error frames are always delivered.
+    /// Takes effect immediately.
+    pub fn set_id_type_filter(
+        &mut self,
+        channel: usize,
+        accept_standard: bool,
+        accept_extended: bool,
+    ) -> Result<(), Error> {
+        self.check_channel(channel)?;
+        self.rx_config.write().unwrap().id_types[channel] = (accept_standard, accept_extended);
+        Ok(())
+    }
+
+    /// Returns the counts of error conditions reported by error frames
+    /// received on a channel since the interface was opened or the counters
+    /// were reset.
+    ///
+    /// Comparing the counts over time helps locate bus problems: form and
+    /// stuff errors usually point to wiring or termination, while ACK errors
+    /// mean no other node is acknowledging frames.
+    pub fn error_counters(&self, channel: usize) -> Result<ErrorCounters, Error> {
+        self.check_channel(channel)?;
+        Ok(self.rx_config.read().unwrap().error_counters[channel])
+    }
+
+    /// Reset the error counters of a channel to zero.
+    pub fn reset_error_counters(&mut self, channel: usize) -> Result<(), Error> {
+        self.check_channel(channel)?;
+        self.rx_config.write().unwrap().error_counters[channel] = ErrorCounters::default();
+        Ok(())
+    }
+
+    /// Returns a receiver that gets the transmit confirmation of every frame
+    /// sent on `channel`, from the time of this call.
+    ///
+    /// Confirmations are delivered in the order the device reports them,
+    /// which is the order the frames were transmitted. See
+    /// `Interface.set_tx_echoes` to remove them from the receive callback.
+    pub fn tx_confirmations(&self, channel: usize) -> Result<Receiver<Frame>, Error> {
+        self.check_channel(channel)?;
+        Ok(self.subscribe(move |f| {
+            f.channel as usize == channel && f.origin == FrameOrigin::TxEcho
+        }))
+    }
+
+    /// Blink the device's LED for the given duration so the device can be
+    /// located among several adapters. Blocks until the duration has elapsed.
+    pub fn identify(&mut self, duration: time::Duration) -> Result<(), Error> {
+        if (self.features & GS_CAN_FEATURE_IDENTIFY) == 0 {
+            return Err(Error::UnsupportedFeature("Identify"));
+        }
+
+        self.dev().set_identify(GS_CAN_IDENTIFY_ON)?;
+        thread::sleep(duration);
+        self.dev().set_identify(GS_CAN_IDENTIFY_OFF)?;
+        Ok(())
+    }
+
+    /// Retry control transfers that fail with a transient USB error, such as
+    /// a timeout or pipe error, up to `max_retries` times. The delay before
+    /// the first retry is `backoff` and doubles for each following retry.
+    ///
+    /// Errors caused by a disconnected device are never retried. By default
+    /// control transfers are not retried.
+    pub fn set_retry_policy(&mut self, max_retries: u32, backoff: time::Duration) {
+        self.dev().set_retry_policy(RetryPolicy {
+            max_retries,
+            backoff,
+        });
+    }
+
+    /// Set the byte used to pad transmitted frames whose data is shorter than
+    /// the length given by the DLC. Defaults to zero.
+    ///
+    /// Some ECUs and diagnostic protocols require unused bytes to be a
+    /// specific value, commonly 0xAA or 0xCC.
+    pub fn set_tx_padding_byte(&mut self, pad: u8) {
+        self.tx.config.lock().unwrap().padding = pad;
+    }
+
+    /// Choose whether the DLC of transmitted frames is set from the length
+    /// of their data, as `Frame::fit_dlc` does, instead of taking `can_dlc`
+    /// as given. Disabled by default.
+    ///
+    /// This avoids truncated or padded transmissions when `data` is changed
+    /// without updating `can_dlc`. CAN-FD frames are rounded up to the next
+    /// valid length and padded with the byte set by
+    /// `Interface.set_tx_padding_byte`. RTR and error frames always keep
+    /// their DLC; leave this disabled to send other frames whose DLC
+    /// intentionally differs from their data. Applies to every send method,
+    /// including handles from `Interface.sender`.
+    pub fn set_auto_dlc(&mut self, enabled: bool) {
+        self.tx.config.lock().unwrap().auto_dlc = enabled;
+    }
+
+    // convert a frame for transmission with the interface's settings
+    fn tx_host_frame(&self, f: &Frame) -> HostFrame {
+        self.tx.host_frame(f)
+    }
+
+    /// Query the device for whether a channel's controller is on the bus.
+    ///
+    /// Unlike the started state kept by the `Interface`, this reads the
+    /// controller state from the device, so it reflects a bus-off condition
+    /// or a device that reset itself. Returns true in the error active,
+    /// warning and passive states, and false when bus-off, stopped or
+    /// sleeping. Requires firmware with the gs_usb GET_STATE request.
+    pub fn channel_active(&mut self, channel: usize) -> Result<bool, Error> {
+        if (self.features & GS_CAN_FEATURE_GET_STATE) == 0 {
+            return Err(Error::UnsupportedFeature("Get state"));
+        }
+        self.check_channel(channel)?;
+
+        let state = self.dev().get_state(channel as u16)?;
+        Ok(state.is_active())
+    }
+
+    /// Feed a synthetic error frame into the receive path, as if the device
+    /// had reported `error` on `channel`.
+    ///
+    /// The frame is delivered to the receive callback, `Interface.poll` and
+    /// receivers like any other received frame. This allows error handling
+    /// such as bus-off recovery to be tested without faulty hardware. Only
+    /// available with the `testing` feature.
+    #[cfg(feature = "testing")]
+    pub fn inject_error_frame(&mut self, channel: usize, error: BusError) -> Result<(), Error> {
+        self.check_channel(channel)?;
+        if !*self.running.read().unwrap() {
+            return Err(Error::NotRunning);
+        }
+
+        let mut hf = error.to_frame(channel as u8).to_host_frame(0);
+        hf.echo_id = GSUSB_RX_ECHO_ID;
+        self.dev().inject(hf);
+        Ok(())
+    }
+
+    /// Returns a report of the device's capabilities, derived from its
+    /// feature flags and versions. Its `Display` output is intended to be
+    /// pasted into bug reports.
+    pub fn capabilities(&self) -> CapabilityReport {
+        CapabilityReport::new(
+            self.features,
+            self.channels(),
+            self.can_clock,
+            self.sw_version,
+            self.hw_version,
+        )
+    }
+
+    /// Returns the identity of the device: its USB IDs and strings, versions,
+    /// channel count and CAN clock. The USB strings are read when the device
+    /// is opened.
+    pub fn device_identity(&self) -> DeviceIdentity {
+        DeviceIdentity {
+            vendor_id: self.usb_identity.vendor_id,
+            product_id: self.usb_identity.product_id,
+            product: self.usb_identity.product.clone(),
+            serial: self.usb_identity.serial.clone(),
+            sw_version: self.sw_version,
+            hw_version: self.hw_version,
+            channels: self.channels(),
+            can_clock: self.can_clock,
+        }
+    }
+
+    /// Returns a snapshot of the interface: the device's identity and
+    /// capabilities, and the configuration, bit timings and error counters of
+    /// every channel. It can be serialized for a bug report or log.
+    pub fn snapshot(&self) -> InterfaceSnapshot {
+        let rx_config = self.rx_config.read().unwrap();
+        let channels = self
+            .channels
+            .iter()
+            .enumerate()
+            .map(|(n, ch)| ChannelSnapshot {
+                config: ch.clone(),
+                paused: self.paused[n],
+                bit_timing: self.bit_timings[n].map(|t| self.describe_timing(t)),
+                data_bit_timing: self.data_bit_timings[n].map(|t| self.describe_timing(t)),
+                error_counters: rx_config.error_counters[n],
+            })
+            .collect();
+        InterfaceSnapshot {
+            identity: self.device_identity(),
+            capabilities: self.capabilities(),
+            usb: self.usb_info(),
+            bit_timing_limits: self.bit_timing_limits,
+            data_bit_timing_limits: self.data_bit_timing_limits,
+            timestamp_frequency: self.timestamp_frequency,
+            running: *self.running.read().unwrap(),
+            disconnected: self.is_disconnected(),
+            channels,
+        }
+    }
+
+    /// Returns the USB interface and bulk endpoints used to talk to the
+    /// device, found in its configuration descriptor when it was opened.
+    pub fn usb_info(&self) -> UsbInfo {
+        self.dev().usb_info()
+    }
+
+    /// Perform a USB control transfer on the device, for vendor requests the
+    /// driver does not wrap. Returns the number of bytes transferred.
+    ///
+    /// The direction is taken from bit 7 of `request_type`: for out
+    /// transfers `data` is sent, and for in transfers it is filled with the
+    /// response. `data` can be at most 65535 bytes.
+    ///
+    /// This is an advanced escape hatch. The driver does not know what the
+    /// request does, so a request that changes the device's mode, bit timing
+    /// or other state can leave the `Interface` out of step with the device,
+    /// and standard gs_usb requests should be made through the other methods.
+    pub fn raw_control_transfer(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &mut [u8],
+        timeout: time::Duration,
+    ) -> Result<usize, Error> {
+        if data.len() > u16::MAX as usize {
+            return Err(Error::InvalidArgument("control transfer data too long"));
+        }
+        Ok(self
+            .dev()
+            .raw_control_transfer(request_type, request, value, index, data, timeout)?)
+    }
+
+    /// Returns true if device suports CAN-FD operation, false otherwise.
+    pub fn supports_fd(&self) -> bool {
+        (self.features & GS_CAN_FEATURE_FD) > 0
+    }
+
+    /// Send a CAN frame using the device
+    ///
+    /// When a transmit window is set with `Interface.set_tx_window`, this
+    /// blocks while the window is full, and returns `Error::TxWindowFull` if
+    /// no confirmation frees a slot within a second. Returns
+    /// `Error::NotRunning` if the interface is stopped, unless buffering is
+    /// enabled with `Interface.set_buffer_before_start`.
+    pub fn send(&self, f: Frame) -> Result<(), Error> {
+        self.send_windowed(f, TX_WINDOW_TIMEOUT)
+    }
+
+    /// Send a CAN frame on `channel`, overriding the frame's `channel` field,
+    /// so the channel is explicit at the call site.
+    pub fn send_on(&self, channel: usize, f: Frame) -> Result<(), Error> {
+        self.check_channel(channel)?;
+        self.send(f.with_channel(channel as u8))
+    }
+
+    /// Send frames with a fixed gap between the start of one send and the
+    /// next, for exercising receivers with deterministic traffic.
+    ///
+    /// The gap is timed on the host by sleeping until shortly before each
+    /// send and spinning for the rest, so it is held to a few microseconds
+    /// regardless of scheduler granularity; the calling thread is busy for
+    /// the duration of the burst. Sends are scheduled on a fixed grid from
+    /// the first, so a late send does not delay the rest. A zero gap, or one
+    /// shorter than a frame takes on the bus, sends back to back as fast as
+    /// the device accepts frames.
+    ///
+    /// All frames are checked before any is sent. The frames are not counted
+    /// against the transmit window set with `Interface.set_tx_window`. If a
+    /// send fails, the error is returned and the remaining frames are not
+    /// sent.
+    pub fn send_burst(&self, frames: &[Frame], gap: time::Duration) -> Result<(), Error> {
+        self.check_disconnected()?;
+        if !*self.running.read().unwrap() {
+            return Err(Error::NotRunning);
+        }
+        for f in frames {
+            self.check_channel(f.channel as usize)?;
+        }
+
+        // convert up front to keep the work between sends minimal
+        let host_frames: Vec<HostFrame> = frames
+            .iter()
+            .map(|f| self.tx_host_frame(f))
+            .collect();
+        let mut deadline = time::Instant::now();
+        for hf in host_frames {
+            wait_until(deadline);
+            self.dev().send(hf)?;
+            deadline += gap;
+        }
+        Ok(())
+    }
+
+    /// Send a CAN frame like `Interface.send`, but return
+    /// `Error::TxWindowFull` at once instead of blocking when the transmit
+    /// window is full.
+    pub fn try_send(&self, f: Frame) -> Result<(), Error> {
+        self.send_windowed(f, time::Duration::ZERO)
+    }
+
+    /// Limit the number of frames sent with `Interface.send` that may await
+    /// their transmit confirmation. Zero, the default, sets no limit.
+    ///
+    /// This applies backpressure from the device's progress, rather than
+    /// letting frames be submitted faster than the device can transmit them.
+    /// Frames are only confirmed once acknowledged, so with no other node on
+    /// the bus the window fills and stays full. When the device was started
+    /// with `Interface.start_polled`, confirmations are only seen while
+    /// `Interface.poll` is called, so `Interface.send` does not block and
+    /// fails at once when the window is full. Frames sent by other methods
+    /// are not counted.
+    pub fn set_tx_window(&mut self, max_in_flight: usize) {
+        self.tx.window.set_max(max_in_flight);
+    }
+
+    fn send_windowed(&self, f: Frame, timeout: time::Duration) -> Result<(), Error> {
+        self.check_disconnected()?;
+        self.tx.send(f, timeout)
+    }
+
+    /// Queue a frame to be sent without waiting, returning a token that
+    /// identifies it in `Interface.send_results`.
+    ///
+    /// Frames are sent in order from a background thread. Each frame's
+    /// result is reported once: the time until its transmit confirmation
+    /// arrived, the error from submitting it to the device, or
+    /// `Error::Timeout` if no confirmation arrived within a second.
+    /// Confirmations are only matched while frames are being received, so
+    /// with `Interface.start_polled` results arrive as `Interface.poll` is
+    /// called.
+    pub fn send_queued(&mut self, f: Frame) -> Result<u64, Error> {
+        if !*self.running.read().unwrap() {
+            return Err(Error::NotRunning);
+        }
+        self.check_channel(f.channel as usize)?;
+
+        let dev = Arc::clone(&self.dev);
+        let running = Arc::clone(&self.running);
+        let completions = Arc::clone(&self.completions);
+        let hf = self.tx_host_frame(&f);
+        Ok(self
+            .tx_queue
+            .get_or_insert_with(|| TxQueue::new(dev, running, completions))
+            .queue(hf))
+    }
+
+    /// Returns a receiver for the results of frames sent with
+    /// `Interface.send_queued`. All receivers share the same results, so
+    /// each result is delivered to only one of them.
+    pub fn send_results(&self) -> Receiver<SendResult> {
+        self.completions.lock().unwrap().results()
+    }
+
+    /// Send a CAN frame on the default channel, ignoring the frame's
+    /// `channel` field.
+    pub fn send_default(&self, f: Frame) -> Result<(), Error> {
+        self.send_on(self.default_channel, f)
+    }
+
+    /// Send every frame from an iterator in order, stopping at the first
+    /// error. Returns the number of frames sent.
+    ///
+    /// This pairs with `FrameGenerator` for stress testing:
+    /// `i.send_iter(FrameGenerator::new(1).take(1000))`.
+    pub fn send_iter(&self, frames: impl IntoIterator<Item = Frame>) -> Result<usize, Error> {
+        let mut sent = 0;
+        for f in frames {
+            self.send(f)?;
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    /// Returns a handle that sends frames through this interface, for use
+    /// from other threads. See `FrameSender`.
+    pub fn sender(&self) -> FrameSender {
+        FrameSender::new(self.tx.clone())
+    }
+
+    /// Send a frame every `period` from a background thread until the
+    /// returned handle is dropped. The first frame is sent immediately.
+    pub fn send_periodic(&self, f: Frame, period: time::Duration) -> Result<PeriodicTx, Error> {
+        self.send_periodic_with(f, period, |_| {})
+    }
+
+    /// Send a frame periodically like `Interface.send_periodic`, calling
+    /// `update` on the frame before each transmission.
+    ///
+    /// Changes made by `update` are kept for the next transmission, so it can
+    /// bump a rolling counter and recompute a checksum in place. `counter_xor`
+    /// and `counter_crc8` provide common schemes:
+    ///
+    /// ```no_run
+    /// # use cantact::{counter_xor, Frame, Interface};
+    /// # use std::time::Duration;
+    /// # let i = Interface::new().unwrap();
+    /// let f = Frame::new_standard(0x100, &[0; 8]).unwrap();
+    /// let heartbeat = i
+    ///     .send_periodic_with(f, Duration::from_millis(10), counter_xor(1, 0))
+    ///     .unwrap();
+    /// ```
+    pub fn send_periodic_with(
+        &self,
+        f: Frame,
+        period: time::Duration,
+        update: impl FnMut(&mut Frame) + Send + 'static,
+    ) -> Result<PeriodicTx, Error> {
+        self.check_channel(f.channel as usize)?;
+        if period.is_zero() {
+            return Err(Error::InvalidArgument("period must not be zero"));
+        }
+        Ok(PeriodicTx::new(self.sender(), f, period, update))
+    }
+
+    /// Send a copy of a CAN frame on every started channel, ignoring the
+    /// frame's `channel` field.
+    ///
+    /// The device is locked once and the copies are submitted back to back,
+    /// so no other frame from this interface is sent between them. Returns
+    /// the channel index and result of each transmission, so a failure on one
+    /// channel does not hide the others.
+    pub fn send_all_channels(&mut self, f: Frame) -> Result<Vec<ChannelResult>, Error> {
+        if !*self.running.read().unwrap() {
+            return Err(Error::NotRunning);
+        }
+
+        let channels: Vec<usize> = self
+            .channels
+            .iter()
+            .enumerate()
+            .filter(|(_, ch)| ch.started)
+            .map(|(n, _)| n)
+            .collect();
+        let host_frame = self.tx_host_frame(&f);
+        let mut dev = self.dev();
+        Ok(channels
+            .into_iter()
+            .map(|n| {
+                let mut hf = host_frame.clone();
+                hf.channel = n as u8;
+                (n, dev.send(hf).map_err(Error::from))
+            })
+            .collect())
+    }
+
+    /// Schedule a frame to be sent at a time `at` after the device was
+    /// started.
+    ///
+    /// Frames are queued to a scheduler thread and sent in order of their
+    /// deadlines, which avoids the drift of sending from a loop with sleeps.
+    /// Frames whose deadline has already passed are sent immediately. Frames
+    /// that come due while the device is stopped are discarded.
+    pub fn send_at(&mut self, f: Frame, at: time::Duration) -> Result<(), Error> {
+        if !*self.running.read().unwrap() {
+            return Err(Error::NotRunning);
+        }
+
+        let deadline = self.start_time + at;
+        let dev = Arc::clone(&self.dev);
+        let running = Arc::clone(&self.running);
+        let hf = self.tx_host_frame(&f);
+        self.scheduler
+            .get_or_insert_with(|| Scheduler::new(dev, running))
+            .schedule(hf, deadline, 0);
+        Ok(())
+    }
+
+    /// Queue a frame to be sent as soon as possible with a priority.
+    ///
+    /// Frames are queued to the same scheduler thread as `Interface.send_at`.
+    /// Among queued frames that are due, frames with a higher `priority` are
+    /// sent first, and frames of equal priority are sent in order. Frames
+    /// from `Interface.send_at` have priority 0, so an urgent frame can
+    /// preempt queued periodic traffic. The gs_usb protocol has no way to
+    /// select a transmit mailbox, so ordering only applies to the host side
+    /// queue; frames already submitted to the device are not reordered.
+    pub fn send_with_priority(&mut self, f: Frame, priority: u8) -> Result<(), Error> {
+        if !*self.running.read().unwrap() {
+            return Err(Error::NotRunning);
+        }
+
+        let dev = Arc::clone(&self.dev);
+        let running = Arc::clone(&self.running);
+        let hf = self.tx_host_frame(&f);
+        self.scheduler
+            .get_or_insert_with(|| Scheduler::new(dev, running))
+            .schedule(hf, time::Instant::now(), priority);
+        Ok(())
+    }
+
+    /// Returns the number of channels this Interface has
+    pub fn channels(&self) -> usize {
+        self.num_channels
+    }
+
+    /// Returns the configuration the interface holds for a channel, or
+    /// `None` if the channel does not exist.
+    pub fn channel_config(&self, channel: usize) -> Option<&Channel> {
+        self.channels.get(channel)
+    }
+
+    /// Returns the configuration the interface holds for every channel.
+    pub fn channel_configs(&self) -> &[Channel] {
+        &self.channels
+    }
+
+    /// Returns the number of channels that are enabled in the configuration.
+    pub fn enabled_channel_count(&self) -> usize {
+        self.enabled_channels().count()
+    }
+
+    /// Returns the index and configuration of each channel that is enabled
+    /// in the configuration.
+    pub fn enabled_channels(&self) -> impl Iterator<Item = (usize, &Channel)> {
+        self.channels.iter().enumerate().filter(|(_, ch)| ch.enabled)
+    }
+}
+
+// pass frames to the receive callback, holding them while it is paused and
+// passing the held frames first once it is not
+fn deliver(
+    held: &mut VecDeque<Frame>,
+    paused: bool,
+    frames: impl Iterator<Item = Frame>,
+    callback: &mut impl FnMut(Frame),
+) {
+    if paused {
+        held.extend(frames);
+        return;
+    }
+    for f in held.drain(..).chain(frames) {
+        callback(f);
+    }
+}
+
+// read the data phase limits of an FD device, falling back to the nominal
+// limits if the device does not report them separately
+fn read_data_bit_timing_limits(
+    dev: &mut Device,
+    bt_consts: &BitTimingConsts,
+) -> Result<Option<BitTimingLimits>, Error> {
+    if (bt_consts.feature & GS_CAN_FEATURE_FD) == 0 {
+        return Ok(None);
+    }
+    if (bt_consts.feature & GS_CAN_FEATURE_BT_CONST_EXT) == 0 {
+        return Ok(Some(bt_consts.limits()));
+    }
+    Ok(Some(dev.get_data_bit_timing_consts()?.limits()))
+}
+
+pub(crate) type DisconnectCallback = Box<dyn FnMut() + Send>;
+
+// mark the interface stopped after its device is gone, running the
+// disconnect callback if it was still running
+pub(crate) fn close_disconnected(running: &RwLock<bool>, on_disconnect: &Mutex<Option<DisconnectCallback>>) {
+    let was_running = std::mem::replace(&mut *running.write().unwrap(), false);
+    if was_running {
+        if let Some(callback) = on_disconnect.lock().unwrap().as_mut() {
+            callback();
+        }
+    }
+}
+
+// returned with Error::PermissionDenied. on linux this is almost always a
+// missing udev rule.
+const PERMISSION_HINT: &str = "permission denied opening the device; on Linux, install a udev \
+rule granting access to USB ID 1d50:606f (for example, MODE=\"0666\" or GROUP=\"plugdev\") \
+and replug the device, or run as root";
+
+fn open_device(selector: &DeviceSelector, detach_kernel_driver: bool) -> Result<Device, Error> {
+    match Device::new(UsbContext::new(), selector, detach_kernel_driver) {
+        Ok(d) => Ok(d),
+        Err(device::Error::DeviceBusy) => Err(Error::DeviceBusy),
+        Err(device::Error::PermissionDenied) => Err(Error::PermissionDenied(PERMISSION_HINT)),
+        Err(_) => Err(Error::DeviceNotFound),
+    }
+}
+
+// wait for a frame from a subscription matching the predicate
+fn wait_on(
+    rx: &Receiver<Frame>,
+    predicate: &impl Fn(&Frame) -> bool,
+    timeout: time::Duration,
+) -> Result<Frame, Error> {
+    let deadline = time::Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(time::Instant::now());
+        match rx.recv_timeout(remaining) {
+            Ok(f) if predicate(&f) => return Ok(f),
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => return Err(Error::Timeout),
+            Err(RecvTimeoutError::Disconnected) => return Err(Error::NotRunning),
+        }
+    }
+}
+
+// configuration of a channel when the interface is opened
+fn default_channel() -> Channel {
+    Channel {
+        enabled: true,
+        ..Default::default()
+    }
+}
+
+// remove and return the buffered frames for started channels, in order
+fn take_started(buffer: &mut Vec<Frame>, started: &[bool]) -> Vec<Frame> {
+    let (ready, waiting) = std::mem::take(buffer)
+        .into_iter()
+        .partition(|f| started.get(f.channel as usize).copied().unwrap_or(false));
+    *buffer = waiting;
+    ready
+}
+
+// channels are numbered from 0 to num_channels - 1
+pub(crate) fn check_channel(channel: usize, num_channels: usize) -> Result<(), Error> {
+    if channel < num_channels {
+        Ok(())
+    } else {
+        Err(Error::InvalidChannel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_channel() {
+        // single channel device
+        assert!(check_channel(0, 1).is_ok());
+        assert!(matches!(check_channel(1, 1), Err(Error::InvalidChannel)));
+        // dual channel device
+        assert!(check_channel(1, 2).is_ok());
+        assert!(matches!(check_channel(2, 2), Err(Error::InvalidChannel)));
+    }
+
+    #[test]
+    fn test_take_started() {
+        let frame = |id, channel| Frame::new_standard(id, &[]).unwrap().with_channel(channel);
+        let mut buffer = vec![frame(1, 0), frame(2, 1), frame(3, 0), frame(4, 1)];
+
+        let ready = take_started(&mut buffer, &[true, false]);
+        assert_eq!(ready.iter().map(|f| f.can_id).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(buffer.iter().map(|f| f.can_id).collect::<Vec<_>>(), vec![2, 4]);
+
+        let ready = take_started(&mut buffer, &[true, true]);
+        assert_eq!(ready.len(), 2);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_deliver() {
+        let frame = |id| Frame::new_standard(id, &[]).unwrap();
+        let mut held = VecDeque::new();
+        let mut ids = Vec::new();
+        let mut callback = |f: Frame| ids.push(f.can_id);
+
+        deliver(&mut held, false, vec![frame(1)].into_iter(), &mut callback);
+        deliver(&mut held, true, vec![frame(2), frame(3)].into_iter(), &mut callback);
+        deliver(&mut held, true, vec![frame(4)].into_iter(), &mut callback);
+        assert_eq!(held.len(), 3);
+        deliver(&mut held, false, vec![frame(5)].into_iter(), &mut callback);
+        assert!(held.is_empty());
+        assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_close_disconnected() {
+        let running = RwLock::new(true);
+        let calls = Arc::new(AtomicU64::new(0));
+        let c = Arc::clone(&calls);
+        let callback: DisconnectCallback = Box::new(move || {
+            c.fetch_add(1, Ordering::SeqCst);
+        });
+        let on_disconnect = Mutex::new(Some(callback));
+
+        close_disconnected(&running, &on_disconnect);
+        assert!(!*running.read().unwrap());
+        // the callback only runs for the transition out of running
+        close_disconnected(&running, &on_disconnect);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_wait_on() {
+        let (send, recv) = unbounded();
+        send.send(Frame::new_standard(0x100, &[]).unwrap()).unwrap();
+        send.send(Frame::new_standard(0x200, &[]).unwrap()).unwrap();
+        let timeout = time::Duration::from_millis(10);
+
+        let f = wait_on(&recv, &|f: &Frame| f.can_id == 0x200, timeout).unwrap();
+        assert_eq!(f.can_id, 0x200);
+        assert!(matches!(wait_on(&recv, &|_: &Frame| true, timeout), Err(Error::Timeout)));
+        drop(send);
+        assert!(matches!(wait_on(&recv, &|_: &Frame| true, timeout), Err(Error::NotRunning)));
+    }
+}
@@ -0,0 +1,682 @@
+//! ISO-TP (ISO 15765-2) transport layer.
+//!
+//! Provides segmentation and reassembly of messages up to 4095 bytes over
+//! classic CAN frames using normal addressing. This is the transport used by
+//! UDS and OBD-II diagnostics.
+
+use std::thread;
+use std::time;
+
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+
+use crate::{Error, Frame, FrameOrigin, Interface};
+
+// largest message that can be described by a 12 bit first frame length
+const MAX_MESSAGE_LEN: usize = 4095;
+
+// protocol control information frame types
+const PCI_SINGLE: u8 = 0x0;
+const PCI_FIRST: u8 = 0x1;
+const PCI_CONSECUTIVE: u8 = 0x2;
+const PCI_FLOW_CONTROL: u8 = 0x3;
+
+// flow control status values
+const FC_CONTINUE: u8 = 0x0;
+const FC_WAIT: u8 = 0x1;
+const FC_OVERFLOW: u8 = 0x2;
+
+/// Errors generated by the ISO-TP layer
+#[derive(Debug)]
+pub enum IsoTpError {
+    /// Error from the underlying interface.
+    Interface(Error),
+    /// A protocol timer expired before the transfer could continue.
+    Timeout(TimeoutPhase),
+    /// The message is too large to be sent with ISO-TP.
+    TooLarge(usize),
+    /// The peer reported that it cannot receive a message of this size.
+    Overflow,
+    /// The peer sent more consecutive wait flow control frames than allowed
+    /// by `IsoTpConfig::max_wait_frames`.
+    WaitLimit,
+    /// A consecutive frame arrived with an unexpected sequence number.
+    SequenceError {
+        /// Sequence number that was expected.
+        expected: u8,
+        /// Sequence number that was received.
+        received: u8,
+    },
+    /// A frame could not be decoded as ISO-TP.
+    InvalidFrame,
+}
+impl From<Error> for IsoTpError {
+    fn from(e: Error) -> IsoTpError {
+        IsoTpError::Interface(e)
+    }
+}
+
+/// The ISO 15765-2 timer that expired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    /// N_As/N_Ar: a frame was not handed to the device in time. This covers
+    /// flow control frames sent while receiving as well as data frames.
+    As,
+    /// N_Bs: the peer did not send a flow control frame after a first frame
+    /// or a complete block.
+    Bs,
+    /// N_Cr: the peer stopped sending consecutive frames mid-transfer.
+    Cr,
+    /// The peer did not start a message within `IsoTpConfig::timeout`.
+    Idle,
+}
+
+/// Configuration of an ISO-TP channel.
+#[derive(Debug, Clone)]
+pub struct IsoTpConfig {
+    /// Number of consecutive frames the peer may send between flow control
+    /// frames. Zero allows the peer to send all frames without waiting.
+    pub block_size: u8,
+    /// Minimum separation time requested from the peer between consecutive
+    /// frames, in ISO-TP STmin encoding.
+    pub st_min: u8,
+    /// When set, frames are padded to 8 bytes with this value.
+    pub padding: Option<u8>,
+    /// Time `IsoTpChannel::recv` waits for the peer to start a message.
+    pub timeout: time::Duration,
+    /// Time allowed to hand a frame to the device (N_As and N_Ar).
+    pub n_as: time::Duration,
+    /// Time to wait for a flow control frame from the peer (N_Bs).
+    pub n_bs: time::Duration,
+    /// Time to wait for the next consecutive frame from the peer (N_Cr).
+    pub n_cr: time::Duration,
+    /// Device channel used to send and receive frames.
+    pub channel: u8,
+    /// Use 29 bit extended CAN IDs for both directions. IDs above 0x7FF are
+    /// always extended.
+    pub extended: bool,
+    /// Number of consecutive wait flow control frames accepted from the
+    /// peer before the transfer is aborted (N_WFTmax).
+    pub max_wait_frames: u8,
+    /// Largest message accepted from the peer. Longer messages are refused
+    /// with an overflow flow control frame. At most 4095.
+    pub max_message_len: usize,
+}
+
+impl Default for IsoTpConfig {
+    fn default() -> IsoTpConfig {
+        IsoTpConfig {
+            block_size: 0,
+            st_min: 0,
+            padding: Some(0xCC),
+            timeout: time::Duration::from_millis(1000),
+            // timeout values from ISO 15765-2
+            n_as: time::Duration::from_millis(1000),
+            n_bs: time::Duration::from_millis(1000),
+            n_cr: time::Duration::from_millis(1000),
+            channel: 0,
+            extended: false,
+            max_wait_frames: 10,
+            max_message_len: MAX_MESSAGE_LEN,
+        }
+    }
+}
+
+// decode an STmin value to a duration
+fn st_min_duration(st_min: u8) -> time::Duration {
+    match st_min {
+        0x00..=0x7F => time::Duration::from_millis(st_min as u64),
+        // 100 to 900 microseconds
+        0xF1..=0xF9 => time::Duration::from_micros((st_min - 0xF0) as u64 * 100),
+        // reserved values are treated as the longest separation time
+        _ => time::Duration::from_millis(0x7F),
+    }
+}
+
+// consecutive frame sequence numbers wrap from 0xF to 0x0
+fn next_sn(sn: u8) -> u8 {
+    (sn + 1) & 0x0F
+}
+
+// a decoded ISO-TP frame
+#[derive(Debug, PartialEq)]
+enum Pci<'a> {
+    Single(&'a [u8]),
+    First(usize, &'a [u8]),
+    Consecutive(u8, &'a [u8]),
+    FlowControl { status: u8, block_size: u8, st_min: u8 },
+}
+
+fn parse(data: &[u8]) -> Result<Pci<'_>, IsoTpError> {
+    if data.is_empty() {
+        return Err(IsoTpError::InvalidFrame);
+    }
+    match data[0] >> 4 {
+        PCI_SINGLE => {
+            let len = (data[0] & 0x0F) as usize;
+            if len == 0 || len >= data.len() {
+                return Err(IsoTpError::InvalidFrame);
+            }
+            Ok(Pci::Single(&data[1..=len]))
+        }
+        PCI_FIRST => {
+            if data.len() < 8 {
+                return Err(IsoTpError::InvalidFrame);
+            }
+            let len = (((data[0] & 0x0F) as usize) << 8) | data[1] as usize;
+            // messages that fit in a single frame must not be segmented. this
+            // also rejects the escape sequence for messages over 4095 bytes.
+            if len <= 7 {
+                return Err(IsoTpError::InvalidFrame);
+            }
+            Ok(Pci::First(len, &data[2..]))
+        }
+        PCI_CONSECUTIVE => Ok(Pci::Consecutive(data[0] & 0x0F, &data[1..])),
+        PCI_FLOW_CONTROL => {
+            if data.len() < 3 {
+                return Err(IsoTpError::InvalidFrame);
+            }
+            Ok(Pci::FlowControl {
+                status: data[0] & 0x0F,
+                block_size: data[1],
+                st_min: data[2],
+            })
+        }
+        _ => Err(IsoTpError::InvalidFrame),
+    }
+}
+
+/// An ISO-TP connection to a single peer, identified by a pair of CAN IDs.
+pub struct IsoTpChannel<'a> {
+    i: &'a mut Interface,
+    tx_id: u32,
+    config: IsoTpConfig,
+    rx: Receiver<Frame>,
+}
+
+impl<'a> IsoTpChannel<'a> {
+    /// Create a new channel sending on `tx_id` and receiving on `rx_id`.
+    ///
+    /// Frames addressed to `rx_id` are collected from the time the channel
+    /// is created. The interface must be started before sending or receiving.
+    pub fn new(i: &'a mut Interface, tx_id: u32, rx_id: u32, config: IsoTpConfig) -> Self {
+        let channel = config.channel;
+        let ext = config.extended || rx_id > 0x7FF;
+        let rx = i.subscribe(move |f| {
+            f.can_id == rx_id
+                && f.ext == ext
+                && f.channel == channel
+                && f.origin != FrameOrigin::TxEcho
+        });
+        IsoTpChannel {
+            i,
+            tx_id,
+            config,
+            rx,
+        }
+    }
+
+    /// Change the CAN ID used to send frames to the peer.
+    ///
+    /// This is needed after a functional (broadcast) request, where flow
+    /// control for the response is sent to the responding ECU's physical ID.
+    pub fn set_tx_id(&mut self, tx_id: u32) {
+        self.tx_id = tx_id;
+    }
+
+    /// Send a message to the peer, segmenting it if it does not fit in a
+    /// single frame.
+    pub fn send(&mut self, data: &[u8]) -> Result<(), IsoTpError> {
+        let mut tx = Segmenter::new(data, self.config.max_wait_frames)?;
+        self.send_frame(tx.first_frame())?;
+
+        while !tx.done() {
+            let st_min = self.wait_flow_control(&mut tx)?;
+            let mut first = true;
+            while let Some(payload) = tx.next_frame() {
+                if !first {
+                    thread::sleep(st_min);
+                }
+                first = false;
+                self.send_frame(payload)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Receive a message from the peer, reassembling it if it spans multiple
+    /// frames.
+    ///
+    /// A segmented message longer than `IsoTpConfig::max_message_len` is
+    /// refused with an overflow flow control frame and `IsoTpError::TooLarge`
+    /// is returned.
+    pub fn recv(&mut self) -> Result<Vec<u8>, IsoTpError> {
+        loop {
+            let deadline = time::Instant::now() + self.config.timeout;
+            let f = recv_before(&self.rx, deadline, TimeoutPhase::Idle)?;
+            match parse(f.payload())? {
+                Pci::Single(data) => return Ok(data.to_vec()),
+                Pci::First(len, data) => return self.recv_segmented(len, data),
+                // consecutive and flow control frames outside of a transfer
+                // are not for us
+                _ => {}
+            }
+        }
+    }
+
+    fn recv_segmented(&mut self, len: usize, first: &[u8]) -> Result<Vec<u8>, IsoTpError> {
+        let mut rx = match Reassembler::new(len, first, &self.config) {
+            Ok(rx) => rx,
+            Err(e) => {
+                self.send_flow_control(FC_OVERFLOW)?;
+                return Err(e);
+            }
+        };
+
+        while !rx.done() {
+            self.send_flow_control(FC_CONTINUE)?;
+            loop {
+                let deadline = time::Instant::now() + self.config.n_cr;
+                let f = recv_before(&self.rx, deadline, TimeoutPhase::Cr)?;
+                if rx.consecutive(f.payload())? {
+                    break;
+                }
+            }
+        }
+        Ok(rx.into_message())
+    }
+
+    // wait for flow control allowing the next block, returning its STmin
+    fn wait_flow_control(&mut self, tx: &mut Segmenter<'_>) -> Result<time::Duration, IsoTpError> {
+        let mut deadline = time::Instant::now() + self.config.n_bs;
+        loop {
+            let f = recv_before(&self.rx, deadline, TimeoutPhase::Bs)?;
+            match tx.flow_control(f.payload())? {
+                Flow::Send(st_min) => return Ok(st_min),
+                // a wait frame restarts N_Bs
+                Flow::Wait => deadline = time::Instant::now() + self.config.n_bs,
+                Flow::Ignore => {}
+            }
+        }
+    }
+
+    fn send_flow_control(&mut self, status: u8) -> Result<(), IsoTpError> {
+        let payload = vec![
+            (PCI_FLOW_CONTROL << 4) | status,
+            self.config.block_size,
+            self.config.st_min,
+        ];
+        self.send_frame(payload)
+    }
+
+    fn send_frame(&mut self, mut payload: Vec<u8>) -> Result<(), IsoTpError> {
+        if let Some(pad) = self.config.padding {
+            payload.resize(8, pad);
+        }
+        let f = Frame {
+            can_id: self.tx_id,
+            can_dlc: payload.len() as u8,
+            channel: self.config.channel,
+            ext: self.config.extended || self.tx_id > 0x7FF,
+            data: payload,
+            ..Default::default()
+        };
+        let start = time::Instant::now();
+        self.i.send(f)?;
+        if start.elapsed() > self.config.n_as {
+            return Err(IsoTpError::Timeout(TimeoutPhase::As));
+        }
+        Ok(())
+    }
+}
+
+// what a sender does after a frame received while waiting for flow control
+#[derive(Debug, PartialEq)]
+enum Flow {
+    // send the next block, with this separation time between frames
+    Send(time::Duration),
+    // keep waiting, with the N_Bs timer restarted
+    Wait,
+    // the frame is not flow control, keep waiting
+    Ignore,
+}
+
+// segments a message into frame payloads, independent of the interface
+struct Segmenter<'a> {
+    data: &'a [u8],
+    // bytes of data already segmented
+    pos: usize,
+    sn: u8,
+    // consecutive frames left in the current block, None if unlimited
+    block_left: Option<u8>,
+    waits: u8,
+    max_wait_frames: u8,
+}
+
+impl<'a> Segmenter<'a> {
+    fn new(data: &'a [u8], max_wait_frames: u8) -> Result<Segmenter<'a>, IsoTpError> {
+        if data.len() > MAX_MESSAGE_LEN {
+            return Err(IsoTpError::TooLarge(data.len()));
+        }
+        Ok(Segmenter {
+            data,
+            pos: 0,
+            sn: 1,
+            block_left: Some(0),
+            waits: 0,
+            max_wait_frames,
+        })
+    }
+
+    // the single frame carrying the whole message, or the first frame
+    fn first_frame(&mut self) -> Vec<u8> {
+        let len = self.data.len();
+        if len <= 7 {
+            let mut payload = vec![(PCI_SINGLE << 4) | len as u8];
+            payload.extend_from_slice(self.data);
+            self.pos = len;
+            return payload;
+        }
+        let mut payload = vec![(PCI_FIRST << 4) | (len >> 8) as u8, (len & 0xFF) as u8];
+        payload.extend_from_slice(&self.data[..6]);
+        self.pos = 6;
+        payload
+    }
+
+    fn done(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    // handle a frame received while waiting for flow control
+    fn flow_control(&mut self, payload: &[u8]) -> Result<Flow, IsoTpError> {
+        let (status, block_size, st_min) = match parse(payload)? {
+            Pci::FlowControl {
+                status,
+                block_size,
+                st_min,
+            } => (status, block_size, st_min),
+            _ => return Ok(Flow::Ignore),
+        };
+        match status {
+            FC_CONTINUE => {
+                self.waits = 0;
+                self.block_left = if block_size == 0 {
+                    None
+                } else {
+                    Some(block_size)
+                };
+                Ok(Flow::Send(st_min_duration(st_min)))
+            }
+            FC_WAIT => {
+                self.waits += 1;
+                if self.waits > self.max_wait_frames {
+                    return Err(IsoTpError::WaitLimit);
+                }
+                Ok(Flow::Wait)
+            }
+            FC_OVERFLOW => Err(IsoTpError::Overflow),
+            _ => Err(IsoTpError::InvalidFrame),
+        }
+    }
+
+    // the next consecutive frame, or None once the message is sent or the
+    // block is complete and flow control is needed
+    fn next_frame(&mut self) -> Option<Vec<u8>> {
+        if self.done() || self.block_left == Some(0) {
+            return None;
+        }
+        let end = std::cmp::min(self.pos + 7, self.data.len());
+        let mut payload = vec![(PCI_CONSECUTIVE << 4) | self.sn];
+        payload.extend_from_slice(&self.data[self.pos..end]);
+        self.pos = end;
+        self.sn = next_sn(self.sn);
+        if let Some(left) = self.block_left.as_mut() {
+            *left -= 1;
+        }
+        Some(payload)
+    }
+}
+
+// reassembles a segmented message from consecutive frames, independent of
+// the interface
+struct Reassembler {
+    len: usize,
+    message: Vec<u8>,
+    sn: u8,
+    block_size: u8,
+    // consecutive frames received in the current block
+    received: u8,
+}
+
+impl Reassembler {
+    // start reassembly from a first frame. a message longer than the
+    // configured maximum is refused with TooLarge.
+    fn new(len: usize, first: &[u8], config: &IsoTpConfig) -> Result<Reassembler, IsoTpError> {
+        if len > config.max_message_len {
+            return Err(IsoTpError::TooLarge(len));
+        }
+        Ok(Reassembler {
+            len,
+            message: first.to_vec(),
+            sn: 1,
+            block_size: config.block_size,
+            received: 0,
+        })
+    }
+
+    fn done(&self) -> bool {
+        self.message.len() >= self.len
+    }
+
+    // add a consecutive frame, returning true once the block or the message
+    // is complete
+    fn consecutive(&mut self, payload: &[u8]) -> Result<bool, IsoTpError> {
+        match parse(payload)? {
+            Pci::Consecutive(n, data) => {
+                if n != self.sn {
+                    return Err(IsoTpError::SequenceError {
+                        expected: self.sn,
+                        received: n,
+                    });
+                }
+                self.message.extend_from_slice(data);
+                self.sn = next_sn(self.sn);
+            }
+            _ => return Err(IsoTpError::InvalidFrame),
+        }
+        self.received += 1;
+        if self.block_size != 0 && self.received == self.block_size {
+            self.received = 0;
+            return Ok(true);
+        }
+        Ok(self.done())
+    }
+
+    fn into_message(mut self) -> Vec<u8> {
+        self.message.truncate(self.len);
+        self.message
+    }
+}
+
+// receive a frame, failing with a timeout in the given phase at the deadline
+fn recv_before(
+    rx: &Receiver<Frame>,
+    deadline: time::Instant,
+    phase: TimeoutPhase,
+) -> Result<Frame, IsoTpError> {
+    match rx.recv_timeout(deadline.saturating_duration_since(time::Instant::now())) {
+        Ok(f) => Ok(f),
+        Err(RecvTimeoutError::Timeout) => Err(IsoTpError::Timeout(phase)),
+        Err(RecvTimeoutError::Disconnected) => Err(IsoTpError::Interface(Error::NotRunning)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(&[0x03, 0x22, 0xF1, 0x90, 0xCC, 0xCC, 0xCC, 0xCC]).unwrap(),
+            Pci::Single(&[0x22, 0xF1, 0x90])
+        );
+        assert_eq!(
+            parse(&[0x10, 0x14, 0x62, 0xF1, 0x90, 0x57, 0x30, 0x4C]).unwrap(),
+            Pci::First(20, &[0x62, 0xF1, 0x90, 0x57, 0x30, 0x4C])
+        );
+        assert_eq!(
+            parse(&[0x2F, 1, 2, 3]).unwrap(),
+            Pci::Consecutive(0x0F, &[1, 2, 3])
+        );
+        assert_eq!(
+            parse(&[0x30, 0x08, 0x14]).unwrap(),
+            Pci::FlowControl {
+                status: FC_CONTINUE,
+                block_size: 8,
+                st_min: 0x14
+            }
+        );
+        // single frame length longer than the frame
+        assert!(parse(&[0x07, 1, 2]).is_err());
+        assert!(parse(&[0x40]).is_err());
+        // first frame for a message that fits in a single frame
+        assert!(parse(&[0x10, 0x07, 1, 2, 3, 4, 5, 6]).is_err());
+    }
+
+    #[test]
+    fn test_st_min_duration() {
+        assert_eq!(st_min_duration(0x14), time::Duration::from_millis(20));
+        assert_eq!(st_min_duration(0x80), time::Duration::from_millis(127));
+        assert_eq!(st_min_duration(0xF1), time::Duration::from_micros(100));
+        assert_eq!(st_min_duration(0xF9), time::Duration::from_micros(900));
+        assert_eq!(st_min_duration(0xFA), time::Duration::from_millis(127));
+    }
+
+    #[test]
+    fn test_recv_before() {
+        let (send, recv) = crossbeam_channel::unbounded();
+        let past = time::Instant::now();
+        send.send(Frame::default()).unwrap();
+        assert!(recv_before(&recv, past, TimeoutPhase::Cr).is_ok());
+        match recv_before(&recv, past, TimeoutPhase::Bs) {
+            Err(IsoTpError::Timeout(phase)) => assert_eq!(phase, TimeoutPhase::Bs),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_next_sn() {
+        assert_eq!(next_sn(1), 2);
+        assert_eq!(next_sn(0x0F), 0);
+    }
+
+    // flow control payload
+    fn fc(status: u8, block_size: u8, st_min: u8) -> Vec<u8> {
+        vec![(PCI_FLOW_CONTROL << 4) | status, block_size, st_min]
+    }
+
+    #[test]
+    fn test_segmenter() {
+        let mut tx = Segmenter::new(&[1, 2, 3], 0).unwrap();
+        assert_eq!(tx.first_frame(), vec![0x03, 1, 2, 3]);
+        assert!(tx.done());
+        assert!(Segmenter::new(&[0; 4096], 0).is_err());
+
+        // 6 bytes in the first frame and 18 consecutive frames of 7
+        let data: Vec<u8> = (0..132).collect();
+        let mut tx = Segmenter::new(&data, 1).unwrap();
+        assert_eq!(tx.first_frame(), vec![0x10, 132, 0, 1, 2, 3, 4, 5]);
+        // nothing is sent before flow control
+        assert_eq!(tx.next_frame(), None);
+
+        // non flow control frames are ignored, and one wait is allowed
+        assert_eq!(tx.flow_control(&[0x21, 0]).unwrap(), Flow::Ignore);
+        assert_eq!(tx.flow_control(&fc(FC_WAIT, 0, 0)).unwrap(), Flow::Wait);
+        assert_eq!(
+            tx.flow_control(&fc(FC_CONTINUE, 4, 0xF5)).unwrap(),
+            Flow::Send(time::Duration::from_micros(500))
+        );
+        // the block size pauses the transfer for the next flow control
+        let mut frames = vec![];
+        while let Some(payload) = tx.next_frame() {
+            frames.push(payload);
+        }
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[0], vec![0x21, 6, 7, 8, 9, 10, 11, 12]);
+
+        // a block size of zero sends the rest, wrapping the sequence number
+        tx.flow_control(&fc(FC_CONTINUE, 0, 0)).unwrap();
+        while let Some(payload) = tx.next_frame() {
+            frames.push(payload);
+        }
+        assert!(tx.done());
+        assert_eq!(frames.len(), 18);
+        let sns: Vec<u8> = frames.iter().map(|p| p[0] & 0x0F).collect();
+        assert_eq!(&sns[13..], &[0xE, 0xF, 0x0, 0x1, 0x2]);
+        assert_eq!(frames[17], vec![0x22, 125, 126, 127, 128, 129, 130, 131]);
+
+        // more waits than allowed, and overflow, abort the transfer
+        let mut tx = Segmenter::new(&data, 1).unwrap();
+        tx.first_frame();
+        tx.flow_control(&fc(FC_WAIT, 0, 0)).unwrap();
+        assert!(matches!(
+            tx.flow_control(&fc(FC_WAIT, 0, 0)),
+            Err(IsoTpError::WaitLimit)
+        ));
+        assert!(matches!(
+            tx.flow_control(&fc(FC_OVERFLOW, 0, 0)),
+            Err(IsoTpError::Overflow)
+        ));
+    }
+
+    #[test]
+    fn test_reassembler() {
+        let config = IsoTpConfig {
+            block_size: 8,
+            ..Default::default()
+        };
+        let data: Vec<u8> = (0..132).collect();
+        let mut tx = Segmenter::new(&data, 0).unwrap();
+        let first = tx.first_frame();
+        let (len, first_data) = match parse(&first).unwrap() {
+            Pci::First(len, data) => (len, data.to_vec()),
+            pci => panic!("unexpected frame: {:?}", pci),
+        };
+        tx.flow_control(&fc(FC_CONTINUE, 0, 0)).unwrap();
+
+        // flow control is needed after every block of 8 frames
+        let mut rx = Reassembler::new(len, &first_data, &config).unwrap();
+        let mut blocks = 0;
+        while let Some(payload) = tx.next_frame() {
+            if rx.consecutive(&payload).unwrap() {
+                blocks += 1;
+            }
+        }
+        assert!(rx.done());
+        assert_eq!(blocks, 3);
+        assert_eq!(rx.into_message(), data);
+
+        // a wrong sequence number aborts
+        let mut rx = Reassembler::new(len, &first_data, &config).unwrap();
+        match rx.consecutive(&[0x22, 0]) {
+            Err(IsoTpError::SequenceError { expected, received }) => {
+                assert_eq!((expected, received), (1, 2))
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(matches!(
+            rx.consecutive(&fc(FC_CONTINUE, 0, 0)),
+            Err(IsoTpError::InvalidFrame)
+        ));
+
+        // messages over the configured maximum are refused
+        let config = IsoTpConfig {
+            max_message_len: 100,
+            ..Default::default()
+        };
+        assert!(matches!(
+            Reassembler::new(132, &first_data, &config),
+            Err(IsoTpError::TooLarge(132))
+        ));
+    }
+}
@@ -0,0 +1,640 @@
+//! SAE J1939 identifiers.
+//!
+//! J1939 uses 29 bit extended IDs made up of a 3 bit priority, an 18 bit
+//! parameter group number (PGN) and an 8 bit source address:
+//!
+//! ```text
+//! 28..26    25   24   23..16   15..8   7..0
+//! priority  EDP  DP   PF       PS      SA
+//! ```
+//!
+//! When the PDU format (PF) is below 240 the frame is PDU1 and the PDU
+//! specific (PS) byte is a destination address. Otherwise the frame is PDU2,
+//! broadcast to all nodes, and PS is a group extension that is part of the
+//! PGN.
+//!
+//! Messages longer than 8 bytes are carried by the transport protocol, and
+//! are reassembled by `J1939Stream`.
+
+use std::collections::HashMap;
+use std::time;
+
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+
+use crate::{Error, Frame, FrameOrigin, Interface};
+
+// PDU format values from this one up are PDU2
+const PDU2_MIN_PF: u8 = 240;
+
+// transport protocol connection management and data transfer PGNs
+const PGN_TP_CM: u32 = 0xEC00;
+const PGN_TP_DT: u32 = 0xEB00;
+
+// connection management control bytes
+const CM_RTS: u8 = 16;
+const CM_CTS: u8 = 17;
+const CM_END_OF_MSG_ACK: u8 = 19;
+const CM_BAM: u8 = 32;
+const CM_ABORT: u8 = 255;
+
+// connection abort reasons
+const ABORT_TIMEOUT: u8 = 3;
+const ABORT_BAD_SEQUENCE: u8 = 7;
+
+// largest message the transport protocol can carry, 255 packets of 7 bytes
+const MAX_TP_LEN: usize = 1785;
+// priority of connection management frames sent by the stream
+const TP_PRIORITY: u8 = 7;
+
+// timeouts from J1939-21: between data packets (T1), and from sending a CTS
+// until the first data packet (T2)
+const T1: time::Duration = time::Duration::from_millis(750);
+const T2: time::Duration = time::Duration::from_millis(1250);
+
+/// Destination address that addresses all nodes.
+pub const GLOBAL_ADDRESS: u8 = 0xFF;
+
+/// The PDU format of a J1939 identifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PduFormat {
+    /// Peer to peer: PS is the destination address.
+    Pdu1,
+    /// Broadcast: PS is a group extension.
+    Pdu2,
+}
+
+/// A J1939 identifier, split into its fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct J1939Id {
+    /// Priority, 0 (highest) to 7.
+    pub priority: u8,
+    /// Extended data page bit.
+    pub edp: bool,
+    /// Data page bit.
+    pub dp: bool,
+    /// PDU format.
+    pub pf: u8,
+    /// PDU specific: the destination address of PDU1 frames, or the group
+    /// extension of PDU2 frames.
+    pub ps: u8,
+    /// Source address.
+    pub sa: u8,
+}
+
+impl J1939Id {
+    /// Build an identifier from a PGN. For PDU1 PGNs, `destination` is
+    /// placed in the PS byte and the low byte of `pgn` is ignored; for PDU2
+    /// PGNs, `destination` is ignored. Bits of `priority` and `pgn` beyond
+    /// their widths are ignored.
+    pub fn new(priority: u8, pgn: u32, destination: u8, sa: u8) -> J1939Id {
+        let mut id = J1939Id::from((pgn & 0x3_FFFF) << 8 | sa as u32);
+        id.priority = priority & 0x7;
+        if id.pdu_format() == PduFormat::Pdu1 {
+            id.ps = destination;
+        }
+        id
+    }
+
+    /// Returns the identifier of an extended frame, or `None` for standard
+    /// frames.
+    pub fn from_frame(f: &Frame) -> Option<J1939Id> {
+        if f.ext {
+            Some(J1939Id::from(f.can_id))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the 29 bit CAN ID.
+    pub fn to_can_id(&self) -> u32 {
+        ((self.priority & 0x7) as u32) << 26
+            | (self.edp as u32) << 25
+            | (self.dp as u32) << 24
+            | (self.pf as u32) << 16
+            | (self.ps as u32) << 8
+            | self.sa as u32
+    }
+
+    /// Returns whether the identifier is PDU1 (addressed) or PDU2
+    /// (broadcast).
+    pub fn pdu_format(&self) -> PduFormat {
+        if self.pf < PDU2_MIN_PF {
+            PduFormat::Pdu1
+        } else {
+            PduFormat::Pdu2
+        }
+    }
+
+    /// Returns the parameter group number. For PDU1 identifiers the
+    /// destination address is not part of the PGN, and reads as zero.
+    pub fn pgn(&self) -> u32 {
+        let ps = match self.pdu_format() {
+            PduFormat::Pdu1 => 0,
+            PduFormat::Pdu2 => self.ps,
+        };
+        (self.edp as u32) << 17 | (self.dp as u32) << 16 | (self.pf as u32) << 8 | ps as u32
+    }
+
+    /// Returns the destination address of a PDU1 identifier, or
+    /// `GLOBAL_ADDRESS` for PDU2 identifiers, which are broadcast.
+    pub fn destination(&self) -> u8 {
+        match self.pdu_format() {
+            PduFormat::Pdu1 => self.ps,
+            PduFormat::Pdu2 => GLOBAL_ADDRESS,
+        }
+    }
+}
+
+impl From<u32> for J1939Id {
+    /// Split a CAN ID into J1939 fields. Bits above the 29 bit ID are
+    /// ignored.
+    fn from(can_id: u32) -> J1939Id {
+        J1939Id {
+            priority: ((can_id >> 26) & 0x7) as u8,
+            edp: can_id & (1 << 25) != 0,
+            dp: can_id & (1 << 24) != 0,
+            pf: (can_id >> 16) as u8,
+            ps: (can_id >> 8) as u8,
+            sa: can_id as u8,
+        }
+    }
+}
+
+/// A complete J1939 message, either from a single frame or reassembled by
+/// the transport protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct J1939Message {
+    /// Priority of the frame, or of the connection management frame that
+    /// started the transfer.
+    pub priority: u8,
+    /// Parameter group number.
+    pub pgn: u32,
+    /// Source address.
+    pub source: u8,
+    /// Destination address, `GLOBAL_ADDRESS` for broadcasts.
+    pub destination: u8,
+    /// Message data.
+    pub data: Vec<u8>,
+}
+
+// a transport protocol transfer in progress
+struct Session {
+    priority: u8,
+    pgn: u32,
+    size: usize,
+    packets: u8,
+    // sequence number of the next data packet
+    next: u8,
+    data: Vec<u8>,
+    deadline: time::Instant,
+    // for RTS/CTS transfers to our address: packets left in the window of
+    // the last CTS, and the most packets the sender accepts per CTS
+    window: Option<(u8, u8)>,
+}
+
+// connection management frame, with the PGN of the transferred message
+fn cm_frame(control: u8, b: [u8; 4], pgn: u32, source: u8, destination: u8, channel: u8) -> Frame {
+    let id = J1939Id::new(TP_PRIORITY, PGN_TP_CM, destination, source);
+    let data = [
+        control,
+        b[0],
+        b[1],
+        b[2],
+        b[3],
+        pgn as u8,
+        (pgn >> 8) as u8,
+        (pgn >> 16) as u8,
+    ];
+    let mut f = Frame::new_extended(id.to_can_id(), &data).unwrap();
+    f.channel = channel;
+    f
+}
+
+// reassembles transport protocol transfers, independent of the interface.
+// transfers are keyed by source and destination address.
+struct Reassembler {
+    address: Option<u8>,
+    channel: u8,
+    sessions: HashMap<(u8, u8), Session>,
+}
+
+impl Reassembler {
+    fn new(address: Option<u8>, channel: u8) -> Reassembler {
+        Reassembler {
+            address,
+            channel,
+            sessions: HashMap::new(),
+        }
+    }
+
+    // process a received frame, returning a completed message and any frames
+    // to send in reply
+    fn process(&mut self, f: &Frame, now: time::Instant) -> (Option<J1939Message>, Vec<Frame>) {
+        let id = match J1939Id::from_frame(f) {
+            Some(id) => id,
+            None => return (None, vec![]),
+        };
+        let key = (id.sa, id.destination());
+        match id.pgn() {
+            PGN_TP_CM => (None, self.connection_management(id, f.payload(), now)),
+            PGN_TP_DT => self.data_transfer(key, f.payload(), now),
+            pgn => {
+                let msg = J1939Message {
+                    priority: id.priority,
+                    pgn,
+                    source: id.sa,
+                    destination: id.destination(),
+                    data: f.payload().to_vec(),
+                };
+                (Some(msg), vec![])
+            }
+        }
+    }
+
+    fn connection_management(
+        &mut self,
+        id: J1939Id,
+        data: &[u8],
+        now: time::Instant,
+    ) -> Vec<Frame> {
+        if data.len() < 8 {
+            return vec![];
+        }
+        let (source, destination) = (id.sa, id.destination());
+        let pgn = data[5] as u32 | (data[6] as u32) << 8 | (data[7] as u32) << 16;
+        match data[0] {
+            CM_RTS | CM_BAM => {
+                let size = data[1] as usize | (data[2] as usize) << 8;
+                let packets = data[3];
+                let bam = data[0] == CM_BAM;
+                if size <= 8
+                    || size > MAX_TP_LEN
+                    || packets as usize != size.div_ceil(7)
+                    || bam != (destination == GLOBAL_ADDRESS)
+                {
+                    return vec![];
+                }
+
+                let ours = !bam && self.address == Some(destination);
+                let max_per_cts = if data[4] == 0 { 0xFF } else { data[4] };
+                let window = std::cmp::min(packets, max_per_cts);
+                // a new transfer replaces one in progress between the same nodes
+                self.sessions.insert(
+                    (source, destination),
+                    Session {
+                        priority: id.priority,
+                        pgn,
+                        size,
+                        packets,
+                        next: 1,
+                        data: Vec::with_capacity(packets as usize * 7),
+                        deadline: now + if bam { T1 } else { T2 },
+                        window: if ours {
+                            Some((window, max_per_cts))
+                        } else {
+                            None
+                        },
+                    },
+                );
+                if ours {
+                    let b = [window, 1, 0xFF, 0xFF];
+                    return vec![cm_frame(CM_CTS, b, pgn, destination, source, self.channel)];
+                }
+                vec![]
+            }
+            CM_ABORT => {
+                // either side can abort
+                self.sessions.remove(&(source, destination));
+                self.sessions.remove(&(destination, source));
+                vec![]
+            }
+            // CTS and acknowledgements of transfers between other nodes
+            _ => vec![],
+        }
+    }
+
+    fn data_transfer(
+        &mut self,
+        key: (u8, u8),
+        data: &[u8],
+        now: time::Instant,
+    ) -> (Option<J1939Message>, Vec<Frame>) {
+        let session = match self.sessions.get_mut(&key) {
+            Some(s) => s,
+            None => return (None, vec![]),
+        };
+        let (source, destination) = key;
+        if data.is_empty() || data[0] != session.next {
+            let pgn = session.pgn;
+            let ours = session.window.is_some();
+            self.sessions.remove(&key);
+            if ours {
+                let b = [ABORT_BAD_SEQUENCE, 0xFF, 0xFF, 0xFF];
+                return (
+                    None,
+                    vec![cm_frame(
+                        CM_ABORT,
+                        b,
+                        pgn,
+                        destination,
+                        source,
+                        self.channel,
+                    )],
+                );
+            }
+            return (None, vec![]);
+        }
+
+        session.data.extend_from_slice(&data[1..]);
+        // short packets are not padded by every sender
+        session.data.resize(session.next as usize * 7, 0xFF);
+        session.deadline = now + T1;
+
+        if session.next == session.packets {
+            let mut session = self.sessions.remove(&key).unwrap();
+            session.data.truncate(session.size);
+            let mut replies = vec![];
+            if session.window.is_some() {
+                let size = session.size as u16;
+                let b = [size as u8, (size >> 8) as u8, session.packets, 0xFF];
+                replies.push(cm_frame(
+                    CM_END_OF_MSG_ACK,
+                    b,
+                    session.pgn,
+                    destination,
+                    source,
+                    self.channel,
+                ));
+            }
+            let msg = J1939Message {
+                priority: session.priority,
+                pgn: session.pgn,
+                source,
+                destination,
+                data: session.data,
+            };
+            return (Some(msg), replies);
+        }
+
+        session.next += 1;
+        if let Some((left, max_per_cts)) = session.window.as_mut() {
+            *left -= 1;
+            if *left == 0 {
+                // request the next window of packets
+                *left = std::cmp::min(session.packets - session.next + 1, *max_per_cts);
+                session.deadline = now + T2;
+                let b = [*left, session.next, 0xFF, 0xFF];
+                let cts = cm_frame(CM_CTS, b, session.pgn, destination, source, self.channel);
+                return (None, vec![cts]);
+            }
+        }
+        (None, vec![])
+    }
+
+    // drop transfers whose timer has expired, returning aborts to send for
+    // transfers to our address
+    fn expire(&mut self, now: time::Instant) -> Vec<Frame> {
+        let expired: Vec<(u8, u8)> = self
+            .sessions
+            .iter()
+            .filter(|(_, s)| s.deadline <= now)
+            .map(|(k, _)| *k)
+            .collect();
+        let mut replies = vec![];
+        for (source, destination) in expired {
+            let session = self.sessions.remove(&(source, destination)).unwrap();
+            if session.window.is_some() {
+                let b = [ABORT_TIMEOUT, 0xFF, 0xFF, 0xFF];
+                replies.push(cm_frame(
+                    CM_ABORT,
+                    b,
+                    session.pgn,
+                    destination,
+                    source,
+                    self.channel,
+                ));
+            }
+        }
+        replies
+    }
+
+    fn next_deadline(&self) -> Option<time::Instant> {
+        self.sessions.values().map(|s| s.deadline).min()
+    }
+}
+
+/// A stream of J1939 messages received on a channel, with multi-packet
+/// messages reassembled.
+///
+/// Broadcast (BAM) transfers are reassembled, as are RTS/CTS transfers
+/// between any nodes. When the stream has an address, RTS/CTS transfers to
+/// that address are answered with clear to send, acknowledgement and abort
+/// frames as the receiver; transfers to other addresses are followed
+/// passively. Transfers that stall or send packets out of sequence are
+/// dropped.
+pub struct J1939Stream<'a> {
+    i: &'a mut Interface,
+    rx: Receiver<Frame>,
+    tp: Reassembler,
+}
+
+impl<'a> J1939Stream<'a> {
+    /// Create a stream of the extended frames received on `channel`,
+    /// answering transfers to `address` if one is given.
+    ///
+    /// Frames are collected from the time the stream is created. The
+    /// interface must be started before receiving.
+    pub fn new(i: &'a mut Interface, channel: u8, address: Option<u8>) -> Self {
+        let rx = i.subscribe(move |f| {
+            f.ext && !f.err && f.channel == channel && f.origin != FrameOrigin::TxEcho
+        });
+        J1939Stream {
+            i,
+            rx,
+            tp: Reassembler::new(address, channel),
+        }
+    }
+
+    /// Receive the next complete message. Single frame messages are returned
+    /// as they arrive, and transport protocol messages once every packet has
+    /// been received. Returns `Error::Timeout` if no message completes within
+    /// `timeout`.
+    pub fn recv(&mut self, timeout: time::Duration) -> Result<J1939Message, Error> {
+        let deadline = time::Instant::now() + timeout;
+        loop {
+            let now = time::Instant::now();
+            for f in self.tp.expire(now) {
+                self.i.send(f)?;
+            }
+            if now >= deadline {
+                return Err(Error::Timeout);
+            }
+
+            // wake up for the transfer timers as well
+            let wake = self
+                .tp
+                .next_deadline()
+                .map_or(deadline, |d| d.min(deadline));
+            match self.rx.recv_timeout(wake.saturating_duration_since(now)) {
+                Ok(f) => {
+                    let (msg, replies) = self.tp.process(&f, time::Instant::now());
+                    for f in replies {
+                        self.i.send(f)?;
+                    }
+                    if let Some(msg) = msg {
+                        return Ok(msg);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::NotRunning),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HostFrame;
+
+    fn frame(id: J1939Id, data: &[u8]) -> Frame {
+        Frame::new_extended(id.to_can_id(), data).unwrap()
+    }
+
+    // ID, data and channel of frames, for comparing replies
+    fn wire(frames: &[Frame]) -> Vec<(u32, Vec<u8>, u8)> {
+        frames
+            .iter()
+            .map(|f| (f.can_id, f.data.clone(), f.channel))
+            .collect()
+    }
+
+    fn dt(source: u8, destination: u8, seq: u8, data: &[u8]) -> Frame {
+        let mut bs = vec![seq];
+        bs.extend_from_slice(data);
+        frame(J1939Id::new(7, PGN_TP_DT, destination, source), &bs)
+    }
+
+    #[test]
+    fn test_j1939_id() {
+        // EEC1 (PGN 61444) from the engine, priority 3
+        let id = J1939Id::from(0x0CF0_0400);
+        assert_eq!(id.priority, 3);
+        assert_eq!((id.pf, id.ps, id.sa), (0xF0, 0x04, 0x00));
+        assert_eq!(id.pdu_format(), PduFormat::Pdu2);
+        assert_eq!(id.pgn(), 61444);
+        assert_eq!(id.destination(), GLOBAL_ADDRESS);
+        assert_eq!(id.to_can_id(), 0x0CF0_0400);
+        assert_eq!(J1939Id::new(3, 61444, 0x12, 0x00), id);
+
+        // request (PGN 59904) from 0xF9 to 0x00, priority 6
+        let id = J1939Id::new(6, 59904, 0x00, 0xF9);
+        assert_eq!(id.to_can_id(), 0x18EA_00F9);
+        assert_eq!(id.pdu_format(), PduFormat::Pdu1);
+        assert_eq!(id.destination(), 0x00);
+        assert_eq!(id.pgn(), 59904);
+
+        let f = Frame::new_extended(0x18EA_00F9, &[]).unwrap();
+        assert_eq!(J1939Id::from_frame(&f), Some(id));
+        let f = Frame::new_standard(0x100, &[]).unwrap();
+        assert_eq!(J1939Id::from_frame(&f), None);
+
+        let id = J1939Id::from(0x1FFF_FFFF | 0xE000_0000);
+        assert!(id.edp && id.dp);
+        assert_eq!(id.to_can_id(), 0x1FFF_FFFF);
+    }
+
+    #[test]
+    fn test_bam() {
+        let mut tp = Reassembler::new(None, 0);
+        let now = time::Instant::now();
+        let bam = cm_frame(CM_BAM, [10, 0, 2, 0xFF], 0xFECA, 0x00, GLOBAL_ADDRESS, 0);
+        let (msg, replies) = tp.process(&bam, now);
+        assert!(msg.is_none() && replies.is_empty());
+        let (msg, replies) = tp.process(&dt(0x00, GLOBAL_ADDRESS, 1, &[1; 7]), now);
+        assert!(msg.is_none() && replies.is_empty());
+        let (msg, replies) = tp.process(&dt(0x00, GLOBAL_ADDRESS, 2, &[2, 2, 2]), now);
+        let msg = msg.unwrap();
+        assert!(replies.is_empty());
+        assert_eq!(
+            (msg.pgn, msg.source, msg.destination),
+            (0xFECA, 0x00, GLOBAL_ADDRESS)
+        );
+        assert_eq!(msg.data, vec![1, 1, 1, 1, 1, 1, 1, 2, 2, 2]);
+
+        // out of sequence packets drop the transfer
+        tp.process(&bam, now);
+        tp.process(&dt(0x00, GLOBAL_ADDRESS, 2, &[2; 7]), now);
+        assert!(tp.sessions.is_empty());
+
+        // stalled transfers expire
+        tp.process(&bam, now);
+        assert_eq!(tp.next_deadline(), Some(now + T1));
+        assert!(tp.expire(now + T1).is_empty());
+        assert!(tp.sessions.is_empty());
+
+        // single frames pass through
+        let f = frame(J1939Id::new(3, 61444, 0, 0x00), &[1, 2, 3]);
+        assert_eq!(tp.process(&f, now).0.unwrap().data, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_padded_frames() {
+        // frames received from a device carry the whole host frame data
+        let padded = |f: Frame| Frame::from_host_frame(HostFrame::from(&f));
+        let mut tp = Reassembler::new(None, 0);
+        let now = time::Instant::now();
+
+        let f = padded(frame(J1939Id::new(3, 61444, 0, 0x00), &[1, 2, 3]));
+        assert_eq!(f.data.len(), 64);
+        assert_eq!(tp.process(&f, now).0.unwrap().data, vec![1, 2, 3]);
+
+        let bam = cm_frame(CM_BAM, [10, 0, 2, 0xFF], 0xFECA, 0x00, GLOBAL_ADDRESS, 0);
+        tp.process(&padded(bam), now);
+        tp.process(&padded(dt(0x00, GLOBAL_ADDRESS, 1, &[1; 7])), now);
+        let last = padded(dt(0x00, GLOBAL_ADDRESS, 2, &[2, 2, 2]));
+        let msg = tp.process(&last, now).0.unwrap();
+        assert_eq!(msg.data, vec![1, 1, 1, 1, 1, 1, 1, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_rts_cts() {
+        let mut tp = Reassembler::new(Some(0x20), 1);
+        let now = time::Instant::now();
+        // 20 bytes in 3 packets, at most 2 packets per CTS
+        let rts = cm_frame(CM_RTS, [20, 0, 3, 2], 0xEF00, 0x10, 0x20, 1);
+        let (_, replies) = tp.process(&rts, now);
+        let cts = cm_frame(CM_CTS, [2, 1, 0xFF, 0xFF], 0xEF00, 0x20, 0x10, 1);
+        assert_eq!(wire(&replies), wire(&[cts]));
+        assert_eq!(replies[0].can_id, 0x1CEC_1020);
+
+        assert!(tp.process(&dt(0x10, 0x20, 1, &[1; 7]), now).1.is_empty());
+        let (_, replies) = tp.process(&dt(0x10, 0x20, 2, &[2; 7]), now);
+        let cts = cm_frame(CM_CTS, [1, 3, 0xFF, 0xFF], 0xEF00, 0x20, 0x10, 1);
+        assert_eq!(wire(&replies), wire(&[cts]));
+        let (msg, replies) = tp.process(&dt(0x10, 0x20, 3, &[3; 7]), now);
+        assert_eq!(msg.unwrap().data.len(), 20);
+        let ack = cm_frame(CM_END_OF_MSG_ACK, [20, 0, 3, 0xFF], 0xEF00, 0x20, 0x10, 1);
+        assert_eq!(wire(&replies), wire(&[ack]));
+
+        // a stalled transfer to our address is aborted
+        tp.process(&rts, now);
+        let abort = cm_frame(
+            CM_ABORT,
+            [ABORT_TIMEOUT, 0xFF, 0xFF, 0xFF],
+            0xEF00,
+            0x20,
+            0x10,
+            1,
+        );
+        assert_eq!(wire(&tp.expire(now + T2)), wire(&[abort]));
+
+        // transfers to other nodes are followed without replying
+        let rts = cm_frame(CM_RTS, [9, 0, 2, 0xFF], 0xEF00, 0x10, 0x30, 1);
+        assert!(tp.process(&rts, now).1.is_empty());
+        tp.process(&dt(0x10, 0x30, 1, &[1; 7]), now);
+        let (msg, replies) = tp.process(&dt(0x10, 0x30, 2, &[2; 7]), now);
+        assert_eq!(msg.unwrap().data, vec![1, 1, 1, 1, 1, 1, 1, 2, 2]);
+        assert!(replies.is_empty());
+    }
+}
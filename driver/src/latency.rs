@@ -0,0 +1,94 @@
+//! Statistics of transmit round trip latency measurements.
+
+use std::time;
+
+/// Round trip latencies measured by `Interface::measure_latency`, from
+/// submitting a frame to receiving its transmit confirmation.
+///
+/// All durations are zero if no frame was confirmed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LatencyStats {
+    /// Number of frames sent.
+    pub sent: usize,
+    /// Number of frames without a confirmation before the timeout.
+    pub lost: usize,
+    /// Shortest round trip.
+    pub min: time::Duration,
+    /// Mean round trip.
+    pub mean: time::Duration,
+    /// Longest round trip.
+    pub max: time::Duration,
+    /// Standard deviation of the round trips.
+    pub stddev: time::Duration,
+    /// Median round trip.
+    pub p50: time::Duration,
+    /// 90th percentile round trip.
+    pub p90: time::Duration,
+    /// 99th percentile round trip.
+    pub p99: time::Duration,
+}
+
+impl LatencyStats {
+    // compute statistics of the confirmed round trips
+    #[cfg(any(feature = "usb", test))]
+    pub(crate) fn from_samples(mut samples: Vec<time::Duration>, lost: usize) -> LatencyStats {
+        let mut stats = LatencyStats {
+            sent: samples.len() + lost,
+            lost,
+            ..Default::default()
+        };
+        if samples.is_empty() {
+            return stats;
+        }
+        samples.sort();
+
+        let n = samples.len() as f64;
+        let mean = samples.iter().map(|d| d.as_secs_f64()).sum::<f64>() / n;
+        let variance = samples
+            .iter()
+            .map(|d| (d.as_secs_f64() - mean).powi(2))
+            .sum::<f64>()
+            / n;
+
+        stats.min = samples[0];
+        stats.max = samples[samples.len() - 1];
+        stats.mean = time::Duration::from_secs_f64(mean);
+        stats.stddev = time::Duration::from_secs_f64(variance.sqrt());
+        stats.p50 = percentile(&samples, 50);
+        stats.p90 = percentile(&samples, 90);
+        stats.p99 = percentile(&samples, 99);
+        stats
+    }
+}
+
+// nearest rank percentile of sorted samples
+#[cfg(any(feature = "usb", test))]
+fn percentile(sorted: &[time::Duration], p: usize) -> time::Duration {
+    let rank = (p * sorted.len()).div_ceil(100);
+    sorted[rank.saturating_sub(1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_samples() {
+        let ms = time::Duration::from_millis;
+        let samples: Vec<time::Duration> = (1..=100).rev().map(ms).collect();
+        let stats = LatencyStats::from_samples(samples, 5);
+        assert_eq!(stats.sent, 105);
+        assert_eq!(stats.lost, 5);
+        assert_eq!(stats.min, ms(1));
+        assert_eq!(stats.max, ms(100));
+        assert_eq!(stats.p50, ms(50));
+        assert_eq!(stats.p90, ms(90));
+        assert_eq!(stats.p99, ms(99));
+        assert_eq!(stats.mean.as_micros(), 50_500);
+        assert_eq!(stats.stddev.as_micros() / 1000, 28);
+
+        let stats = LatencyStats::from_samples(vec![], 3);
+        assert_eq!(stats.sent, 3);
+        assert_eq!(stats.max, time::Duration::ZERO);
+    }
+}
@@ -7,21 +7,105 @@
 
 #![warn(missing_docs)]
 
-use std::fmt;
-use std::sync::{Arc, RwLock};
-use std::thread;
-use std::time;
-use std::default;
-
-use crossbeam_channel::RecvError;
-
 use serde::{Deserialize, Serialize};
 
+mod gsusb;
+pub use gsusb::{BitTiming, HostFrame, HostFrameBatch};
+
+#[cfg(feature = "usb")]
 mod device;
-use device::gsusb::*;
-use device::*;
 
+mod frame;
+pub use frame::{Direction, Frame, FrameOrigin, CAN_EFF_FLAG, CAN_ERR_FLAG, CAN_RTR_FLAG};
+
+#[cfg(feature = "usb")]
+mod builder;
+#[cfg(feature = "usb")]
+pub use builder::{DeviceSelector, InterfaceBuilder, TimestampSource};
+
+mod bus_error;
+pub use bus_error::{BusError, ErrorCounters};
+
+#[cfg(feature = "usb")]
+mod build_info;
+#[cfg(feature = "usb")]
+pub use build_info::{build_info, BuildInfo};
+
+mod capabilities;
+pub use capabilities::CapabilityReport;
+
+#[cfg(feature = "usb")]
+mod interface;
+#[cfg(feature = "usb")]
+pub use interface::Interface;
+
+mod config;
+pub use config::{Config, ConfigBuilder};
+
+mod filter;
+pub use filter::ChangeFilter;
+
+mod generator;
+pub use generator::FrameGenerator;
+
+mod identity;
+pub use identity::{DeviceIdentity, UsbInfo};
+
+mod snapshot;
+pub use snapshot::{ChannelSnapshot, InterfaceSnapshot};
+
+#[cfg(feature = "usb")]
+mod hotplug;
+#[cfg(feature = "usb")]
+pub use hotplug::{
+    describe_devices, list_devices, watch_hotplug, DeviceDescription, DeviceInfo, HotplugEvent,
+    HotplugWatch,
+};
+
+mod latency;
+pub use latency::LatencyStats;
+
+mod timing;
+pub use timing::{
+    calculate_bit_timing_with_options, calculate_data_bit_timing, BitTimingInfo, BitTimingLimits,
+    BitTimingOptions,
+};
+
+#[cfg(feature = "usb")]
+mod logger;
+#[cfg(feature = "usb")]
+pub use logger::{LogFormat, LogRotation};
+
+#[cfg(feature = "usb")]
+mod periodic;
+#[cfg(feature = "usb")]
+pub use periodic::{counter_crc8, counter_xor, PeriodicTx};
+
+#[cfg(feature = "usb")]
+mod rx;
+#[cfg(feature = "testing")]
+pub use rx::RxPath;
+
+#[cfg(feature = "usb")]
+mod tx;
+#[cfg(feature = "usb")]
+pub use tx::{FrameSender, SendResult};
+
+#[cfg(feature = "usb")]
 pub mod c;
+pub mod dbc;
+pub mod export;
+#[cfg(feature = "usb")]
+pub mod isotp;
+#[cfg(feature = "usb")]
+pub mod j1939;
+#[cfg(feature = "usb")]
+pub mod obd;
+#[cfg(feature = "usb")]
+pub mod server;
+#[cfg(all(feature = "usb", target_os = "linux"))]
+pub mod socketcan;
+pub mod slcan;
 /// Implementation of Python bindings
 #[cfg(feature = "python")]
 pub mod python;
@@ -30,10 +114,17 @@ pub mod python;
 #[derive(Debug)]
 pub enum Error {
     /// Errors from device interaction.
+    #[cfg(feature = "usb")]
     DeviceError(device::Error),
-    /// The device could not be found, or the user does not have permissions to access it.
+    /// The device could not be found.
     DeviceNotFound,
-    /// Timeout while communicating with the device.
+    /// The device was found, but the user does not have permission to open
+    /// it. The message describes the usual fix.
+    PermissionDenied(&'static str),
+    /// The device is already in use by another process or a kernel driver.
+    DeviceBusy,
+    /// Timeout while communicating with the device, or while waiting for a
+    /// frame.
     Timeout,
     /// Attempted to perform an action on a device that is running when this is not allowed.
     Running,
@@ -45,7 +136,16 @@ pub enum Error {
     InvalidBitrate(u32),
     /// The requested set of features is not supported by the device
     UnsupportedFeature(&'static str),
+    /// An argument was outside the range accepted by the device or protocol
+    InvalidArgument(&'static str),
+    /// The transmit window set with `Interface.set_tx_window` is full.
+    TxWindowFull,
+    /// The device was disconnected or failed while running. The interface
+    /// is stopped and must be recovered with `Interface.reset_device` or
+    /// opened again.
+    Disconnected,
 }
+#[cfg(feature = "usb")]
 impl From<device::Error> for Error {
     fn from(e: device::Error) -> Error {
         // TODO
@@ -54,156 +154,8 @@ impl From<device::Error> for Error {
     }
 }
 
-/// Controller Area Network Frame
-#[derive(Debug, Clone)]
-pub struct Frame {
-    /// CAN frame arbitration ID.
-    pub can_id: u32,
-
-    /// CAN frame Data Length Code (DLC).
-    pub can_dlc: u8,
-
-    /// Device channel used to send or receive the frame.
-    pub channel: u8,
-
-    /// Frame data contents.
-    pub data: Vec<u8>,
-
-    /// Extended (29 bit) arbitration identifier if true,
-    /// standard (11 bit) arbitration identifer if false.
-    pub ext: bool,
-
-    /// CAN Flexible Data (CAN-FD) frame flag.
-    pub fd: bool,
-
-    /// CAN-FD Bit Rate Switch (BRS) flag.
-    pub brs: bool,
-
-    /// CAN-FD Error State Indicator (ESI) flag.
-    pub esi: bool,
-
-    /// Loopback flag. When true, frame was sent by this device/channel.
-    /// False for received frames.
-    pub loopback: bool,
-
-    /// Error frame flag.
-    pub err: bool,
-
-    /// Remote Transmission Request (RTR) flag.
-    pub rtr: bool,
-
-    /// Timestamp when frame was received
-    pub timestamp: Option<time::Duration>,
-}
-
-impl default::Default for Frame {
-    /// Returns a default CAN frame with all values set to zero/false.
-    fn default() -> Frame {
-        Frame {
-            can_id: 0,
-            can_dlc: 0,
-            data: vec![0; 64],
-            channel: 0,
-            ext: false,
-            fd: false,
-            loopback: false,
-            rtr: false,
-            brs: false,
-            esi: false,
-            err: false,
-            timestamp: None,
-        }
-    }
-}
-
-impl Frame {
-    fn data_as_array(&self) -> [u8; 64] {
-        let mut data = [0u8; 64];
-        let len = std::cmp::min(self.data.len(), data.len());
-        data[..len].copy_from_slice(&self.data[..len]);
-        data
-    }
-    // convert to a frame format expected by the device
-    fn to_host_frame(&self) -> HostFrame {
-        // if frame is extended, set the extended bit in host frame CAN ID
-        let mut can_id = if self.ext {
-            self.can_id | GSUSB_EXT_FLAG
-        } else {
-            self.can_id
-        };
-        // apply RTR and ERR flags
-        can_id = if self.rtr {
-            can_id | GSUSB_RTR_FLAG
-        } else {
-            can_id
-        };
-        can_id = if self.err {
-            can_id | GSUSB_ERR_FLAG
-        } else {
-            can_id
-        };
-
-        HostFrame {
-            echo_id: 1,
-            flags: if self.fd { GS_CAN_FLAG_FD } else { 0 },
-            reserved: 0,
-            can_id,
-            can_dlc: self.can_dlc,
-            channel: self.channel,
-            data: self.data_as_array(),
-        }
-    }
-
-    fn from_host_frame(hf: HostFrame) -> Frame {
-        // check the extended bit of host frame
-        // if set, frame is extended
-        let ext = (hf.can_id & GSUSB_EXT_FLAG) > 0;
-        // check the RTR and ERR bits of host frame ID
-        let rtr = (hf.can_id & GSUSB_RTR_FLAG) > 0;
-        let err = (hf.can_id & GSUSB_ERR_FLAG) > 0;
-        // remove flags from CAN ID
-        let can_id = hf.can_id & 0x1FFF_FFFF;
-        // loopback frame if echo_id is not -1
-        let loopback = hf.echo_id != GSUSB_RX_ECHO_ID;
-        // apply FD flags
-        let fd = (hf.flags & GS_CAN_FLAG_FD) > 0;
-        let brs = (hf.flags & GS_CAN_FLAG_BRS) > 0;
-        let esi = (hf.flags & GS_CAN_FLAG_ESI) > 0;
-
-        Frame {
-            can_id,
-            can_dlc: hf.can_dlc,
-            data: hf.data.to_vec(),
-            channel: hf.channel,
-            ext,
-            loopback,
-            rtr,
-            fd,
-            brs,
-            esi,
-            err,
-            timestamp: None,
-        }
-    }
-
-    /// Return the length of data in this frame. This is the DLC for non-FD frames.
-    pub fn data_len(&self) -> usize {
-        match self.can_dlc {
-            0..=8 => self.can_dlc as usize,
-            9 => 12,
-            10 => 16,
-            11 => 20,
-            12 => 24,
-            13 => 32,
-            14 => 48,
-            15 => 64,
-            16..=u8::MAX => panic!("invalid DLC value"),
-        }
-    }
-}
-
 /// Configuration for a device's CAN channel.
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Channel {
     /// Bitrate of the channel in bits/second
     pub bitrate: u32,
@@ -222,418 +174,42 @@ pub struct Channel {
 
 }
 
-/// Interface for interacting with CANtact devices
-pub struct Interface {
-    dev: Device,
-    running: Arc<RwLock<bool>>,
-
-    can_clock: u32,
-    // zero indexed (0 = 1 channel, 1 = 2 channels, etc...)
-    channel_count: usize,
-    sw_version: u32,
-    hw_version: u32,
-    features: u32,
-
-    channels: Vec<Channel>,
-}
-
-impl fmt::Debug for Interface {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Interface")
-            .field("running", &(*self.running.read().unwrap()))
-            .field("can_clock", &self.can_clock)
-            .field("channel_count", &self.channel_count)
-            .field("sw_version", &self.sw_version)
-            .field("hw_version", &self.hw_version)
-            .field("channels", &self.channels)
-            .finish()
-    }
-}
-
-impl Interface {
-    /// Creates a new interface. This always selects the first device found by
-    /// libusb. If no device is found, Error::DeviceNotFound is returned.
-    pub fn new() -> Result<Interface, Error> {
-        let mut dev = match Device::new(UsbContext::new()) {
-            Ok(d) => d,
-            Err(_) => return Err(Error::DeviceNotFound),
-        };
-
-        let dev_config = dev.get_device_config()?;
-        let bt_consts = dev.get_bit_timing_consts()?;
-
-        let channel_count = dev_config.icount as usize;
-
-        let mut channels = Vec::new();
-        // note: channel_count is zero indexed
-        for _ in 0..(channel_count + 1) {
-            channels.push(Channel {
-                bitrate: 0,
-                enabled: true,
-                loopback: false,
-                monitor: false,
-                fd: false,
-                data_bitrate: 0,
-                started: false,
-            });
-        }
-
-        let i = Interface {
-            dev,
-            running: Arc::new(RwLock::from(false)),
-
-            channel_count,
-            can_clock: bt_consts.fclk_can,
-            sw_version: dev_config.sw_version,
-            hw_version: dev_config.hw_version,
-            features: bt_consts.feature,
-
-            channels,
-        };
-
-        Ok(i)
-    }
-
-    /// Start CAN communication on all configured channels.
+impl Channel {
+    /// Check that the channel's modes can be used together. This is done by
+    /// `Interface.start`, `Interface.set_loopback` and `Interface.set_monitor`.
     ///
-    /// After starting the device, `Interface.send` can be used to send frames.
-    /// For every received frame, the `rx_callback` closure will be called.
-    pub fn start(
-        &mut self,
-        channel: usize,
-        mut rx_callback: impl FnMut(Frame) + Sync + Send + 'static,
-    ) -> Result<(), Error> {
-        if channel > self.channel_count {
-          return Err(Error::InvalidChannel);
-        }
-
-        // tell the device to go on bus
-        let mut flags = 0;
-        // for each mode flag, check that the feature is supported before applying feature
-        // this is necessary since the feature flags are pub
-        if self.channels[channel].monitor {
-            if (self.features & GS_CAN_FEATURE_LISTEN_ONLY) == 0 {
-                return Err(Error::UnsupportedFeature("Monitor"));
-            }
-            flags |= GS_CAN_MODE_LISTEN_ONLY;
-        }
-        if self.channels[channel].loopback {
-            if (self.features & GS_CAN_FEATURE_LOOP_BACK) == 0 {
-                return Err(Error::UnsupportedFeature("Loopback"));
-            }
-            flags |= GS_CAN_MODE_LOOP_BACK;
-        }
-        if self.channels[channel].fd {
-            if !self.supports_fd() {
-                return Err(Error::UnsupportedFeature("FD"));
-            }
-            flags |= GS_CAN_MODE_FD;
-        }
-
-        let mode = Mode {
-            mode: CanMode::Start as u32,
-            flags,
-        };
-
-        if self.channels[channel].enabled {
-            self.dev.set_mode(channel as u16, mode).unwrap();
-        }
-
-        if !(self.channels[channel].started) {
-            self.channels[channel].started = true;
-        }
-
-        if !(*self.running.read().unwrap()) {
-
-            *self.running.write().unwrap() = true;
-
-            let can_rx = self.dev.can_rx_recv.clone();
-            let running = Arc::clone(&self.running);
-            let start_time = time::Instant::now();
-            thread::spawn(move || {
-                while *running.read().unwrap() {
-                    match can_rx.recv() {
-                        Ok(hf) => {
-                            let mut f = Frame::from_host_frame(hf);
-                            f.timestamp = Some(time::Instant::now().duration_since(start_time));
-                            rx_callback(f)
-                        }
-                        Err(RecvError) => {
-                            // channel disconnected
-                            break;
-                        }
-                    }
-                }
-            });
-            self.dev.start_transfers().unwrap();
-        }
-        Ok(())
-    }
-
-    /// Stop CAN communication on all channels.
-    pub fn stop(&mut self, channel: usize) -> Result<(), Error> {
-        // TODO multi-channel
-        if channel > self.channel_count {
-            return Err(Error::InvalidChannel);
-        }
-
-        if self.channels[channel].enabled {
-            let mode = Mode {
-                mode: CanMode::Reset as u32,
-                flags: 0,
-            };
-            self.dev.set_mode(channel as u16, mode).unwrap();
-            self.channels[channel].started = false;
-        }
-
-        for ch in self.channels.iter() {
-            if ch.started {
-               return Ok(());
-            }
-        }
-
-        self.dev.stop_transfers().unwrap();
-        *self.running.write().unwrap() = false;
-
-        Ok(())
-    }
-
-    /// Set bitrate for specified channel to requested bitrate value in bits per second.
-    pub fn set_bitrate(&mut self, channel: usize, bitrate: u32) -> Result<(), Error> {
-        if channel > self.channel_count {
-            return Err(Error::InvalidChannel);
-        }
-
-        let bt = calculate_bit_timing(self.can_clock, bitrate)?;
-        self.dev
-            .set_bit_timing(channel as u16, bt)
-            .expect("failed to set bit timing");
-
-        self.channels[channel].bitrate = bitrate;
-        Ok(())
-    }
-
-    /// Set CAN FD data bitrate for specified channel to requested bitrate value in bits per second.
-    pub fn set_data_bitrate(&mut self, channel: usize, bitrate: u32) -> Result<(), Error> {
-        if !self.supports_fd() {
-            return Err(Error::UnsupportedFeature("FD"));
-        }
-
-        if channel > self.channel_count {
-            return Err(Error::InvalidChannel);
-        }
-
-        let bt = calculate_bit_timing(self.can_clock, bitrate)?;
-        self.dev
-            .set_data_bit_timing(channel as u16, bt)
-            .expect("failed to set bit timing");
-
-        self.channels[channel].data_bitrate = bitrate;
-        Ok(())
-    }
-
-    /// Set a custom bit timing for the specified channel.
-    pub fn set_bit_timing(
-        &mut self,
-        channel: usize,
-        brp: u32,
-        phase_seg1: u32,
-        phase_seg2: u32,
-        sjw: u32,
-    ) -> Result<(), Error> {
-        let bt = BitTiming {
-            brp,
-            prop_seg: 0,
-            phase_seg1,
-            phase_seg2,
-            sjw,
-        };
-        self.dev
-            .set_bit_timing(channel as u16, bt)
-            .expect("failed to set bit timing");
-        Ok(())
-    }
-
-    /// Set a custom data bit timing for the specified channel.
-    pub fn set_data_bit_timing(
-        &mut self,
-        channel: usize,
-        brp: u32,
-        phase_seg1: u32,
-        phase_seg2: u32,
-        sjw: u32,
-    ) -> Result<(), Error> {
-        let bt = BitTiming {
-            brp,
-            prop_seg: 0,
-            phase_seg1,
-            phase_seg2,
-            sjw,
-        };
-        self.dev
-            .set_data_bit_timing(channel as u16, bt)
-            .expect("failed to set data bit timing");
-        Ok(())
-    }
-
-    /// Enable or disable a channel's listen only mode. When this mode is enabled,
-    /// the device will not transmit any frames, errors, or acknowledgements.
-    pub fn set_monitor(&mut self, channel: usize, enabled: bool) -> Result<(), Error> {
-        if self.features & GS_CAN_FEATURE_LISTEN_ONLY == 0 {
-            return Err(Error::UnsupportedFeature("Monitor"));
-        }
-        if channel > self.channel_count {
-            return Err(Error::InvalidChannel);
-        }
-        if *self.running.read().unwrap() && self.channels[channel].started {
-            return Err(Error::Running);
-        }
-
-        self.channels[channel].monitor = enabled;
-        Ok(())
-    }
-
-    /// Enable or disable a channel's listen only mode. When this mode is enabled,
-    /// the device will not transmit any frames, errors, or acknowledgements.
-    pub fn set_enabled(&mut self, channel: usize, enabled: bool) -> Result<(), Error> {
-        if channel > self.channel_count {
-            return Err(Error::InvalidChannel);
-        }
-        if *self.running.read().unwrap() && self.channels[channel].started {
-            return Err(Error::Running);
-        }
-
-        self.channels[channel].enabled = enabled;
-        Ok(())
-    }
-
-    /// Enable or disable a channel's loopback mode. When this mode is enabled,
-    /// frames sent by the device will be received by the device
-    /// *as if they had been sent by another node on the bus*.
+    /// The following combinations are rejected with
+    /// `Error::InvalidArgument`:
     ///
-    /// This mode is primarily intended for device testing!
-    pub fn set_loopback(&mut self, channel: usize, enabled: bool) -> Result<(), Error> {
-        if self.features & GS_CAN_FEATURE_LOOP_BACK == 0 {
-            return Err(Error::UnsupportedFeature("Loopback"));
-        }
-        if channel > self.channel_count {
-            return Err(Error::InvalidChannel);
-        }
-        if *self.running.read().unwrap() && self.channels[channel].started {
-            return Err(Error::Running);
-        }
-
-        self.channels[channel].loopback = enabled;
-        Ok(())
-    }
-
-    /// Enable or disable CAN FD support for a channel
-    pub fn set_fd(&mut self, channel: usize, enabled: bool) -> Result<(), Error> {
-        if !self.supports_fd() {
-            return Err(Error::UnsupportedFeature("FD"));
-        }
-        if channel > self.channel_count {
-            return Err(Error::InvalidChannel);
-        }
-        if *self.running.read().unwrap() && self.channels[channel].started {
-            return Err(Error::Running);
-        }
-
-        self.channels[channel].fd = enabled;
-        Ok(())
-    }
-
-    /// Returns true if device suports CAN-FD operation, false otherwise.
-    pub fn supports_fd(&self) -> bool {
-        (self.features & GS_CAN_FEATURE_FD) > 0
-    }
-
-    /// Send a CAN frame using the device
-    pub fn send(&mut self, f: Frame) -> Result<(), Error> {
-        if !*self.running.read().unwrap() {
-            return Err(Error::NotRunning);
+    /// - `loopback` with `monitor`: a listen only channel never transmits,
+    ///   so there is nothing to loop back.
+    pub fn check_modes(&self) -> Result<(), Error> {
+        if self.loopback && self.monitor {
+            return Err(Error::InvalidArgument(
+                "loopback and monitor modes cannot be enabled together",
+            ));
         }
-
-        self.dev.send(f.to_host_frame()).unwrap();
         Ok(())
     }
-
-    /// Returns the number of channels this Interface has
-    pub fn channels(&self) -> usize {
-        self.channel_count + 1
-    }
 }
 
-fn calculate_bit_timing(clk: u32, bitrate: u32) -> Result<BitTiming, Error> {
-    let max_brp = 1024;
-    let min_seg1 = 1;
-    let max_seg1 = 16;
-    let min_seg2 = 1;
-    let max_seg2 = 8;
-    let tolerances = vec![0.0, 0.1 / 100.0, 0.5 / 100.0];
-
-    for tolerance in tolerances {
-        let tmp = clk as f32 / bitrate as f32;
-        for brp in 1..(max_brp + 1) {
-            let btq = tmp / brp as f32;
-            let btq_rounded = btq.round() as u32;
-
-            if (4..=32).contains(&btq_rounded) {
-                let err = ((btq / (btq_rounded as f32) - 1.0) * 10000.0).round() / 10000.0;
-                if err.abs() > tolerance {
-                    // error is not acceptable
-                    continue;
-                }
-            }
-
-            for seg1 in min_seg1..max_seg1 {
-                // subtract 1 from seg2 to account for propagation phase
-                if btq_rounded < (seg1 + 1) {
-                    break;
-                } else {
-                    let seg2 = btq_rounded - (seg1 + 1);
-                    if seg2 < min_seg2 || seg2 > max_seg2 {
-                        // invalid seg2 value
-                        continue;
-                    }
-                    // brp, seg1, and seg2 are all valid
-                    return Ok(BitTiming {
-                        brp,
-                        prop_seg: 0,
-                        phase_seg1: seg1,
-                        phase_seg2: seg2,
-                        sjw: 1,
-                    });
-                }
-            }
-        }
-    }
-
-    Err(Error::InvalidBitrate(bitrate))
-}
-
-#[allow(dead_code)]
-fn effective_bitrate(clk: u32, bt: BitTiming) -> u32 {
-    clk / bt.brp / (bt.prop_seg + bt.phase_seg1 + bt.phase_seg2 + 1)
-}
+/// Result of an operation on one channel, paired with the channel index.
+pub type ChannelResult = (usize, Result<(), Error>);
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    #[test]
-    fn test_bit_timing() {
-        let clk = 120000000;
-        let bitrates = vec![4_020_100, 4_000_000, 3_000_000, 2_400_000, 2_000_000, 1_500_000, 1_200_000, 1_000_000,
-                                    800_000, 600_000, 520_000, 500_000, 420_000, 400_000, 320_000, 300_000, 250_000, 200_000, 125_000, 120_000, 100_000,
-                                    90_000, 80_000, 70_000, 60_000, 50_000, 40_000, 33_333, 30_000, 29_628];
-        for b in bitrates {
-            let bt = calculate_bit_timing(clk, b).unwrap();
 
-            // ensure error < 0.5%
-            println!("{:?}", &bt);
-            let err = 100.0 * (1.0 - (effective_bitrate(clk, bt) as f32 / b as f32).abs());
-            println!("{:?}", err);
-            assert!(err < 0.5);
-        }
+    #[test]
+    fn test_check_modes() {
+        let mut ch = Channel {
+            loopback: true,
+            ..Default::default()
+        };
+        assert!(ch.check_modes().is_ok());
+        ch.monitor = true;
+        assert!(matches!(ch.check_modes(), Err(Error::InvalidArgument(_))));
+        ch.loopback = false;
+        assert!(ch.check_modes().is_ok());
     }
 }
@@ -0,0 +1,262 @@
+//! Logging of received frames to rotating files from a background thread.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time;
+
+use crossbeam_channel::{bounded, select, tick, unbounded, Receiver, Sender};
+use log::warn;
+
+use crate::export::{format_asc, format_candump, format_csv, format_json, ASC_HEADER, CSV_HEADER};
+use crate::Frame;
+
+// buffered lines are written out at least this often
+const FLUSH_INTERVAL: time::Duration = time::Duration::from_secs(1);
+// how long a flush request waits for the writer
+const FLUSH_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+/// File format of a frame log, see `Interface::enable_logging`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// candump log lines, as written by `candump -L`.
+    Candump,
+    /// Vector ASC.
+    Asc,
+    /// CSV, as written by `export::write_csv`.
+    Csv,
+    /// Newline delimited JSON, as written by `export::write_ndjson`.
+    Ndjson,
+}
+
+impl LogFormat {
+    fn header(self) -> Option<&'static str> {
+        match self {
+            LogFormat::Asc => Some(ASC_HEADER),
+            LogFormat::Csv => Some(CSV_HEADER),
+            LogFormat::Candump | LogFormat::Ndjson => None,
+        }
+    }
+
+    fn format(self, f: &Frame) -> String {
+        match self {
+            LogFormat::Candump => format_candump(f),
+            LogFormat::Asc => format_asc(f),
+            LogFormat::Csv => format_csv(f),
+            LogFormat::Ndjson => format_json(f),
+        }
+    }
+}
+
+/// When a frame log is rotated. The default never rotates.
+///
+/// On rotation the log file is renamed with the next unused numeric suffix,
+/// such as `can.log.1`, and a new file is started at the original path.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LogRotation {
+    /// Rotate once the file reaches this many bytes.
+    pub max_bytes: Option<u64>,
+    /// Rotate once the file has been open this long.
+    pub max_age: Option<time::Duration>,
+}
+
+// writes formatted frames to the log file, rotating it as configured
+struct LogWriter {
+    path: PathBuf,
+    format: LogFormat,
+    rotation: LogRotation,
+    file: BufWriter<File>,
+    written: u64,
+    opened: time::Instant,
+}
+
+impl LogWriter {
+    fn new(path: &Path, format: LogFormat, rotation: LogRotation) -> io::Result<LogWriter> {
+        let mut w = LogWriter {
+            path: path.to_path_buf(),
+            format,
+            rotation,
+            file: BufWriter::new(File::create(path)?),
+            written: 0,
+            opened: time::Instant::now(),
+        };
+        w.write_header()?;
+        Ok(w)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if let Some(header) = self.format.header() {
+            self.write_line(header)?;
+        }
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.file, "{}", line)?;
+        self.written += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    fn write(&mut self, f: &Frame) -> io::Result<()> {
+        if self.rotation_due() {
+            self.rotate()?;
+        }
+        let line = self.format.format(f);
+        self.write_line(&line)
+    }
+
+    fn rotation_due(&self) -> bool {
+        let full = self
+            .rotation
+            .max_bytes
+            .is_some_and(|max| self.written >= max);
+        let old = self
+            .rotation
+            .max_age
+            .is_some_and(|max| self.opened.elapsed() >= max);
+        full || old
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let mut n = 1;
+        let rotated = loop {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            let rotated = PathBuf::from(name);
+            if !rotated.exists() {
+                break rotated;
+            }
+            n += 1;
+        };
+        fs::rename(&self.path, rotated)?;
+        self.file = BufWriter::new(File::create(&self.path)?);
+        self.written = 0;
+        self.opened = time::Instant::now();
+        self.write_header()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// a thread writing frames from a receiver to a log file. the file is flushed
+// periodically, on request, and when the logger is dropped.
+pub(crate) struct FrameLogger {
+    flush: Option<Sender<Sender<()>>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl FrameLogger {
+    pub(crate) fn new(
+        frames: Receiver<Frame>,
+        path: &Path,
+        format: LogFormat,
+        rotation: LogRotation,
+    ) -> io::Result<FrameLogger> {
+        let mut w = LogWriter::new(path, format, rotation)?;
+        let path = path.to_path_buf();
+        let (flush, flush_requests) = unbounded::<Sender<()>>();
+        let thread = thread::spawn(move || {
+            // fires under steady traffic too, unlike a select timeout
+            let flush_timer = tick(FLUSH_INTERVAL);
+            let report = |r: io::Result<()>| {
+                if let Err(e) = r {
+                    warn!("writing frame log {:?} failed: {}", path, e);
+                }
+            };
+            loop {
+                select! {
+                    recv(frames) -> f => match f {
+                        Ok(f) => report(w.write(&f)),
+                        // the interface was dropped
+                        Err(_) => break,
+                    },
+                    recv(flush_requests) -> done => match done {
+                        Ok(done) => {
+                            report(w.flush());
+                            let _ = done.send(());
+                        }
+                        // the logger was dropped
+                        Err(_) => break,
+                    },
+                    recv(flush_timer) -> _ => report(w.flush()),
+                }
+            }
+            // frames received before the logger was dropped are kept
+            while let Ok(f) = frames.try_recv() {
+                report(w.write(&f));
+            }
+            report(w.flush());
+        });
+        Ok(FrameLogger {
+            flush: Some(flush),
+            thread: Some(thread),
+        })
+    }
+
+    // write out buffered lines, waiting briefly for the writer
+    pub(crate) fn flush(&self) {
+        if let Some(flush) = &self.flush {
+            let (done, wait) = bounded(1);
+            if flush.send(done).is_ok() {
+                let _ = wait.recv_timeout(FLUSH_TIMEOUT);
+            }
+        }
+    }
+}
+
+impl Drop for FrameLogger {
+    fn drop(&mut self) {
+        // closing the request channel ends the thread
+        self.flush.take();
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("cantact-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("can.log");
+
+        // each candump line of this frame is 25 bytes
+        let f = Frame::new_standard(0x123, &[0xAA, 0xBB]).unwrap();
+        let rotation = LogRotation {
+            max_bytes: Some(50),
+            ..Default::default()
+        };
+        let mut w = LogWriter::new(&path, LogFormat::Candump, rotation).unwrap();
+        for _ in 0..5 {
+            w.write(&f).unwrap();
+        }
+        w.flush().unwrap();
+
+        let lines = |p: &Path| fs::read_to_string(p).unwrap().lines().count();
+        assert_eq!(lines(&dir.join("can.log.1")), 2);
+        assert_eq!(lines(&dir.join("can.log.2")), 2);
+        assert_eq!(lines(&path), 1);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "(0.000000) can0 123#AABB\n"
+        );
+
+        // formats with a header start each file with it
+        let w = LogWriter::new(&path, LogFormat::Csv, LogRotation::default());
+        drop(w);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", CSV_HEADER)
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
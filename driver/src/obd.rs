@@ -0,0 +1,152 @@
+//! OBD-II (SAE J1979) requests over ISO-TP.
+//!
+//! Requests are sent to the functional broadcast address and the response
+//! of the first ECU (the engine controller in most vehicles) is returned.
+
+use crate::isotp::{IsoTpChannel, IsoTpConfig, IsoTpError};
+use crate::Interface;
+
+/// Functional request ID, received by all OBD-II ECUs.
+pub const OBD_BROADCAST_ID: u32 = 0x7DF;
+/// Physical request ID of the first ECU.
+pub const OBD_ECU_REQUEST_ID: u32 = 0x7E0;
+/// Response ID of the first ECU.
+pub const OBD_ECU_RESPONSE_ID: u32 = 0x7E8;
+
+/// Service 01: show current data.
+pub const SERVICE_CURRENT_DATA: u8 = 0x01;
+/// Service 09: request vehicle information.
+pub const SERVICE_VEHICLE_INFO: u8 = 0x09;
+
+/// Service 01 PID for engine coolant temperature.
+pub const PID_COOLANT_TEMP: u8 = 0x05;
+/// Service 01 PID for engine speed.
+pub const PID_ENGINE_RPM: u8 = 0x0C;
+/// Service 01 PID for vehicle speed.
+pub const PID_VEHICLE_SPEED: u8 = 0x0D;
+/// Service 09 PID for the vehicle identification number.
+pub const PID_VIN: u8 = 0x02;
+
+// response service IDs are the request service ID plus this offset
+const POSITIVE_RESPONSE_OFFSET: u8 = 0x40;
+const NEGATIVE_RESPONSE: u8 = 0x7F;
+// negative response code: request received, response pending
+const NRC_RESPONSE_PENDING: u8 = 0x78;
+
+/// Errors generated by OBD-II requests
+#[derive(Debug)]
+pub enum ObdError {
+    /// Error from the ISO-TP transport.
+    Transport(IsoTpError),
+    /// The ECU rejected the request with the given negative response code.
+    NegativeResponse(u8),
+    /// The response could not be decoded.
+    InvalidResponse,
+}
+impl From<IsoTpError> for ObdError {
+    fn from(e: IsoTpError) -> ObdError {
+        ObdError::Transport(e)
+    }
+}
+
+/// Request a PID from the given service, returning the data bytes of the
+/// response following the PID.
+///
+/// The interface must be started on channel 0.
+pub fn request_pid(i: &mut Interface, service: u8, pid: u8) -> Result<Vec<u8>, ObdError> {
+    let mut tp = IsoTpChannel::new(
+        i,
+        OBD_BROADCAST_ID,
+        OBD_ECU_RESPONSE_ID,
+        IsoTpConfig::default(),
+    );
+    tp.send(&[service, pid])?;
+    // flow control for a segmented response is sent to the responding ECU
+    tp.set_tx_id(OBD_ECU_REQUEST_ID);
+
+    loop {
+        let response = tp.recv()?;
+        match response.as_slice() {
+            [NEGATIVE_RESPONSE, s, NRC_RESPONSE_PENDING] if *s == service => {}
+            [NEGATIVE_RESPONSE, s, nrc] if *s == service => {
+                return Err(ObdError::NegativeResponse(*nrc))
+            }
+            [s, p, data @ ..] if *s == service + POSITIVE_RESPONSE_OFFSET && *p == pid => {
+                return Ok(data.to_vec())
+            }
+            _ => return Err(ObdError::InvalidResponse),
+        }
+    }
+}
+
+/// Decode engine speed in revolutions per minute.
+pub fn decode_engine_rpm(data: &[u8]) -> Option<f32> {
+    match data {
+        [a, b, ..] => Some(((*a as u32 * 256 + *b as u32) as f32) / 4.0),
+        _ => None,
+    }
+}
+
+/// Decode vehicle speed in km/h.
+pub fn decode_vehicle_speed(data: &[u8]) -> Option<u8> {
+    data.first().copied()
+}
+
+/// Decode engine coolant temperature in degrees Celsius.
+pub fn decode_coolant_temp(data: &[u8]) -> Option<i16> {
+    data.first().map(|a| *a as i16 - 40)
+}
+
+/// Decode the vehicle identification number.
+pub fn decode_vin(data: &[u8]) -> Option<String> {
+    // the VIN may be preceded by a data item count
+    let vin = match data.len() {
+        17 => data,
+        18 => &data[1..],
+        _ => return None,
+    };
+    String::from_utf8(vin.to_vec()).ok()
+}
+
+/// Read the engine speed in revolutions per minute.
+pub fn read_engine_rpm(i: &mut Interface) -> Result<f32, ObdError> {
+    let data = request_pid(i, SERVICE_CURRENT_DATA, PID_ENGINE_RPM)?;
+    decode_engine_rpm(&data).ok_or(ObdError::InvalidResponse)
+}
+
+/// Read the vehicle speed in km/h.
+pub fn read_vehicle_speed(i: &mut Interface) -> Result<u8, ObdError> {
+    let data = request_pid(i, SERVICE_CURRENT_DATA, PID_VEHICLE_SPEED)?;
+    decode_vehicle_speed(&data).ok_or(ObdError::InvalidResponse)
+}
+
+/// Read the engine coolant temperature in degrees Celsius.
+pub fn read_coolant_temp(i: &mut Interface) -> Result<i16, ObdError> {
+    let data = request_pid(i, SERVICE_CURRENT_DATA, PID_COOLANT_TEMP)?;
+    decode_coolant_temp(&data).ok_or(ObdError::InvalidResponse)
+}
+
+/// Read the vehicle identification number.
+pub fn read_vin(i: &mut Interface) -> Result<String, ObdError> {
+    let data = request_pid(i, SERVICE_VEHICLE_INFO, PID_VIN)?;
+    decode_vin(&data).ok_or(ObdError::InvalidResponse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoders() {
+        assert_eq!(decode_engine_rpm(&[0x1A, 0xF8]), Some(1726.0));
+        assert_eq!(decode_engine_rpm(&[0x1A]), None);
+        assert_eq!(decode_vehicle_speed(&[0x3C]), Some(60));
+        assert_eq!(decode_coolant_temp(&[0x28]), Some(0));
+        assert_eq!(decode_coolant_temp(&[0x00]), Some(-40));
+        assert_eq!(
+            decode_vin(b"\x011HGCM82633A004352").as_deref(),
+            Some("1HGCM82633A004352")
+        );
+        assert_eq!(decode_vin(b"1HGCM8263"), None);
+    }
+}
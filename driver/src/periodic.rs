@@ -0,0 +1,158 @@
+//! Periodic transmission of frames from a background thread.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+use log::warn;
+
+use crate::{Error, Frame, FrameSender};
+
+/// A frame being sent periodically, returned by `Interface::send_periodic`.
+///
+/// Transmission stops when the handle is dropped. Periods that pass while
+/// the device is stopped are skipped.
+pub struct PeriodicTx {
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl PeriodicTx {
+    pub(crate) fn new(
+        sender: FrameSender,
+        mut f: Frame,
+        period: time::Duration,
+        mut update: impl FnMut(&mut Frame) + Send + 'static,
+    ) -> PeriodicTx {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let thread = thread::spawn(move || {
+            let mut next = time::Instant::now();
+            while !thread_stop.load(Ordering::SeqCst) {
+                let now = time::Instant::now();
+                if now < next {
+                    // unparked when the handle is dropped
+                    thread::park_timeout(next - now);
+                    continue;
+                }
+
+                update(&mut f);
+                match sender.send(f.clone()) {
+                    Ok(_) | Err(Error::NotRunning) => {}
+                    Err(e) => warn!("periodic transmit of {:#x} failed: {:?}", f.can_id, e),
+                }
+                // deadlines are kept on a fixed grid so sends do not drift,
+                // but missed periods are skipped rather than sent in a burst
+                next += period;
+                if next < now {
+                    next = now + period;
+                }
+            }
+        });
+        PeriodicTx {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for PeriodicTx {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(t) = self.thread.take() {
+            t.thread().unpark();
+            let _ = t.join();
+        }
+    }
+}
+
+/// Returns an update function for `Interface::send_periodic_with` that
+/// keeps a 4 bit alive counter in the low nibble of data byte `counter`,
+/// and stores the XOR of the other data bytes in byte `checksum`.
+///
+/// The counter is incremented before every transmission, wrapping from 15
+/// to 0, and the checksum covers the updated counter. Bytes outside the
+/// frame's data are ignored.
+pub fn counter_xor(counter: usize, checksum: usize) -> impl FnMut(&mut Frame) + Send {
+    move |f| {
+        bump_counter(f, counter);
+        let sum = checksum_bytes(f, checksum).fold(0, |acc, b| acc ^ b);
+        if let Some(b) = f.data.get_mut(checksum) {
+            *b = sum;
+        }
+    }
+}
+
+/// Returns an update function for `Interface::send_periodic_with` that
+/// keeps a 4 bit alive counter like `counter_xor`, and stores a CRC-8 SAE
+/// J1850 of the other data bytes in byte `checksum`, as used by AUTOSAR E2E
+/// profile 1.
+pub fn counter_crc8(counter: usize, checksum: usize) -> impl FnMut(&mut Frame) + Send {
+    move |f| {
+        bump_counter(f, counter);
+        let crc = crc8_sae_j1850(checksum_bytes(f, checksum));
+        if let Some(b) = f.data.get_mut(checksum) {
+            *b = crc;
+        }
+    }
+}
+
+fn bump_counter(f: &mut Frame, counter: usize) {
+    if let Some(b) = f.data.get_mut(counter) {
+        *b = (*b & 0xF0) | (b.wrapping_add(1) & 0x0F);
+    }
+}
+
+// the data bytes of a frame other than the checksum byte
+fn checksum_bytes(f: &Frame, checksum: usize) -> impl Iterator<Item = u8> + '_ {
+    f.payload()
+        .iter()
+        .enumerate()
+        .filter(move |(n, _)| *n != checksum)
+        .map(|(_, b)| *b)
+}
+
+// CRC-8 with polynomial 0x1D, initial value and final XOR 0xFF
+fn crc8_sae_j1850(bytes: impl Iterator<Item = u8>) -> u8 {
+    let mut crc: u8 = 0xFF;
+    for b in bytes {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x1D
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc ^ 0xFF
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter_checksums() {
+        assert_eq!(crc8_sae_j1850(b"123456789".iter().copied()), 0x4B);
+
+        let mut f = Frame::new_standard(0x100, &[0x00, 0xAE, 0x12, 0x34]).unwrap();
+        let mut update = counter_xor(1, 0);
+        update(&mut f);
+        assert_eq!(f.data, vec![0xAF ^ 0x12 ^ 0x34, 0xAF, 0x12, 0x34]);
+        update(&mut f);
+        // the counter wraps without touching the high nibble
+        assert_eq!(f.data[1], 0xA0);
+        assert_eq!(f.data[0], 0xA0 ^ 0x12 ^ 0x34);
+
+        let mut f = Frame::new_standard(0x100, &[0x00, 0x00, 0x12]).unwrap();
+        counter_crc8(1, 0)(&mut f);
+        assert_eq!(f.data[0], crc8_sae_j1850([0x01, 0x12].iter().copied()));
+
+        // out of range bytes are ignored
+        let mut f = Frame::new_standard(0x100, &[0x05]).unwrap();
+        counter_xor(4, 5)(&mut f);
+        assert_eq!(f.data, vec![0x05]);
+    }
+}
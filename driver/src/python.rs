@@ -1,5 +1,5 @@
 use crate::Error;
-use crate::{Frame, Interface};
+use crate::{Frame, FrameOrigin, Interface};
 use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
 use pyo3::exceptions;
 use pyo3::prelude::*;
@@ -159,11 +159,13 @@ impl PyInterface {
             data: data_array,
             channel,
             loopback: false,
+            origin: FrameOrigin::Received,
             fd: false,
             brs: false,
             err: false,
             esi: false,
             timestamp: None,
+            raw_can_id: None,
         })?;
         Ok(())
     }
@@ -193,11 +195,13 @@ impl PyInterface {
             data: data_array,
             channel,
             loopback: false,
+            origin: FrameOrigin::Received,
             fd,
             brs,
             err: false,
             esi: false,
             timestamp: None,
+            raw_can_id: None,
         })?;
         Ok(())
     }
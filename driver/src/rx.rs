@@ -0,0 +1,508 @@
+//! Receive path shared by the receive thread and polling mode.
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::time;
+
+use crossbeam_channel::Sender;
+
+use crate::gsusb::{HostFrame, GSUSB_RX_ECHO_ID};
+use crate::tx::{Completions, TxWindow, WINDOW_ECHO_ID};
+use crate::{ErrorCounters, Frame, FrameOrigin};
+
+/// Receive settings, and the error counters kept by the receive path, shared
+/// between the `Interface` and the receive path.
+pub(crate) struct RxConfig {
+    // channels started in hardware loopback mode
+    pub(crate) loopback: Vec<bool>,
+    // when false, frames are not timestamped
+    pub(crate) timestamping: bool,
+    // when false, transmit confirmations only go to taps
+    pub(crate) tx_echoes: bool,
+    // when false, transmit echoes and loopback frames only go to taps
+    pub(crate) deliver_echoes: bool,
+    // when true, received frames keep the unmasked host CAN ID
+    pub(crate) raw_can_ids: bool,
+    // (standard, extended) ID types accepted on each channel
+    pub(crate) id_types: Vec<(bool, bool)>,
+    // conditions reported by error frames on each channel
+    pub(crate) error_counters: Vec<ErrorCounters>,
+}
+
+impl RxConfig {
+    pub(crate) fn new(channel_count: usize) -> RxConfig {
+        RxConfig {
+            loopback: vec![false; channel_count],
+            timestamping: true,
+            tx_echoes: true,
+            deliver_echoes: true,
+            raw_can_ids: false,
+            id_types: vec![(true, true); channel_count],
+            error_counters: vec![ErrorCounters::default(); channel_count],
+        }
+    }
+}
+
+// convert a hardware timestamp in device ticks to a duration
+fn ticks_to_duration(ticks: u64, freq: u32) -> time::Duration {
+    let micros = (ticks as u128 * 1_000_000) / freq as u128;
+    time::Duration::from_micros(micros as u64)
+}
+
+// timestamps are reported in whole microseconds, the resolution of gs_usb
+// hardware timestamps, so host and hardware timestamps look alike
+fn truncate_to_micros(d: time::Duration) -> time::Duration {
+    time::Duration::from_micros(d.as_micros() as u64)
+}
+
+// extends the device's 32 bit timestamp counter to 64 bits. the counter
+// wraps every 2^32 ticks (about 71 minutes at 1 MHz); a raw value more than
+// half the counter range behind the latest one means the counter has wrapped,
+// so 2^32 is added to every following value. a smaller step back is a frame
+// reordered against the latest one, such as a transmit echo and a received
+// frame, and is returned as the slightly earlier time. this keeps timestamps
+// correct as long as frames arrive at least once per half wrap period.
+#[derive(Default)]
+struct TimestampExtender {
+    last: Option<u32>,
+    high: u64,
+}
+
+impl TimestampExtender {
+    fn extend(&mut self, raw: u32) -> u64 {
+        if let Some(last) = self.last {
+            let behind = last.wrapping_sub(raw);
+            if behind > 0 && behind <= 1 << 31 {
+                // the latest value is kept, so a reorder does not move the
+                // wrap detection back
+                return (self.high + last as u64).saturating_sub(behind as u64);
+            }
+            if raw < last {
+                self.high += 1 << 32;
+            }
+        }
+        self.last = Some(raw);
+        self.high + raw as u64
+    }
+}
+
+/// Detects a receive pipeline that has stopped delivering transfers.
+pub(crate) struct RxWatchdog {
+    timeout: time::Duration,
+    activity: u64,
+    idle_since: time::Instant,
+}
+
+impl RxWatchdog {
+    pub(crate) fn new(timeout: time::Duration, activity: u64, now: time::Instant) -> RxWatchdog {
+        RxWatchdog {
+            timeout,
+            activity,
+            idle_since: now,
+        }
+    }
+
+    /// Returns true if the activity count has not changed for the timeout.
+    /// The timeout then starts again, so a restart is not repeated at once.
+    pub(crate) fn expired(&mut self, activity: u64, now: time::Instant) -> bool {
+        if activity != self.activity {
+            self.activity = activity;
+            self.idle_since = now;
+            return false;
+        }
+        if now.duration_since(self.idle_since) < self.timeout {
+            return false;
+        }
+        self.idle_since = now;
+        true
+    }
+}
+
+/// A receiver of copies of received frames, in addition to the callback.
+pub(crate) struct Tap {
+    // delivers a frame if it is wanted, returning false once the receiver
+    // has gone away
+    deliver: Box<dyn Fn(&Frame) -> bool + Send + Sync>,
+}
+
+impl Tap {
+    pub(crate) fn new(
+        filter: impl Fn(&Frame) -> bool + Send + Sync + 'static,
+        sender: Sender<Frame>,
+    ) -> Tap {
+        Tap::map(move |f| if filter(f) { Some(f.clone()) } else { None }, sender)
+    }
+
+    /// A tap that sends `map` of each frame for which it returns `Some`.
+    pub(crate) fn map<T: Send + 'static>(
+        map: impl Fn(&Frame) -> Option<T> + Send + Sync + 'static,
+        sender: Sender<T>,
+    ) -> Tap {
+        Tap {
+            deliver: Box::new(move |f| match map(f) {
+                Some(t) => sender.send(t).is_ok(),
+                None => true,
+            }),
+        }
+    }
+}
+
+/// Converts host frames received from the device into `Frame`s.
+pub(crate) struct RxPipeline {
+    start_time: time::Instant,
+    timestamp_frequency: Option<u32>,
+    timestamps: TimestampExtender,
+    config: Arc<RwLock<RxConfig>>,
+    taps: Arc<Mutex<Vec<Tap>>>,
+    completions: Option<Arc<Mutex<Completions>>>,
+    tx_window: Option<Arc<TxWindow>>,
+}
+
+impl RxPipeline {
+    pub(crate) fn new(
+        start_time: time::Instant,
+        timestamp_frequency: Option<u32>,
+        config: Arc<RwLock<RxConfig>>,
+        taps: Arc<Mutex<Vec<Tap>>>,
+    ) -> RxPipeline {
+        RxPipeline {
+            start_time,
+            timestamp_frequency,
+            timestamps: TimestampExtender::default(),
+            config,
+            taps,
+            completions: None,
+            tx_window: None,
+        }
+    }
+
+    /// Report transmit confirmations of queued frames to `completions`.
+    pub(crate) fn with_completions(mut self, completions: Arc<Mutex<Completions>>) -> RxPipeline {
+        self.completions = Some(completions);
+        self
+    }
+
+    /// Free a slot of `tx_window` for each confirmation of a frame sent
+    /// within the window.
+    pub(crate) fn with_tx_window(mut self, tx_window: Arc<TxWindow>) -> RxPipeline {
+        self.tx_window = Some(tx_window);
+        self
+    }
+
+    /// Convert a host frame, returning `None` if the frame should not be
+    /// delivered.
+    pub(crate) fn process(&mut self, hf: HostFrame) -> Option<Frame> {
+        let mut f = Frame::default();
+        if self.process_into(&hf, &mut f) {
+            Some(f)
+        } else {
+            None
+        }
+    }
+
+    /// Convert a host frame into an existing frame, reusing its data buffer.
+    /// Returns false if the frame should not be delivered.
+    pub(crate) fn process_into(&mut self, hf: &HostFrame, f: &mut Frame) -> bool {
+        let hw_timestamp = hf.timestamp;
+        f.set_from_host_frame(hf);
+
+        if let Some(completions) = &self.completions {
+            if hf.echo_id != GSUSB_RX_ECHO_ID {
+                completions.lock().unwrap().confirm(hf.echo_id, time::Instant::now());
+            }
+        }
+        if let Some(tx_window) = &self.tx_window {
+            if hf.echo_id == WINDOW_ECHO_ID {
+                tx_window.release();
+            }
+        }
+
+        if f.err {
+            // error frames are rare enough to take the write lock for
+            let mut config = self.config.write().unwrap();
+            if let Some(counters) = config.error_counters.get_mut(f.channel as usize) {
+                counters.record(f);
+            }
+        }
+
+        let config = self.config.read().unwrap();
+        if config.raw_can_ids {
+            f.raw_can_id = Some(hf.can_id);
+        }
+        // wraps are tracked even when timestamps are not reported, so they
+        // stay monotonic if timestamping is enabled again
+        let timestamps = &mut self.timestamps;
+        let ticks = hw_timestamp.map(|t| timestamps.extend(t));
+        f.timestamp = match (config.timestamping, ticks, self.timestamp_frequency) {
+            (false, _, _) => None,
+            (true, Some(ticks), Some(freq)) => Some(ticks_to_duration(ticks, freq)),
+            (true, _, _) => Some(truncate_to_micros(
+                time::Instant::now().duration_since(self.start_time),
+            )),
+        };
+
+        // drop data frames of an ID type the channel does not accept
+        let (standard, extended) = config
+            .id_types
+            .get(f.channel as usize)
+            .copied()
+            .unwrap_or((true, true));
+        if !f.err && !(if f.ext { extended } else { standard }) {
+            return false;
+        }
+
+        // frames received on a hardware loopback channel were looped back
+        // by the controller rather than received from the bus
+        if f.origin == FrameOrigin::Received
+            && config.loopback.get(f.channel as usize).copied().unwrap_or(false)
+        {
+            f.origin = FrameOrigin::Loopback;
+            f.loopback = true;
+        }
+
+        // send a copy to each interested tap, dropping taps whose receiver
+        // has gone away
+        self.taps
+            .lock()
+            .unwrap()
+            .retain(|t| (t.deliver)(f));
+        match f.origin {
+            FrameOrigin::Received => true,
+            FrameOrigin::TxEcho => config.tx_echoes && config.deliver_echoes,
+            FrameOrigin::Loopback => config.deliver_echoes,
+        }
+    }
+}
+
+/// The receive path of an `Interface` with default settings, fed host frames
+/// directly rather than by a device. Only available with the `testing`
+/// feature, for benchmarking the conversion of received frames.
+#[cfg(feature = "testing")]
+pub struct RxPath {
+    rx: RxPipeline,
+}
+
+#[cfg(feature = "testing")]
+impl RxPath {
+    /// Creates the receive path of a device with `channel_count` channels.
+    pub fn new(channel_count: usize) -> RxPath {
+        let config = Arc::new(RwLock::new(RxConfig::new(channel_count)));
+        let taps = Arc::new(Mutex::new(Vec::new()));
+        RxPath {
+            rx: RxPipeline::new(time::Instant::now(), None, config, taps),
+        }
+    }
+
+    /// Convert a host frame into a new frame, as `Interface.poll` does.
+    /// Returns `None` if the frame would not be delivered.
+    pub fn process(&mut self, hf: HostFrame) -> Option<Frame> {
+        self.rx.process(hf)
+    }
+
+    /// Convert a host frame into `frame`, as `Interface.poll_into` does.
+    /// Returns false if the frame would not be delivered.
+    pub fn process_into(&mut self, hf: &HostFrame, frame: &mut Frame) -> bool {
+        self.rx.process_into(hf, frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gsusb::{GSUSB_ERR_FLAG, GSUSB_EXT_FLAG, GSUSB_RX_ECHO_ID};
+    use crate::BusError;
+    use crossbeam_channel::unbounded;
+
+    fn host_frame(can_id: u32) -> HostFrame {
+        HostFrame {
+            echo_id: GSUSB_RX_ECHO_ID,
+            can_id,
+            can_dlc: 0,
+            channel: 0,
+            flags: 0,
+            reserved: 0,
+            data: [0u8; 64],
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_taps() {
+        let taps = Arc::new(Mutex::new(Vec::new()));
+        let mut rx = RxPipeline::new(
+            time::Instant::now(),
+            None,
+            Arc::new(RwLock::new(RxConfig::new(1))),
+            Arc::clone(&taps),
+        );
+
+        let (send, recv) = unbounded();
+        taps.lock().unwrap().push(Tap::new(|f| f.can_id == 0x7E8, send));
+        let (send, dropped) = unbounded();
+        taps.lock().unwrap().push(Tap::new(|_| true, send));
+        drop(dropped);
+
+        assert!(rx.process(host_frame(0x7DF)).is_some());
+        assert!(rx.process(host_frame(0x7E8)).is_some());
+        assert_eq!(recv.try_recv().unwrap().can_id, 0x7E8);
+        assert!(recv.try_recv().is_err());
+        // the tap with a dropped receiver is removed
+        assert_eq!(taps.lock().unwrap().len(), 1);
+
+        let (send, errors) = unbounded();
+        taps.lock().unwrap().push(Tap::map(BusError::from_frame, send));
+        let mut hf = host_frame(0x040);
+        hf.can_id |= GSUSB_ERR_FLAG;
+        rx.process(host_frame(0x040));
+        rx.process(hf);
+        assert_eq!(errors.try_recv(), Ok(BusError::BusOff));
+        assert!(errors.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_timestamp_wrap() {
+        let mut rx = RxPipeline::new(
+            time::Instant::now(),
+            Some(1_000_000),
+            Arc::new(RwLock::new(RxConfig::new(1))),
+            Arc::new(Mutex::new(Vec::new())),
+        );
+
+        // forward steps below half the counter range
+        let raw = [
+            0xFFFF_FF00,
+            0xFFFF_FFFF,
+            0x0000_0010,
+            0x7000_0000,
+            0xE000_0000,
+            0x0000_0001,
+        ];
+        let timestamps: Vec<time::Duration> = raw
+            .iter()
+            .map(|t| {
+                let mut hf = host_frame(0x100);
+                hf.timestamp = Some(*t);
+                rx.process(hf).unwrap().timestamp.unwrap()
+            })
+            .collect();
+
+        assert!(timestamps.windows(2).all(|w| w[0] < w[1]));
+        // after two wraps
+        assert_eq!(timestamps[5], time::Duration::from_micros((2 << 32) + 1));
+    }
+
+    #[test]
+    fn test_timestamp_reorder() {
+        let mut ts = TimestampExtender::default();
+        assert_eq!(ts.extend(1000), 1000);
+        // a frame a little older than the last does not count as a wrap
+        assert_eq!(ts.extend(999), 999);
+        assert_eq!(ts.extend(1001), 1001);
+
+        // nor does one from just before a wrap, arriving after it
+        let mut ts = TimestampExtender::default();
+        ts.extend(0xFFFF_FFF0);
+        assert_eq!(ts.extend(0x0000_0010), (1 << 32) + 0x10);
+        assert_eq!(ts.extend(0xFFFF_FFFF), 0xFFFF_FFFF);
+        assert_eq!(ts.extend(0x0000_0020), (1 << 32) + 0x20);
+    }
+
+    #[test]
+    fn test_timestamping_disabled() {
+        let config = Arc::new(RwLock::new(RxConfig::new(1)));
+        let mut rx = RxPipeline::new(
+            time::Instant::now(),
+            None,
+            Arc::clone(&config),
+            Arc::new(Mutex::new(Vec::new())),
+        );
+        let t = rx.process(host_frame(0x100)).unwrap().timestamp.unwrap();
+        assert_eq!(t.subsec_nanos() % 1000, 0);
+        config.write().unwrap().timestamping = false;
+        assert!(rx.process(host_frame(0x100)).unwrap().timestamp.is_none());
+    }
+
+    #[test]
+    fn test_tx_echoes() {
+        let config = Arc::new(RwLock::new(RxConfig::new(1)));
+        let taps = Arc::new(Mutex::new(Vec::new()));
+        let mut rx = RxPipeline::new(
+            time::Instant::now(),
+            None,
+            Arc::clone(&config),
+            Arc::clone(&taps),
+        );
+        let (send, recv) = unbounded();
+        taps.lock().unwrap().push(Tap::new(|f| f.origin == FrameOrigin::TxEcho, send));
+
+        let mut echo = host_frame(0x100);
+        echo.echo_id = 1;
+        config.write().unwrap().tx_echoes = false;
+        // echoes still reach taps, but are not delivered
+        assert!(rx.process(echo.clone()).is_none());
+        assert!(rx.process(host_frame(0x200)).is_some());
+        assert_eq!(recv.try_recv().unwrap().can_id, 0x100);
+        assert!(recv.try_recv().is_err());
+
+        config.write().unwrap().tx_echoes = true;
+        assert!(rx.process(echo.clone()).is_some());
+
+        // frames looped back by the controller are also our own
+        config.write().unwrap().loopback[0] = true;
+        config.write().unwrap().deliver_echoes = false;
+        assert!(rx.process(echo).is_none());
+        assert!(rx.process(host_frame(0x300)).is_none());
+        config.write().unwrap().deliver_echoes = true;
+        let f = rx.process(host_frame(0x300)).unwrap();
+        assert_eq!(f.origin, FrameOrigin::Loopback);
+        assert!(f.loopback);
+    }
+
+    #[test]
+    fn test_id_types() {
+        let config = Arc::new(RwLock::new(RxConfig::new(1)));
+        let taps = Arc::new(Mutex::new(Vec::new()));
+        let mut rx = RxPipeline::new(
+            time::Instant::now(),
+            None,
+            Arc::clone(&config),
+            Arc::clone(&taps),
+        );
+        let (send, recv) = unbounded();
+        taps.lock().unwrap().push(Tap::new(|_| true, send));
+
+        config.write().unwrap().id_types[0] = (false, true);
+        assert!(rx.process(host_frame(0x100)).is_none());
+        assert!(rx.process(host_frame(0x100 | GSUSB_EXT_FLAG)).is_some());
+        // error frames are always delivered
+        assert!(rx.process(host_frame(0x40 | GSUSB_ERR_FLAG)).is_some());
+        // dropped frames do not reach taps either
+        assert_eq!(recv.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn test_rx_watchdog() {
+        let start = time::Instant::now();
+        let ms = time::Duration::from_millis;
+        let mut w = RxWatchdog::new(ms(100), 0, start);
+        assert!(!w.expired(0, start + ms(50)));
+        // activity restarts the timeout
+        assert!(!w.expired(1, start + ms(80)));
+        assert!(!w.expired(1, start + ms(150)));
+        assert!(w.expired(1, start + ms(180)));
+        assert!(!w.expired(1, start + ms(200)));
+        assert!(w.expired(1, start + ms(280)));
+    }
+
+    #[test]
+    fn test_ticks_to_duration() {
+        assert_eq!(
+            ticks_to_duration(1_500_000, 1_000_000),
+            time::Duration::from_micros(1_500_000)
+        );
+        // truncated to whole microseconds
+        assert_eq!(
+            ticks_to_duration(100, 48_000_000),
+            time::Duration::from_micros(2)
+        );
+    }
+}
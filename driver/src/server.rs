@@ -0,0 +1,426 @@
+//! Bridge an interface to remote clients over TCP.
+//!
+//! Frames are exchanged in both directions using a simple binary encoding.
+//! Each frame is sent as a fixed 17 byte header followed by the data bytes:
+//!
+//! | offset | size | field                                         |
+//! |--------|------|-----------------------------------------------|
+//! | 0      | 4    | CAN ID, little endian                         |
+//! | 4      | 1    | DLC                                           |
+//! | 5      | 1    | channel                                       |
+//! | 6      | 1    | flags (see `FLAG_*`)                          |
+//! | 7      | 1    | origin: 0 received, 1 TX echo, 2 loopback     |
+//! | 8      | 8    | timestamp in microseconds, `u64::MAX` if none |
+//! | 16     | 1    | number of data bytes (at most 64)             |
+//! | 17     | n    | data                                          |
+//!
+//! `Server` runs on the machine the device is connected to and forwards every
+//! received frame to all connected clients. Frames sent by a client are
+//! transmitted by the interface. `RemoteInterface` is the matching client.
+
+use std::io;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time;
+
+use crossbeam_channel::{bounded, unbounded, RecvTimeoutError, Sender, TrySendError};
+use log::warn;
+
+use crate::{Error, Frame, FrameOrigin, Interface};
+
+const HEADER_LEN: usize = 17;
+const MAX_DATA_LEN: usize = 64;
+const NO_TIMESTAMP: u64 = u64::MAX;
+
+/// Frame has an extended identifier.
+pub const FLAG_EXT: u8 = 1;
+/// Frame is a CAN-FD frame.
+pub const FLAG_FD: u8 = 1 << 1;
+/// CAN-FD bit rate switch.
+pub const FLAG_BRS: u8 = 1 << 2;
+/// CAN-FD error state indicator.
+pub const FLAG_ESI: u8 = 1 << 3;
+/// Remote transmission request.
+pub const FLAG_RTR: u8 = 1 << 4;
+/// Error frame.
+pub const FLAG_ERR: u8 = 1 << 5;
+/// Frame was sent by the device. Derived from the origin byte, which takes
+/// precedence when decoding.
+pub const FLAG_LOOPBACK: u8 = 1 << 6;
+
+// how long the server waits for a frame before servicing clients
+const POLL_INTERVAL: time::Duration = time::Duration::from_millis(10);
+// encoded frames queued for a client before it is considered stalled
+const CLIENT_QUEUE_LEN: usize = 1024;
+
+/// Errors generated by the TCP bridge
+#[derive(Debug)]
+pub enum ServerError {
+    /// Error from the underlying interface.
+    Interface(Error),
+    /// Error on a network connection.
+    Io(io::Error),
+}
+impl From<Error> for ServerError {
+    fn from(e: Error) -> ServerError {
+        ServerError::Interface(e)
+    }
+}
+impl From<io::Error> for ServerError {
+    fn from(e: io::Error) -> ServerError {
+        ServerError::Io(e)
+    }
+}
+
+/// Encode a frame for transmission over the network.
+pub fn encode_frame(f: &Frame) -> Vec<u8> {
+    let len = std::cmp::min(f.data.len(), std::cmp::min(f.data_len(), MAX_DATA_LEN));
+
+    let mut flags = 0;
+    for (set, flag) in [
+        (f.ext, FLAG_EXT),
+        (f.fd, FLAG_FD),
+        (f.brs, FLAG_BRS),
+        (f.esi, FLAG_ESI),
+        (f.rtr, FLAG_RTR),
+        (f.err, FLAG_ERR),
+        (f.origin != FrameOrigin::Received, FLAG_LOOPBACK),
+    ] {
+        if set {
+            flags |= flag;
+        }
+    }
+    let origin = match f.origin {
+        FrameOrigin::Received => 0,
+        FrameOrigin::TxEcho => 1,
+        FrameOrigin::Loopback => 2,
+    };
+    let timestamp = match f.timestamp {
+        Some(t) => t.as_micros() as u64,
+        None => NO_TIMESTAMP,
+    };
+
+    let mut bs = Vec::with_capacity(HEADER_LEN + len);
+    bs.extend_from_slice(&f.can_id.to_le_bytes());
+    bs.push(f.can_dlc);
+    bs.push(f.channel);
+    bs.push(flags);
+    bs.push(origin);
+    bs.extend_from_slice(&timestamp.to_le_bytes());
+    bs.push(len as u8);
+    bs.extend_from_slice(&f.data[..len]);
+    bs
+}
+
+/// Read a single encoded frame from a stream.
+pub fn decode_frame(r: &mut impl Read) -> io::Result<Frame> {
+    let mut header = [0u8; HEADER_LEN];
+    r.read_exact(&mut header)?;
+
+    let len = header[16] as usize;
+    if len > MAX_DATA_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "frame data too long",
+        ));
+    }
+    let mut data = vec![0u8; len];
+    r.read_exact(&mut data)?;
+
+    let flags = header[6];
+    // the loopback flag is derived from the origin, so the two always agree
+    let origin = match header[7] {
+        0 => FrameOrigin::Received,
+        1 => FrameOrigin::TxEcho,
+        2 => FrameOrigin::Loopback,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid frame origin",
+            ))
+        }
+    };
+    let mut ts = [0u8; 8];
+    ts.copy_from_slice(&header[8..16]);
+    let timestamp = match u64::from_le_bytes(ts) {
+        NO_TIMESTAMP => None,
+        us => Some(time::Duration::from_micros(us)),
+    };
+
+    Ok(Frame {
+        can_id: u32::from_le_bytes([header[0], header[1], header[2], header[3]]),
+        can_dlc: header[4],
+        channel: header[5],
+        data,
+        ext: (flags & FLAG_EXT) != 0,
+        fd: (flags & FLAG_FD) != 0,
+        brs: (flags & FLAG_BRS) != 0,
+        esi: (flags & FLAG_ESI) != 0,
+        rtr: (flags & FLAG_RTR) != 0,
+        err: (flags & FLAG_ERR) != 0,
+        loopback: origin != FrameOrigin::Received,
+        origin,
+        timestamp,
+        raw_can_id: None,
+    })
+}
+
+/// A TCP server bridging an interface to remote clients.
+pub struct Server {
+    listener: TcpListener,
+}
+
+impl Server {
+    /// Listen for client connections on the given address.
+    pub fn bind(addr: impl ToSocketAddrs) -> Result<Server, ServerError> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Server { listener })
+    }
+
+    /// Returns the address the server is listening on.
+    pub fn local_addr(&self) -> Result<std::net::SocketAddr, ServerError> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Bridge frames between the interface and connected clients until `stop`
+    /// is set.
+    ///
+    /// The interface must be started before calling this. Frames received by
+    /// the interface are forwarded to every client, and frames sent by any
+    /// client are transmitted. Frames the interface refuses to send are
+    /// logged and dropped. Clients that disconnect are dropped, as are
+    /// clients that stop reading until over a thousand frames are waiting
+    /// for them, so a stalled client does not hold up the others.
+    pub fn run(&self, i: &mut Interface, stop: &AtomicBool) -> Result<(), ServerError> {
+        let rx = i.subscribe(|_| true);
+        let (tx_send, tx_recv) = unbounded();
+        let mut clients: Vec<Sender<Vec<u8>>> = vec![];
+
+        while !stop.load(Ordering::SeqCst) {
+            // accept new clients
+            loop {
+                match self.listener.accept() {
+                    Ok((stream, _)) => {
+                        // the listener is non-blocking, but client reads block
+                        stream.set_nonblocking(false)?;
+                        stream.set_nodelay(true)?;
+                        spawn_reader(stream.try_clone()?, tx_send.clone());
+                        clients.push(spawn_writer(stream));
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+
+            // transmit frames from clients
+            while let Ok(f) = tx_recv.try_recv() {
+                match i.send(f) {
+                    Ok(_) => {}
+                    Err(Error::Disconnected) => return Err(Error::Disconnected.into()),
+                    Err(e) => warn!("dropping frame from client: {:?}", e),
+                }
+            }
+
+            // forward received frames to clients
+            match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(f) => {
+                    let bs = encode_frame(&f);
+                    clients.retain(|c| match c.try_send(bs.clone()) {
+                        Ok(_) => true,
+                        Err(TrySendError::Full(_)) => {
+                            warn!("dropping stalled client");
+                            false
+                        }
+                        Err(TrySendError::Disconnected(_)) => false,
+                    });
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::NotRunning.into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+// read frames from a client until it disconnects
+fn spawn_reader(mut stream: TcpStream, frames: Sender<Frame>) {
+    thread::spawn(move || {
+        while let Ok(f) = decode_frame(&mut stream) {
+            if frames.send(f).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+// write queued frames to a client until it disconnects or is dropped from
+// the server, then close the connection
+fn spawn_writer(mut stream: TcpStream) -> Sender<Vec<u8>> {
+    let (send, recv) = bounded::<Vec<u8>>(CLIENT_QUEUE_LEN);
+    thread::spawn(move || {
+        for bs in recv {
+            if stream.write_all(&bs).is_err() {
+                break;
+            }
+        }
+        // also ends the client's reader
+        let _ = stream.shutdown(Shutdown::Both);
+    });
+    send
+}
+
+/// A connection to a remote `Server`.
+pub struct RemoteInterface {
+    stream: TcpStream,
+    // bytes of a frame read before a receive timed out
+    pending: Vec<u8>,
+}
+
+impl RemoteInterface {
+    /// Connect to a server at the given address.
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<RemoteInterface, ServerError> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(RemoteInterface {
+            stream,
+            pending: vec![],
+        })
+    }
+
+    /// Send a CAN frame using the remote device.
+    pub fn send(&mut self, f: &Frame) -> Result<(), ServerError> {
+        self.stream.write_all(&encode_frame(f))?;
+        Ok(())
+    }
+
+    /// Receive the next frame from the remote device, blocking until one
+    /// arrives.
+    pub fn recv(&mut self) -> Result<Frame, ServerError> {
+        self.read_frame(None)
+    }
+
+    /// Receive the next frame, returning `Error::Timeout` if none arrives
+    /// within `timeout`. Part of a frame received before the timeout is
+    /// kept for the next receive.
+    pub fn recv_timeout(&mut self, timeout: time::Duration) -> Result<Frame, ServerError> {
+        self.read_frame(Some(time::Instant::now() + timeout))
+    }
+
+    // read until a whole frame is buffered, then decode it
+    fn read_frame(&mut self, deadline: Option<time::Instant>) -> Result<Frame, ServerError> {
+        loop {
+            let needed = match self.pending.get(HEADER_LEN - 1) {
+                Some(len) => HEADER_LEN + *len as usize,
+                None => HEADER_LEN,
+            };
+            if self.pending.len() >= needed {
+                let f = decode_frame(&mut &self.pending[..needed])?;
+                self.pending.drain(..needed);
+                return Ok(f);
+            }
+
+            let timeout = match deadline {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(time::Instant::now());
+                    if left.is_zero() {
+                        return Err(Error::Timeout.into());
+                    }
+                    Some(left)
+                }
+                None => None,
+            };
+            self.stream.set_read_timeout(timeout)?;
+            let mut buf = [0u8; HEADER_LEN + MAX_DATA_LEN];
+            let start = self.pending.len();
+            let result = self.stream.read(&mut buf[..needed - start]);
+            match result {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Ok(n) => self.pending.extend_from_slice(&buf[..n]),
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Err(Error::Timeout.into())
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let f = Frame {
+            can_id: 0x1234_5678,
+            can_dlc: 9,
+            channel: 1,
+            data: (0..64).collect(),
+            ext: true,
+            fd: true,
+            brs: true,
+            origin: FrameOrigin::TxEcho,
+            loopback: true,
+            timestamp: Some(time::Duration::from_micros(1_500_000)),
+            ..Default::default()
+        };
+        let bs = encode_frame(&f);
+        // only the 12 bytes covered by the DLC are sent
+        assert_eq!(bs.len(), HEADER_LEN + 12);
+
+        let d = decode_frame(&mut bs.as_slice()).unwrap();
+        assert_eq!(d.can_id, f.can_id);
+        assert_eq!(d.can_dlc, 9);
+        assert_eq!(d.channel, 1);
+        assert_eq!(d.data, (0..12).collect::<Vec<u8>>());
+        assert!(d.ext && d.fd && d.brs && d.loopback);
+        assert!(!d.esi && !d.rtr && !d.err);
+        assert_eq!(d.origin, FrameOrigin::TxEcho);
+        assert_eq!(d.timestamp, f.timestamp);
+
+        // a truncated frame is an error
+        assert!(decode_frame(&mut &bs[..HEADER_LEN + 4]).is_err());
+
+        // the loopback flag follows the origin
+        let f = Frame {
+            origin: FrameOrigin::Loopback,
+            ..Default::default()
+        };
+        let bs = encode_frame(&f);
+        assert_eq!(bs[6], FLAG_LOOPBACK);
+        assert!(decode_frame(&mut bs.as_slice()).unwrap().loopback);
+    }
+
+    #[test]
+    fn test_recv_partial() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut remote = RemoteInterface::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+
+        let f = Frame::new_standard(0x123, &[1, 2, 3]).unwrap();
+        let bs = encode_frame(&f);
+        let short = time::Duration::from_millis(20);
+
+        // a receive that times out partway through a frame keeps what it read
+        peer.write_all(&bs[..HEADER_LEN + 1]).unwrap();
+        assert!(matches!(
+            remote.recv_timeout(short),
+            Err(ServerError::Interface(Error::Timeout))
+        ));
+        peer.write_all(&bs[HEADER_LEN + 1..]).unwrap();
+        peer.write_all(&bs[..4]).unwrap();
+        let d = remote.recv_timeout(time::Duration::from_secs(1)).unwrap();
+        assert_eq!((d.can_id, d.data), (0x123, vec![1, 2, 3]));
+
+        assert!(remote.recv_timeout(short).is_err());
+        peer.write_all(&bs[4..]).unwrap();
+        assert_eq!(remote.recv().unwrap().can_id, 0x123);
+    }
+}
@@ -0,0 +1,322 @@
+//! Lawicel / SLCAN ASCII protocol.
+//!
+//! SLCAN adapters are driven with ASCII commands terminated by a carriage
+//! return, usually over a serial port. `Slcan` works on any `Read + Write`
+//! stream, so a serial device opened as a file (with the line settings
+//! configured by the caller) or a pseudo terminal can be used.
+//!
+//! Only classic CAN frames are supported by the protocol.
+
+use std::collections::VecDeque;
+use std::io;
+use std::io::{Read, Write};
+
+use crate::{Error, Frame};
+
+// command terminator
+const CR: u8 = b'\r';
+// error response
+const BELL: u8 = 0x07;
+
+// standard bitrates selected with the `Sn` command
+const BITRATES: [u32; 9] = [
+    10_000, 20_000, 50_000, 100_000, 125_000, 250_000, 500_000, 800_000, 1_000_000,
+];
+
+/// Errors generated by the SLCAN transport
+#[derive(Debug)]
+pub enum SlcanError {
+    /// Error from the underlying stream.
+    Io(io::Error),
+    /// A frame or setting is invalid or not supported by the protocol.
+    Interface(Error),
+    /// The adapter rejected a command.
+    Rejected,
+    /// A line received from the adapter could not be decoded.
+    InvalidLine(String),
+}
+impl From<io::Error> for SlcanError {
+    fn from(e: io::Error) -> SlcanError {
+        SlcanError::Io(e)
+    }
+}
+impl From<Error> for SlcanError {
+    fn from(e: Error) -> SlcanError {
+        SlcanError::Interface(e)
+    }
+}
+
+/// Format a frame as an SLCAN transmit command, without the terminator.
+pub fn format_frame(f: &Frame) -> Result<String, Error> {
+    if f.fd {
+        return Err(Error::UnsupportedFeature("CAN-FD frames over SLCAN"));
+    }
+    if f.can_dlc > 8 {
+        return Err(Error::InvalidArgument("DLC must be at most 8"));
+    }
+
+    let mut s = match (f.ext, f.rtr) {
+        (false, false) => format!("t{:03X}", f.can_id & 0x7FF),
+        (false, true) => format!("r{:03X}", f.can_id & 0x7FF),
+        (true, false) => format!("T{:08X}", f.can_id & 0x1FFF_FFFF),
+        (true, true) => format!("R{:08X}", f.can_id & 0x1FFF_FFFF),
+    };
+    s.push_str(&format!("{}", f.can_dlc));
+    if !f.rtr {
+        for i in 0..f.can_dlc as usize {
+            s.push_str(&format!("{:02X}", f.data.get(i).copied().unwrap_or(0)));
+        }
+    }
+    Ok(s)
+}
+
+/// Parse a received SLCAN frame line, without the terminator.
+///
+/// An optional 4 digit timestamp following the data is ignored.
+pub fn parse_frame(line: &str) -> Option<Frame> {
+    let (ext, rtr) = match line.as_bytes().first()? {
+        b't' => (false, false),
+        b'r' => (false, true),
+        b'T' => (true, false),
+        b'R' => (true, true),
+        _ => return None,
+    };
+    let id_len = if ext { 8 } else { 3 };
+    let id = line.get(1..1 + id_len)?;
+    let can_id = u32::from_str_radix(id, 16).ok()?;
+    if (!ext && can_id > 0x7FF) || can_id > 0x1FFF_FFFF {
+        return None;
+    }
+    let dlc_pos = 1 + id_len;
+    let can_dlc = line.get(dlc_pos..dlc_pos + 1)?.parse::<u8>().ok()?;
+    if can_dlc > 8 {
+        return None;
+    }
+
+    let mut data = vec![];
+    if !rtr {
+        let data_pos = dlc_pos + 1;
+        for i in 0..can_dlc as usize {
+            let b = line.get(data_pos + 2 * i..data_pos + 2 * i + 2)?;
+            data.push(u8::from_str_radix(b, 16).ok()?);
+        }
+    }
+
+    Some(Frame {
+        can_id,
+        can_dlc,
+        data,
+        ext,
+        rtr,
+        ..Default::default()
+    })
+}
+
+/// An SLCAN adapter connected over a stream.
+pub struct Slcan<T: Read + Write> {
+    stream: T,
+    // bytes received but not yet terminated by a carriage return
+    line: Vec<u8>,
+    // frames received while waiting for a command response
+    pending: VecDeque<Frame>,
+}
+
+impl<T: Read + Write> Slcan<T> {
+    /// Create an adapter using the given stream.
+    pub fn new(stream: T) -> Slcan<T> {
+        Slcan {
+            stream,
+            line: vec![],
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Set the bitrate of the adapter. Only the standard SLCAN bitrates from
+    /// 10 kbit/s to 1 Mbit/s are supported. Must be called while closed.
+    pub fn set_bitrate(&mut self, bitrate: u32) -> Result<(), SlcanError> {
+        let n = match BITRATES.iter().position(|b| *b == bitrate) {
+            Some(n) => n,
+            None => return Err(Error::InvalidBitrate(bitrate).into()),
+        };
+        self.command(&format!("S{}", n))
+    }
+
+    /// Open the channel for normal operation.
+    pub fn open(&mut self) -> Result<(), SlcanError> {
+        self.command("O")
+    }
+
+    /// Open the channel in listen only mode.
+    pub fn open_listen_only(&mut self) -> Result<(), SlcanError> {
+        self.command("L")
+    }
+
+    /// Close the channel.
+    pub fn close(&mut self) -> Result<(), SlcanError> {
+        self.command("C")
+    }
+
+    /// Send a CAN frame.
+    pub fn send(&mut self, f: &Frame) -> Result<(), SlcanError> {
+        let cmd = format_frame(f)?;
+        self.command(&cmd)
+    }
+
+    /// Receive the next CAN frame, blocking until one arrives. Frames that
+    /// arrived while a command waited for its response are returned first.
+    /// Stray command responses received while waiting are discarded.
+    pub fn recv(&mut self) -> Result<Frame, SlcanError> {
+        if let Some(f) = self.pending.pop_front() {
+            return Ok(f);
+        }
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() || line == "z" || line == "Z" {
+                // acknowledgement of a command
+                continue;
+            }
+            return parse_frame(&line).ok_or(SlcanError::InvalidLine(line));
+        }
+    }
+
+    // send a command and wait for its response: a carriage return, or z or
+    // Z for transmit commands, on success and a bell character on error
+    fn command(&mut self, cmd: &str) -> Result<(), SlcanError> {
+        self.stream.write_all(cmd.as_bytes())?;
+        self.stream.write_all(&[CR])?;
+        self.stream.flush()?;
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() || line == "z" || line == "Z" {
+                return Ok(());
+            }
+            // frames received before the response are kept for recv
+            match parse_frame(&line) {
+                Some(f) => self.pending.push_back(f),
+                None => return Err(SlcanError::InvalidLine(line)),
+            }
+        }
+    }
+
+    // read up to the next carriage return or bell character
+    fn read_line(&mut self) -> Result<String, SlcanError> {
+        let mut b = [0u8; 1];
+        loop {
+            self.stream.read_exact(&mut b)?;
+            match b[0] {
+                CR => {
+                    let line = String::from_utf8_lossy(&self.line).into_owned();
+                    self.line.clear();
+                    return Ok(line);
+                }
+                BELL => {
+                    self.line.clear();
+                    return Err(SlcanError::Rejected);
+                }
+                // some adapters terminate lines with CR LF
+                b'\n' => {}
+                c => self.line.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_frame() {
+        let f = Frame {
+            can_id: 0x123,
+            can_dlc: 3,
+            data: vec![0x11, 0x22, 0x33],
+            ..Default::default()
+        };
+        assert_eq!(format_frame(&f).unwrap(), "t1233112233");
+
+        let f = Frame {
+            can_id: 0x1234_5678,
+            can_dlc: 2,
+            ext: true,
+            rtr: true,
+            ..Default::default()
+        };
+        assert_eq!(format_frame(&f).unwrap(), "R123456782");
+
+        let f = Frame {
+            fd: true,
+            ..Default::default()
+        };
+        assert!(format_frame(&f).is_err());
+    }
+
+    #[test]
+    fn test_parse_frame() {
+        let f = parse_frame("t1233112233").unwrap();
+        assert_eq!(f.can_id, 0x123);
+        assert_eq!(f.can_dlc, 3);
+        assert_eq!(f.data, vec![0x11, 0x22, 0x33]);
+        assert!(!f.ext && !f.rtr);
+
+        // trailing timestamp
+        let f = parse_frame("T123456781AA1F2E").unwrap();
+        assert_eq!(f.can_id, 0x1234_5678);
+        assert!(f.ext);
+        assert_eq!(f.data, vec![0xAA]);
+
+        let f = parse_frame("r7FF8").unwrap();
+        assert!(f.rtr);
+        assert_eq!(f.can_dlc, 8);
+
+        assert!(parse_frame("t800").is_none());
+        assert!(parse_frame("t1239").is_none());
+        assert!(parse_frame("t1232AA").is_none());
+        assert!(parse_frame("V1010").is_none());
+    }
+
+    // an adapter replying with fixed input, recording the commands sent
+    struct Adapter {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+    impl Read for Adapter {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+    impl Write for Adapter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_command() {
+        // the adapter rejects the bitrate, accepts the open, and receives a
+        // frame before acknowledging the transmit
+        let input = b"\x07\rt1231AB\rz\r".to_vec();
+        let mut s = Slcan::new(Adapter {
+            input: io::Cursor::new(input),
+            output: vec![],
+        });
+        assert!(matches!(s.set_bitrate(500_000), Err(SlcanError::Rejected)));
+        s.open().unwrap();
+        s.send(&Frame::new_standard(0x100, &[]).unwrap()).unwrap();
+        assert_eq!(s.stream.output, b"S6\rO\rt1000\r");
+        assert_eq!(s.recv().unwrap().data, vec![0xAB]);
+        // the stream is exhausted
+        assert!(matches!(s.recv(), Err(SlcanError::Io(_))));
+    }
+
+    #[test]
+    fn test_recv() {
+        let input = b"z\rt1231AB\r\x07".to_vec();
+        let mut s = Slcan::new(io::Cursor::new(input));
+        assert_eq!(s.recv().unwrap().data, vec![0xAB]);
+        assert!(matches!(s.recv(), Err(SlcanError::Rejected)));
+    }
+}
@@ -0,0 +1,51 @@
+//! A complete record of an interface's state, for support requests and logs.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    BitTimingInfo, BitTimingLimits, CapabilityReport, Channel, DeviceIdentity, ErrorCounters,
+    UsbInfo,
+};
+
+/// The state of an interface at one moment, returned by
+/// `Interface::snapshot`.
+///
+/// It gathers what the individual getters report into one record that can
+/// be serialized, so everything about a setup can be attached to a bug
+/// report or logged at startup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InterfaceSnapshot {
+    /// Identity of the device and its firmware.
+    pub identity: DeviceIdentity,
+    /// Capabilities derived from the device's feature flags.
+    pub capabilities: CapabilityReport,
+    /// USB interface and endpoints used to talk to the device.
+    pub usb: UsbInfo,
+    /// Nominal bit timing limits of the device.
+    pub bit_timing_limits: BitTimingLimits,
+    /// Data phase bit timing limits, on CAN-FD devices.
+    pub data_bit_timing_limits: Option<BitTimingLimits>,
+    /// Frequency of hardware timestamps in Hz, if they are used.
+    pub timestamp_frequency: Option<u32>,
+    /// True if the interface was started.
+    pub running: bool,
+    /// True if the device was disconnected or failed while running.
+    pub disconnected: bool,
+    /// State of each channel, in channel order.
+    pub channels: Vec<ChannelSnapshot>,
+}
+
+/// The state of one channel in an `InterfaceSnapshot`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelSnapshot {
+    /// Configuration of the channel.
+    pub config: Channel,
+    /// True if the channel is paused by `Interface::pause`.
+    pub paused: bool,
+    /// Nominal bit timing last set on the channel.
+    pub bit_timing: Option<BitTimingInfo>,
+    /// Data phase bit timing last set on the channel.
+    pub data_bit_timing: Option<BitTimingInfo>,
+    /// Error conditions reported on the channel.
+    pub error_counters: ErrorCounters,
+}
@@ -0,0 +1,317 @@
+//! Bridge an interface to a Linux SocketCAN network interface.
+//!
+//! Frames received by the device are written to the SocketCAN interface, and
+//! frames sent on the SocketCAN interface by other applications are
+//! transmitted by the device. Bridging to a virtual CAN interface lets
+//! standard SocketCAN tools such as `candump` and `cansend` use the device:
+//!
+//! ```text
+//! ip link add dev vcan0 type vcan
+//! ip link set up vcan0
+//! ```
+
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time;
+
+use crossbeam_channel::RecvTimeoutError;
+
+use crate::{Error, Frame, FrameOrigin, Interface};
+
+// from linux/can.h and linux/can/raw.h
+const CAN_RAW: libc::c_int = 1;
+const SOL_CAN_RAW: libc::c_int = 101;
+const CAN_RAW_FD_FRAMES: libc::c_int = 5;
+const CAN_MTU: usize = 16;
+const CANFD_MTU: usize = 72;
+const CANFD_BRS: u8 = 0x01;
+const CANFD_ESI: u8 = 0x02;
+
+// how long the bridge waits for a frame before checking for a stop request
+const POLL_INTERVAL: time::Duration = time::Duration::from_millis(10);
+
+// struct sockaddr_can, with the protocol specific address left zeroed
+#[repr(C)]
+struct SockaddrCan {
+    can_family: libc::sa_family_t,
+    can_ifindex: libc::c_int,
+    can_addr: [u64; 2],
+}
+
+/// Errors generated by the SocketCAN bridge
+#[derive(Debug)]
+pub enum SocketCanError {
+    /// Error from the underlying interface.
+    Interface(Error),
+    /// Error on the SocketCAN socket.
+    Io(io::Error),
+}
+impl From<Error> for SocketCanError {
+    fn from(e: Error) -> SocketCanError {
+        SocketCanError::Interface(e)
+    }
+}
+impl From<io::Error> for SocketCanError {
+    fn from(e: io::Error) -> SocketCanError {
+        SocketCanError::Io(e)
+    }
+}
+
+/// A raw socket bound to a SocketCAN network interface.
+pub struct SocketCan {
+    fd: RawFd,
+}
+
+impl SocketCan {
+    /// Open a raw CAN socket on the named network interface, such as `vcan0`.
+    /// CAN-FD frames are enabled on the socket.
+    pub fn open(ifname: &str) -> Result<SocketCan, SocketCanError> {
+        let name = CString::new(ifname)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid interface name"))?;
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let fd = unsafe { libc::socket(libc::AF_CAN, libc::SOCK_RAW, CAN_RAW) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        // closes the socket if any of the following steps fail
+        let sock = SocketCan { fd };
+
+        let enable: libc::c_int = 1;
+        sock.check(unsafe {
+            libc::setsockopt(
+                fd,
+                SOL_CAN_RAW,
+                CAN_RAW_FD_FRAMES,
+                &enable as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        })?;
+
+        let addr = SockaddrCan {
+            can_family: libc::AF_CAN as libc::sa_family_t,
+            can_ifindex: ifindex as libc::c_int,
+            can_addr: [0; 2],
+        };
+        sock.check(unsafe {
+            libc::bind(
+                fd,
+                &addr as *const SockaddrCan as *const libc::sockaddr,
+                mem::size_of::<SockaddrCan>() as libc::socklen_t,
+            )
+        })?;
+        Ok(sock)
+    }
+
+    /// Set how long `recv` waits for a frame. `None` waits forever.
+    pub fn set_read_timeout(
+        &self,
+        timeout: Option<time::Duration>,
+    ) -> Result<(), SocketCanError> {
+        let tv = match timeout {
+            Some(t) => libc::timeval {
+                tv_sec: t.as_secs() as libc::time_t,
+                tv_usec: t.subsec_micros() as libc::suseconds_t,
+            },
+            None => libc::timeval {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+        };
+        self.check(unsafe {
+            libc::setsockopt(
+                self.fd,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &tv as *const libc::timeval as *const libc::c_void,
+                mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Write a frame to the socket. The frame's channel is ignored.
+    pub fn send(&self, f: &Frame) -> Result<(), SocketCanError> {
+        let bs = encode_frame(f);
+        let n = unsafe { libc::write(self.fd, bs.as_ptr() as *const libc::c_void, bs.len()) };
+        if n < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+
+    /// Read a frame from the socket. The returned frame is on channel 0.
+    ///
+    /// Returns an `io::ErrorKind::WouldBlock` error if the read timeout
+    /// expires before a frame arrives.
+    pub fn recv(&self) -> Result<Frame, SocketCanError> {
+        let mut buf = [0u8; CANFD_MTU];
+        let n =
+            unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(decode_frame(&buf[..n as usize])?)
+    }
+
+    /// Bridge frames between a channel of the interface and the socket until
+    /// `stop` is set.
+    ///
+    /// The interface must be started before calling this. Frames received on
+    /// the channel are written to the socket, and frames read from the socket
+    /// are transmitted on the channel. Transmit confirmations are not
+    /// written, since SocketCAN already delivers sent frames to the other
+    /// sockets on the interface.
+    pub fn bridge(
+        &self,
+        i: &mut Interface,
+        channel: usize,
+        stop: &AtomicBool,
+    ) -> Result<(), SocketCanError> {
+        if channel >= i.channels() {
+            return Err(Error::InvalidChannel.into());
+        }
+        self.set_read_timeout(Some(POLL_INTERVAL))?;
+        let rx = i.subscribe(move |f| {
+            f.channel as usize == channel && f.origin != FrameOrigin::TxEcho
+        });
+
+        thread::scope(|s| {
+            // forward received frames to the socket
+            let forward = s.spawn(|| -> Result<(), SocketCanError> {
+                while !stop.load(Ordering::SeqCst) {
+                    match rx.recv_timeout(POLL_INTERVAL) {
+                        Ok(f) => self.send(&f)?,
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => {
+                            return Err(Error::NotRunning.into())
+                        }
+                    }
+                }
+                Ok(())
+            });
+
+            // transmit frames from the socket
+            let mut result = Ok(());
+            while !stop.load(Ordering::SeqCst) && !forward.is_finished() {
+                match self.recv() {
+                    Ok(mut f) => {
+                        f.channel = channel as u8;
+                        if let Err(e) = i.send(f) {
+                            result = Err(e.into());
+                            break;
+                        }
+                    }
+                    Err(SocketCanError::Io(e)) if is_timeout(&e) => {}
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
+                }
+            }
+            stop.store(true, Ordering::SeqCst);
+            let forwarded = forward.join().unwrap();
+            result.and(forwarded)
+        })
+    }
+
+    fn check(&self, ret: libc::c_int) -> io::Result<()> {
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Drop for SocketCan {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+// encode a frame as a struct can_frame, or a struct canfd_frame for FD frames
+fn encode_frame(f: &Frame) -> Vec<u8> {
+    let mut bs = vec![0u8; if f.fd { CANFD_MTU } else { CAN_MTU }];
+    let len = std::cmp::min(f.data.len(), std::cmp::min(f.data_len(), bs.len() - 8));
+    bs[0..4].copy_from_slice(&f.socketcan_id().to_ne_bytes());
+    bs[4] = len as u8;
+    if f.fd {
+        if f.brs {
+            bs[5] |= CANFD_BRS;
+        }
+        if f.esi {
+            bs[5] |= CANFD_ESI;
+        }
+    }
+    bs[8..8 + len].copy_from_slice(&f.data[..len]);
+    bs
+}
+
+// decode a struct can_frame or struct canfd_frame, telling them apart by size
+fn decode_frame(bs: &[u8]) -> io::Result<Frame> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid SocketCAN frame");
+    let fd = match bs.len() {
+        CAN_MTU => false,
+        CANFD_MTU => true,
+        _ => return Err(invalid()),
+    };
+    let id = u32::from_ne_bytes([bs[0], bs[1], bs[2], bs[3]]);
+    let len = std::cmp::min(bs[4] as usize, bs.len() - 8);
+    let data = &bs[8..8 + len];
+
+    if !fd {
+        return Frame::from_socketcan_id(id, data).map_err(|_| invalid());
+    }
+    // new_fd picks the DLC for the data length; the ID format comes from the
+    // SocketCAN flags
+    let mut f = Frame::new_fd(0, data, bs[5] & CANFD_BRS != 0).map_err(|_| invalid())?;
+    let id = Frame::from_socketcan_id(id, &[]).map_err(|_| invalid())?;
+    f.can_id = id.can_id;
+    f.ext = id.ext;
+    f.err = id.err;
+    f.esi = bs[5] & CANFD_ESI != 0;
+    Ok(f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let f = Frame::new_extended(0x12, &[1, 2, 3]).unwrap();
+        let bs = encode_frame(&f);
+        assert_eq!(bs.len(), CAN_MTU);
+        let d = decode_frame(&bs).unwrap();
+        assert_eq!(d.can_id, 0x12);
+        assert!(d.ext && !d.fd);
+        assert_eq!(d.data, vec![1, 2, 3]);
+
+        let mut f = Frame::new_fd(0x123, &[0xAA; 21], true).unwrap();
+        f.esi = true;
+        let bs = encode_frame(&f);
+        assert_eq!(bs.len(), CANFD_MTU);
+        assert_eq!(bs[4], 24);
+        let d = decode_frame(&bs).unwrap();
+        assert_eq!(d.can_id, 0x123);
+        assert!(!d.ext && d.fd && d.brs && d.esi);
+        assert_eq!(d.can_dlc, f.can_dlc);
+        assert_eq!(&d.data[..21], &[0xAA; 21]);
+
+        assert!(decode_frame(&bs[..20]).is_err());
+    }
+}
@@ -0,0 +1,400 @@
+//! Bit timing calculation.
+//!
+//! This module only depends on the gs_usb protocol definitions and does not
+//! use the device or any threads.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::gsusb::BitTiming;
+use crate::Error;
+
+/// Options used when calculating a bit timing from a bitrate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BitTimingOptions {
+    /// Synchronization jump width in time quanta. Must be at least 1 and no
+    /// larger than either phase segment.
+    pub sjw: u32,
+    /// Propagation segment in time quanta.
+    pub prop_seg: u32,
+}
+
+impl Default for BitTimingOptions {
+    /// Returns the options used by `Interface::set_bitrate`: SJW of 1 and no
+    /// propagation segment.
+    fn default() -> BitTimingOptions {
+        BitTimingOptions {
+            sjw: 1,
+            prop_seg: 0,
+        }
+    }
+}
+
+/// Bit timing limits of a device's CAN controller, as reported by the
+/// device. Returned by `Interface::bit_timing_limits` and
+/// `Interface::data_bit_timing_limits`.
+///
+/// `tseg1` is the sum of the propagation and phase 1 segments, and `tseg2`
+/// the phase 2 segment, both in time quanta.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BitTimingLimits {
+    /// Smallest time segment 1.
+    pub tseg1_min: u32,
+    /// Largest time segment 1.
+    pub tseg1_max: u32,
+    /// Smallest time segment 2.
+    pub tseg2_min: u32,
+    /// Largest time segment 2.
+    pub tseg2_max: u32,
+    /// Largest synchronization jump width.
+    pub sjw_max: u32,
+    /// Smallest bitrate prescaler.
+    pub brp_min: u32,
+    /// Largest bitrate prescaler.
+    pub brp_max: u32,
+    /// Step between valid bitrate prescalers.
+    pub brp_inc: u32,
+}
+
+impl BitTimingLimits {
+    /// Returns true if the bit timing is within the limits.
+    pub fn accepts(&self, bt: &BitTiming) -> bool {
+        let tseg1 = bt.prop_seg + bt.phase_seg1;
+        let brp_step = self.brp_inc.max(1);
+        (self.tseg1_min..=self.tseg1_max).contains(&tseg1)
+            && (self.tseg2_min..=self.tseg2_max).contains(&bt.phase_seg2)
+            && bt.sjw <= self.sjw_max
+            && (self.brp_min..=self.brp_max).contains(&bt.brp)
+            && (bt.brp - self.brp_min).is_multiple_of(brp_step)
+    }
+}
+
+/// A bit timing described in terms of its effect on the bus, returned by
+/// `BitTiming::describe`.
+///
+/// Displays as, for example,
+/// `brp=6, tseg1=13, tseg2=2, sjw=1, sample point 87.5%, 500 kbit/s @ 0.00% error`.
+/// The error is only shown when `requested` is set.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BitTimingInfo {
+    /// Bitrate prescaler.
+    pub brp: u32,
+    /// Time quanta before the sample point, excluding the synchronization
+    /// quantum: the propagation segment plus phase segment 1.
+    pub tseg1: u32,
+    /// Time quanta after the sample point (phase segment 2).
+    pub tseg2: u32,
+    /// Synchronization jump width.
+    pub sjw: u32,
+    /// Sample point as a percentage of the bit time.
+    pub sample_point: f64,
+    /// Bitrate produced by the timing, in bits/second.
+    pub bitrate: f64,
+    /// Bitrate the timing was calculated for, if known.
+    pub requested: Option<u32>,
+}
+
+impl BitTimingInfo {
+    /// Returns the difference between the produced and requested bitrates as
+    /// a percentage of the requested bitrate, or `None` if the requested
+    /// bitrate is not known.
+    pub fn error(&self) -> Option<f64> {
+        self.requested
+            .map(|r| (self.bitrate - r as f64) / r as f64 * 100.0)
+    }
+}
+
+impl fmt::Display for BitTimingInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "brp={}, tseg1={}, tseg2={}, sjw={}, sample point {}%, ",
+            self.brp,
+            self.tseg1,
+            self.tseg2,
+            self.sjw,
+            trim(format!("{:.1}", self.sample_point)),
+        )?;
+        write!(f, "{} kbit/s", trim(format!("{:.3}", self.bitrate / 1000.0)))?;
+        if let Some(error) = self.error() {
+            write!(f, " @ {:.2}% error", error)?;
+        }
+        Ok(())
+    }
+}
+
+// remove trailing zeros from a formatted decimal number
+fn trim(s: String) -> String {
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+impl BitTiming {
+    /// Describe the timing when used with a CAN clock of `clk` Hz.
+    pub fn describe(&self, clk: u32) -> BitTimingInfo {
+        let tseg1 = self.prop_seg + self.phase_seg1;
+        let quanta = 1 + tseg1 + self.phase_seg2;
+        BitTimingInfo {
+            brp: self.brp,
+            tseg1,
+            tseg2: self.phase_seg2,
+            sjw: self.sjw,
+            sample_point: (1 + tseg1) as f64 / quanta as f64 * 100.0,
+            bitrate: clk as f64 / (self.brp as f64 * quanta as f64),
+            requested: None,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn calculate_bit_timing(clk: u32, bitrate: u32) -> Result<BitTiming, Error> {
+    calculate_bit_timing_with_options(clk, bitrate, BitTimingOptions::default())
+}
+
+/// Calculate the nominal bit timing for `bitrate` from a CAN clock of `clk`
+/// Hz, as `Interface.set_bitrate_with_options` does. The timing is found
+/// within the classic bxCAN limits, trying exact bitrates before those
+/// within 0.1% and 0.5%.
+pub fn calculate_bit_timing_with_options(
+    clk: u32,
+    bitrate: u32,
+    options: BitTimingOptions,
+) -> Result<BitTiming, Error> {
+    if options.sjw == 0 {
+        return Err(Error::InvalidArgument("SJW must be at least 1"));
+    }
+
+    let max_brp = 1024;
+    let min_seg1 = 1;
+    let max_seg1 = 16;
+    let min_seg2 = 1;
+    let max_seg2 = 8;
+    let tolerances = vec![0.0, 0.1 / 100.0, 0.5 / 100.0];
+
+    for tolerance in tolerances {
+        let tmp = clk as f32 / bitrate as f32;
+        for brp in 1..(max_brp + 1) {
+            let btq = tmp / brp as f32;
+            let btq_rounded = btq.round() as u32;
+
+            if (4..=32).contains(&btq_rounded) {
+                let err = ((btq / (btq_rounded as f32) - 1.0) * 10000.0).round() / 10000.0;
+                if err.abs() > tolerance {
+                    // error is not acceptable
+                    continue;
+                }
+            }
+
+            for seg1 in min_seg1..max_seg1 {
+                // subtract 1 from seg2 to account for the sync segment
+                if btq_rounded < (options.prop_seg + seg1 + 1) {
+                    break;
+                } else {
+                    let seg2 = btq_rounded - (options.prop_seg + seg1 + 1);
+                    if seg2 < min_seg2 || seg2 > max_seg2 {
+                        // invalid seg2 value
+                        continue;
+                    }
+                    if options.sjw > seg1 || options.sjw > seg2 {
+                        // sjw can't be larger than either phase segment
+                        continue;
+                    }
+                    // brp, seg1, and seg2 are all valid
+                    return Ok(BitTiming {
+                        brp,
+                        prop_seg: options.prop_seg,
+                        phase_seg1: seg1,
+                        phase_seg2: seg2,
+                        sjw: options.sjw,
+                    });
+                }
+            }
+        }
+    }
+
+    Err(Error::InvalidBitrate(bitrate))
+}
+
+/// Calculate the data phase bit timing for `bitrate` from a CAN clock of
+/// `clk` Hz within `limits`, as `Interface.set_data_bitrate` does. The
+/// sample point is placed near 75%, and the smallest prescaler is preferred,
+/// since more time quanta per bit give a more accurate bitrate.
+pub fn calculate_data_bit_timing(
+    clk: u32,
+    bitrate: u32,
+    limits: &BitTimingLimits,
+) -> Result<BitTiming, Error> {
+    let tolerances = vec![0.0, 0.1 / 100.0, 0.5 / 100.0];
+    let brp_step = limits.brp_inc.max(1) as usize;
+    let min_quanta = 1 + limits.tseg1_min + limits.tseg2_min;
+    let max_quanta = 1 + limits.tseg1_max + limits.tseg2_max;
+
+    for tolerance in tolerances {
+        let tmp = clk as f32 / bitrate as f32;
+        for brp in (limits.brp_min.max(1)..=limits.brp_max).step_by(brp_step) {
+            let btq = tmp / brp as f32;
+            let quanta = btq.round() as u32;
+            if quanta < min_quanta || quanta > max_quanta {
+                continue;
+            }
+            let err = ((btq / (quanta as f32) - 1.0) * 10000.0).round() / 10000.0;
+            if err.abs() > tolerance {
+                continue;
+            }
+
+            // segment 1 ends at the sample point, after the sync segment
+            let tseg1 = ((quanta as f32 * 0.75).round() as u32)
+                .saturating_sub(1)
+                .clamp(limits.tseg1_min, limits.tseg1_max);
+            let tseg2 = quanta - 1 - tseg1;
+            let bt = BitTiming {
+                brp,
+                prop_seg: 0,
+                phase_seg1: tseg1,
+                phase_seg2: tseg2,
+                sjw: 1,
+            };
+            if limits.accepts(&bt) {
+                return Ok(bt);
+            }
+        }
+    }
+
+    Err(Error::InvalidBitrate(bitrate))
+}
+
+#[cfg(test)]
+fn effective_bitrate(clk: u32, bt: BitTiming) -> u32 {
+    clk / bt.brp / (bt.prop_seg + bt.phase_seg1 + bt.phase_seg2 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bit_timing() {
+        let clk = 120000000;
+        let bitrates = vec![4_020_100, 4_000_000, 3_000_000, 2_400_000, 2_000_000, 1_500_000, 1_200_000, 1_000_000,
+                                    800_000, 600_000, 520_000, 500_000, 420_000, 400_000, 320_000, 300_000, 250_000, 200_000, 125_000, 120_000, 100_000,
+                                    90_000, 80_000, 70_000, 60_000, 50_000, 40_000, 33_333, 30_000, 29_628];
+        for b in bitrates {
+            let bt = calculate_bit_timing(clk, b).unwrap();
+
+            // ensure error < 0.5%
+            println!("{:?}", &bt);
+            let err = 100.0 * (1.0 - (effective_bitrate(clk, bt) as f32 / b as f32).abs());
+            println!("{:?}", err);
+            assert!(err < 0.5);
+        }
+    }
+
+    #[test]
+    fn test_describe() {
+        let bt = BitTiming {
+            brp: 6,
+            prop_seg: 0,
+            phase_seg1: 13,
+            phase_seg2: 2,
+            sjw: 1,
+        };
+        let mut info = bt.describe(48_000_000);
+        assert_eq!(info.sample_point, 87.5);
+        assert_eq!(info.bitrate, 500_000.0);
+        assert_eq!(info.error(), None);
+        assert_eq!(
+            info.to_string(),
+            "brp=6, tseg1=13, tseg2=2, sjw=1, sample point 87.5%, 500 kbit/s"
+        );
+
+        info.requested = Some(500_000);
+        assert_eq!(
+            info.to_string(),
+            "brp=6, tseg1=13, tseg2=2, sjw=1, sample point 87.5%, 500 kbit/s @ 0.00% error"
+        );
+        info.requested = Some(512_000);
+        assert!((info.error().unwrap() + 2.34375).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_impossible_data_bitrate() {
+        // data phase limits of a typical CAN-FD controller
+        let limits = BitTimingLimits {
+            tseg1_min: 1,
+            tseg1_max: 32,
+            tseg2_min: 1,
+            tseg2_max: 16,
+            sjw_max: 16,
+            brp_min: 1,
+            brp_max: 32,
+            brp_inc: 1,
+        };
+        // two clock cycles per bit leaves no room for the bit segments
+        assert!(matches!(
+            calculate_data_bit_timing(80_000_000, 40_000_000, &limits),
+            Err(Error::InvalidBitrate(40_000_000))
+        ));
+    }
+
+    #[test]
+    fn test_data_bit_timing() {
+        let limits = BitTimingLimits {
+            tseg1_min: 1,
+            tseg1_max: 32,
+            tseg2_min: 1,
+            tseg2_max: 16,
+            sjw_max: 16,
+            brp_min: 1,
+            brp_max: 32,
+            brp_inc: 1,
+        };
+        let clk = 80_000_000;
+        for b in [1_000_000, 2_000_000, 4_000_000, 5_000_000, 8_000_000] {
+            let bt = calculate_data_bit_timing(clk, b, &limits).unwrap();
+            assert!(limits.accepts(&bt));
+            assert_eq!(effective_bitrate(clk, bt), b);
+            let sample_point = bt.describe(clk).sample_point;
+            assert!((70.0..=80.0).contains(&sample_point), "{}", sample_point);
+        }
+
+        // 2 Mbit/s is 40 quanta at a prescaler of 1, too many for these
+        // limits, and a prescaler of 3 cannot divide it exactly
+        let limits = BitTimingLimits {
+            tseg1_max: 8,
+            tseg2_max: 4,
+            brp_inc: 2,
+            ..limits
+        };
+        let bt = calculate_data_bit_timing(clk, 2_000_000, &limits).unwrap();
+        assert_eq!(bt.brp, 5);
+        assert!(limits.accepts(&bt));
+        assert!(matches!(
+            calculate_data_bit_timing(clk, 40_000_000, &limits),
+            Err(Error::InvalidBitrate(40_000_000))
+        ));
+    }
+
+    #[test]
+    fn test_bit_timing_options() {
+        let clk = 120000000;
+        let options = BitTimingOptions { sjw: 3, prop_seg: 2 };
+        let bt = calculate_bit_timing_with_options(clk, 500_000, options).unwrap();
+        assert_eq!(bt.sjw, 3);
+        assert_eq!(bt.prop_seg, 2);
+        assert!(bt.sjw <= bt.phase_seg1 && bt.sjw <= bt.phase_seg2);
+        assert_eq!(effective_bitrate(clk, bt), 500_000);
+
+        let options = BitTimingOptions { sjw: 0, prop_seg: 0 };
+        assert!(matches!(
+            calculate_bit_timing_with_options(clk, 500_000, options),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        // seg2 is at most 8, so an sjw of 9 can never be satisfied
+        let options = BitTimingOptions { sjw: 9, prop_seg: 0 };
+        assert!(matches!(
+            calculate_bit_timing_with_options(clk, 500_000, options),
+            Err(Error::InvalidBitrate(500_000))
+        ));
+    }
+}
@@ -0,0 +1,620 @@
+//! Transmit scheduling performed on a background thread.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::{atomic, Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::time;
+
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender, TryRecvError};
+
+use crate::gsusb::HostFrame;
+use crate::device::Device;
+use crate::interface::{check_channel, close_disconnected, DisconnectCallback};
+use crate::{Error, Frame};
+
+// the scheduler sleeps until this long before a deadline, then spins
+const SPIN_THRESHOLD: time::Duration = time::Duration::from_millis(1);
+
+// wait until the deadline, sleeping until it is close and then spinning,
+// which keeps the wake-up jitter far below the sleep granularity
+pub(crate) fn wait_until(deadline: time::Instant) {
+    if let Some(wake) = deadline.checked_sub(SPIN_THRESHOLD) {
+        let now = time::Instant::now();
+        if wake > now {
+            std::thread::sleep(wake - now);
+        }
+    }
+    while time::Instant::now() < deadline {
+        std::hint::spin_loop();
+    }
+}
+
+// echo IDs used by queued sends. frames sent directly use echo ID 1, so
+// queued frames use the upper half of the range, below the receive echo ID.
+const QUEUED_ECHO_ID_MIN: u32 = 0x8000_0000;
+const QUEUED_ECHO_ID_MAX: u32 = 0xFFFF_FFFE;
+// queued frames without a transmit confirmation after this long fail
+const CONFIRM_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+// how often the queue thread checks for expired confirmations when idle
+const EXPIRE_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
+// frames sent by Interface::send while a transmit window is set use this
+// echo ID, so their confirmations can be counted
+pub(crate) const WINDOW_ECHO_ID: u32 = 2;
+
+// longest time send() waits for a slot in the transmit window
+pub(crate) const TX_WINDOW_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+struct Scheduled {
+    deadline: time::Instant,
+    // higher priority frames are sent first among frames that are due
+    priority: u8,
+    // insertion order, used to send frames with equal deadlines in order
+    seq: u64,
+    frame: HostFrame,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Scheduled {}
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Scheduled {
+    // reversed so the earliest deadline is at the top of the heap
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .deadline
+            .cmp(&self.deadline)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+// a frame whose deadline has passed, ordered by priority, then deadline
+struct Due(Scheduled);
+
+impl PartialEq for Due {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Due {}
+impl PartialOrd for Due {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Due {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .priority
+            .cmp(&other.0.priority)
+            .then_with(|| self.0.cmp(&other.0))
+    }
+}
+
+/// Sends frames at scheduled times from a background thread.
+pub(crate) struct Scheduler {
+    queue: Sender<Scheduled>,
+    seq: u64,
+}
+
+impl Scheduler {
+    pub(crate) fn new(dev: Arc<Mutex<Device>>, running: Arc<RwLock<bool>>) -> Scheduler {
+        let (send, recv) = unbounded::<Scheduled>();
+
+        thread::spawn(move || {
+            let mut heap: BinaryHeap<Scheduled> = BinaryHeap::new();
+            let mut due: BinaryHeap<Due> = BinaryHeap::new();
+            loop {
+                // collect newly scheduled frames without waiting
+                loop {
+                    match recv.try_recv() {
+                        Ok(s) => heap.push(s),
+                        Err(TryRecvError::Empty) => break,
+                        // the interface was dropped
+                        Err(TryRecvError::Disconnected) => return,
+                    }
+                }
+                let now = time::Instant::now();
+                while heap.peek().is_some_and(|s| s.deadline <= now) {
+                    due.push(Due(heap.pop().unwrap()));
+                }
+
+                if let Some(Due(s)) = due.pop() {
+                    // frames scheduled while the device is stopped are dropped
+                    if *running.read().unwrap() {
+                        let _ = dev.lock().unwrap().send(s.frame);
+                    }
+                    continue;
+                }
+
+                // wait for a new frame, or until the next deadline is close
+                let next = match heap.peek() {
+                    Some(s) => {
+                        let wake = s.deadline.checked_sub(SPIN_THRESHOLD).unwrap_or(s.deadline);
+                        recv.recv_timeout(wake.saturating_duration_since(time::Instant::now()))
+                    }
+                    None => recv.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match next {
+                    Ok(s) => heap.push(s),
+                    Err(RecvTimeoutError::Timeout) => {
+                        // spin for the remaining time to minimize jitter
+                        if let Some(s) = heap.peek() {
+                            while time::Instant::now() < s.deadline {
+                                std::hint::spin_loop();
+                            }
+                        }
+                    }
+                    // the interface was dropped
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+
+        Scheduler {
+            queue: send,
+            seq: 0,
+        }
+    }
+
+    pub(crate) fn schedule(&mut self, frame: HostFrame, deadline: time::Instant, priority: u8) {
+        self.seq += 1;
+        let _ = self.queue.send(Scheduled {
+            deadline,
+            priority,
+            seq: self.seq,
+            frame,
+        });
+    }
+}
+
+/// Settings of `Interface::send`, shared with `FrameSender` handles so
+/// changes reach handles created earlier.
+pub(crate) struct TxConfig {
+    // fills data bytes missing from transmitted frames up to the DLC length
+    pub(crate) padding: u8,
+    // when true, the DLC of transmitted frames is set from their data
+    pub(crate) auto_dlc: bool,
+    // when true, confirmations are only collected by Interface::poll
+    pub(crate) polling: bool,
+    // channels that are started, mirroring the interface's channels
+    pub(crate) started: Vec<bool>,
+    // frames sent while stopped, when buffering is enabled, to be sent on
+    // the next start
+    pub(crate) buffer: Option<Vec<Frame>>,
+}
+
+impl TxConfig {
+    pub(crate) fn new(num_channels: usize) -> TxConfig {
+        TxConfig {
+            padding: 0,
+            auto_dlc: false,
+            polling: false,
+            started: vec![false; num_channels],
+            buffer: None,
+        }
+    }
+}
+
+/// The transmit path of `Interface::send`, shared with `FrameSender`
+/// handles.
+#[derive(Clone)]
+pub(crate) struct TxPath {
+    dev: Arc<Mutex<Device>>,
+    running: Arc<RwLock<bool>>,
+    on_disconnect: Arc<Mutex<Option<DisconnectCallback>>>,
+    num_channels: usize,
+    pub(crate) config: Arc<Mutex<TxConfig>>,
+    // frames sent that await their confirmation
+    pub(crate) window: Arc<TxWindow>,
+}
+
+impl TxPath {
+    pub(crate) fn new(
+        dev: Arc<Mutex<Device>>,
+        running: Arc<RwLock<bool>>,
+        on_disconnect: Arc<Mutex<Option<DisconnectCallback>>>,
+        num_channels: usize,
+    ) -> TxPath {
+        TxPath {
+            dev,
+            running,
+            on_disconnect,
+            num_channels,
+            config: Arc::new(Mutex::new(TxConfig::new(num_channels))),
+            window: Arc::new(TxWindow::new()),
+        }
+    }
+
+    // convert a frame for transmission with the current settings
+    pub(crate) fn host_frame(&self, f: &Frame) -> HostFrame {
+        let config = self.config.lock().unwrap();
+        f.to_tx_host_frame(config.padding, config.auto_dlc)
+    }
+
+    // send a frame, waiting up to `timeout` for a slot in the transmit window
+    pub(crate) fn send(&self, f: Frame, timeout: time::Duration) -> Result<(), Error> {
+        // the device behind the mutex is replaced when it is opened again,
+        // so its flag is always the current one
+        let disconnected = Arc::clone(&self.dev.lock().unwrap().disconnected);
+        if disconnected.load(atomic::Ordering::SeqCst) {
+            close_disconnected(&self.running, &self.on_disconnect);
+            return Err(Error::Disconnected);
+        }
+        check_channel(f.channel as usize, self.num_channels)?;
+        let running = *self.running.read().unwrap();
+        let (mut hf, polling) = {
+            let mut config = self.config.lock().unwrap();
+            let started = config.started[f.channel as usize];
+            if let Some(buffer) = &mut config.buffer {
+                if !running || !started {
+                    buffer.push(f);
+                    return Ok(());
+                }
+            }
+            if !running {
+                return Err(Error::NotRunning);
+            }
+            (
+                f.to_tx_host_frame(config.padding, config.auto_dlc),
+                config.polling,
+            )
+        };
+
+        if self.window.max() == 0 {
+            self.dev.lock().unwrap().send(hf)?;
+            return Ok(());
+        }
+        // a polled interface would wait for confirmations it never collects
+        let timeout = if polling {
+            time::Duration::ZERO
+        } else {
+            timeout
+        };
+        self.window.acquire(timeout)?;
+        hf.echo_id = WINDOW_ECHO_ID;
+        if let Err(e) = self.dev.lock().unwrap().send(hf) {
+            self.window.release();
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+/// A handle for sending frames from other threads, obtained with
+/// `Interface::sender`.
+///
+/// Handles can be cloned and moved to other threads, so frames can be sent
+/// while the `Interface` itself is used for configuration. Sending through a
+/// handle behaves exactly as `Interface::send`: it uses the current padding
+/// byte and automatic DLC setting, counts frames against the transmit
+/// window, and buffers frames before start when buffering is enabled.
+/// Handles share the `Interface`'s device, so they keep working after
+/// `Interface::reset_device`, including when the device had to be opened
+/// again.
+#[derive(Clone)]
+pub struct FrameSender {
+    tx: TxPath,
+}
+
+impl FrameSender {
+    pub(crate) fn new(tx: TxPath) -> FrameSender {
+        FrameSender { tx }
+    }
+
+    /// Send a CAN frame, as `Interface::send` does.
+    pub fn send(&self, f: Frame) -> Result<(), Error> {
+        self.tx.send(f, TX_WINDOW_TIMEOUT)
+    }
+}
+
+/// The outcome of a frame sent with `Interface::send_queued`.
+#[derive(Debug)]
+pub struct SendResult {
+    /// Token returned by `Interface::send_queued` for the frame.
+    pub token: u64,
+    /// Time from submitting the frame to the device to its transmit
+    /// confirmation, or the error that prevented it from being sent.
+    /// `Error::Timeout` means no confirmation was received within a second,
+    /// for example because no other node acknowledged the frame.
+    pub result: Result<time::Duration, Error>,
+}
+
+/// Queued frames awaiting their transmit confirmation, shared between the
+/// queue thread and the receive path.
+pub(crate) struct Completions {
+    // echo ID -> (token, submit time)
+    pending: HashMap<u32, (u64, time::Instant)>,
+    send: Sender<SendResult>,
+    recv: Receiver<SendResult>,
+}
+
+impl Completions {
+    pub(crate) fn new() -> Completions {
+        let (send, recv) = unbounded();
+        Completions {
+            pending: HashMap::new(),
+            send,
+            recv,
+        }
+    }
+
+    pub(crate) fn results(&self) -> Receiver<SendResult> {
+        self.recv.clone()
+    }
+
+    fn submit(&mut self, echo_id: u32, token: u64, now: time::Instant) {
+        self.pending.insert(echo_id, (token, now));
+    }
+
+    fn fail(&mut self, echo_id: u32, e: Error) {
+        if let Some((token, _)) = self.pending.remove(&echo_id) {
+            let _ = self.send.send(SendResult {
+                token,
+                result: Err(e),
+            });
+        }
+    }
+
+    /// Report the frame with this echo ID as confirmed. Echo IDs that are not
+    /// pending, such as those of frames sent directly, are ignored.
+    pub(crate) fn confirm(&mut self, echo_id: u32, now: time::Instant) {
+        if let Some((token, submitted)) = self.pending.remove(&echo_id) {
+            let _ = self.send.send(SendResult {
+                token,
+                result: Ok(now.saturating_duration_since(submitted)),
+            });
+        }
+    }
+
+    fn expire(&mut self, now: time::Instant) {
+        let expired: Vec<u32> = self
+            .pending
+            .iter()
+            .filter(|(_, (_, submitted))| now.saturating_duration_since(*submitted) >= CONFIRM_TIMEOUT)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            self.fail(id, Error::Timeout);
+        }
+    }
+}
+
+/// Sends queued frames from a background thread, tagging each with an echo
+/// ID so its confirmation can be matched.
+pub(crate) struct TxQueue {
+    queue: Sender<(u64, HostFrame)>,
+    next_token: u64,
+}
+
+impl TxQueue {
+    pub(crate) fn new(
+        dev: Arc<Mutex<Device>>,
+        running: Arc<RwLock<bool>>,
+        completions: Arc<Mutex<Completions>>,
+    ) -> TxQueue {
+        let (send, recv) = unbounded::<(u64, HostFrame)>();
+
+        thread::spawn(move || {
+            let mut echo_id = QUEUED_ECHO_ID_MIN;
+            loop {
+                match recv.recv_timeout(EXPIRE_INTERVAL) {
+                    Ok((token, mut hf)) => {
+                        hf.echo_id = echo_id;
+                        echo_id = if echo_id == QUEUED_ECHO_ID_MAX {
+                            QUEUED_ECHO_ID_MIN
+                        } else {
+                            echo_id + 1
+                        };
+                        // registered before sending, since the confirmation
+                        // can arrive before send() returns
+                        completions
+                            .lock()
+                            .unwrap()
+                            .submit(hf.echo_id, token, time::Instant::now());
+                        let result = if *running.read().unwrap() {
+                            dev.lock().unwrap().send(hf.clone()).map_err(Error::from)
+                        } else {
+                            Err(Error::NotRunning)
+                        };
+                        if let Err(e) = result {
+                            completions.lock().unwrap().fail(hf.echo_id, e);
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    // the interface was dropped
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                completions.lock().unwrap().expire(time::Instant::now());
+            }
+        });
+
+        TxQueue {
+            queue: send,
+            next_token: 0,
+        }
+    }
+
+    pub(crate) fn queue(&mut self, frame: HostFrame) -> u64 {
+        self.next_token += 1;
+        let _ = self.queue.send((self.next_token, frame));
+        self.next_token
+    }
+}
+
+/// Limits the number of frames awaiting their transmit confirmation.
+pub(crate) struct TxWindow {
+    // frames in flight, and the most allowed with zero for no limit
+    state: Mutex<(usize, usize)>,
+    freed: Condvar,
+}
+
+impl TxWindow {
+    pub(crate) fn new() -> TxWindow {
+        TxWindow {
+            state: Mutex::new((0, 0)),
+            freed: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn set_max(&self, max: usize) {
+        self.state.lock().unwrap().1 = max;
+        self.freed.notify_all();
+    }
+
+    pub(crate) fn max(&self) -> usize {
+        self.state.lock().unwrap().1
+    }
+
+    // take a slot in the window, waiting up to `timeout` for one to be freed
+    pub(crate) fn acquire(&self, timeout: time::Duration) -> Result<(), Error> {
+        let deadline = time::Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        while state.1 != 0 && state.0 >= state.1 {
+            let remaining = deadline.saturating_duration_since(time::Instant::now());
+            if remaining.is_zero() {
+                return Err(Error::TxWindowFull);
+            }
+            state = self.freed.wait_timeout(state, remaining).unwrap().0;
+        }
+        state.0 += 1;
+        Ok(())
+    }
+
+    // free a slot, when a frame is confirmed or could not be sent
+    pub(crate) fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.0 = state.0.saturating_sub(1);
+        self.freed.notify_one();
+    }
+
+    // forget frames in flight, whose confirmations will not arrive
+    pub(crate) fn reset(&self) {
+        self.state.lock().unwrap().0 = 0;
+        self.freed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduled(deadline: time::Instant, seq: u64) -> Scheduled {
+        Scheduled {
+            deadline,
+            priority: 0,
+            seq,
+            frame: HostFrame {
+                echo_id: 0,
+                can_id: seq as u32,
+                can_dlc: 0,
+                channel: 0,
+                flags: 0,
+                reserved: 0,
+                data: [0u8; 64],
+                timestamp: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_schedule_order() {
+        let now = time::Instant::now();
+        let mut heap = BinaryHeap::new();
+        heap.push(scheduled(now + time::Duration::from_millis(20), 1));
+        heap.push(scheduled(now + time::Duration::from_millis(10), 2));
+        heap.push(scheduled(now + time::Duration::from_millis(20), 3));
+        heap.push(scheduled(now, 4));
+
+        let order: Vec<u64> = std::iter::from_fn(|| heap.pop().map(|s| s.seq)).collect();
+        assert_eq!(order, vec![4, 2, 1, 3]);
+    }
+
+    #[test]
+    fn test_due_order() {
+        let now = time::Instant::now();
+        let mut due = BinaryHeap::new();
+        for (seq, priority) in [(1, 0), (2, 5), (3, 0), (4, 5)] {
+            let mut s = scheduled(now, seq);
+            s.priority = priority;
+            due.push(Due(s));
+        }
+        // higher priority first, then in order of scheduling
+        let order: Vec<u64> = std::iter::from_fn(|| due.pop().map(|d| d.0.seq)).collect();
+        assert_eq!(order, vec![2, 4, 1, 3]);
+    }
+
+    #[test]
+    fn test_wait_until() {
+        let deadline = time::Instant::now() + time::Duration::from_millis(3);
+        wait_until(deadline);
+        assert!(time::Instant::now() >= deadline);
+        // a deadline in the past returns at once
+        wait_until(deadline - time::Duration::from_millis(2));
+    }
+
+    #[test]
+    fn test_completions() {
+        let mut c = Completions::new();
+        let results = c.results();
+        let start = time::Instant::now();
+        c.submit(QUEUED_ECHO_ID_MIN, 1, start);
+        c.submit(QUEUED_ECHO_ID_MIN + 1, 2, start);
+        c.submit(QUEUED_ECHO_ID_MIN + 2, 3, start);
+
+        c.confirm(QUEUED_ECHO_ID_MIN + 1, start + time::Duration::from_millis(2));
+        // unknown echo IDs are ignored
+        c.confirm(1, start);
+        c.fail(QUEUED_ECHO_ID_MIN + 2, Error::NotRunning);
+        c.expire(start + CONFIRM_TIMEOUT);
+
+        let r: Vec<SendResult> = results.try_iter().collect();
+        assert_eq!(r.len(), 3);
+        assert_eq!(r[0].token, 2);
+        assert_eq!(*r[0].result.as_ref().unwrap(), time::Duration::from_millis(2));
+        assert!(matches!(r[1], SendResult { token: 3, result: Err(Error::NotRunning) }));
+        assert!(matches!(r[2], SendResult { token: 1, result: Err(Error::Timeout) }));
+        assert!(c.pending.is_empty());
+    }
+
+    #[test]
+    fn test_tx_window() {
+        let window = Arc::new(TxWindow::new());
+        let none = time::Duration::ZERO;
+        // no limit by default
+        for _ in 0..10 {
+            window.acquire(none).unwrap();
+        }
+        window.reset();
+
+        window.set_max(2);
+        window.acquire(none).unwrap();
+        window.acquire(none).unwrap();
+        assert!(matches!(window.acquire(none), Err(Error::TxWindowFull)));
+        window.release();
+        window.acquire(none).unwrap();
+
+        // a waiting sender continues once a confirmation frees a slot
+        let w = Arc::clone(&window);
+        let confirm = thread::spawn(move || {
+            thread::sleep(time::Duration::from_millis(20));
+            w.release();
+        });
+        window.acquire(time::Duration::from_secs(5)).unwrap();
+        confirm.join().unwrap();
+    }
+
+    #[test]
+    fn test_sender_is_shareable() {
+        fn assert_send_sync<T: Send + Sync + Clone>() {}
+        assert_send_sync::<FrameSender>();
+    }
+}
@@ -0,0 +1,46 @@
+use crate::Error;
+use cantact::socketcan::{SocketCan, SocketCanError};
+use cantact::{Frame, Interface};
+use clap::ArgMatches;
+use log::info;
+
+use crate::config::read_config;
+use crate::helpers;
+
+pub fn cmd(matches: &ArgMatches) -> Result<(), Error> {
+    let flag = helpers::initialize_ctrlc();
+    let mut config = read_config();
+
+    let ch = helpers::parse_channel(matches)?.unwrap_or(0);
+    // only the bridged channel is used
+    for n in 0..config.channels.len() {
+        if n != ch {
+            config.channels[n].enabled = false;
+        }
+    }
+    info!("config: {:?}", config);
+
+    let ifname = matches.value_of("interface").unwrap();
+    let sock = match SocketCan::open(ifname) {
+        Ok(s) => s,
+        Err(e) => return Err(Error::InvalidArgument(format!("{}: {:?}", ifname, e))),
+    };
+
+    // initialize the interface
+    let mut i = Interface::new()?;
+    config.apply_to_interface(&mut i)?;
+
+    // start the device
+    info!("bridging channel {} to {}", ch, ifname);
+    i.start(ch, move |_: Frame| {})
+        .expect("failed to start device");
+
+    let result = sock.bridge(&mut i, ch, &flag);
+
+    i.stop(ch).expect("failed to stop device");
+    match result {
+        Ok(_) => Ok(()),
+        Err(SocketCanError::Interface(e)) => Err(e.into()),
+        Err(SocketCanError::Io(e)) => Err(Error::InvalidArgument(format!("{:?}", e))),
+    }
+}
@@ -1,11 +1,11 @@
 use crate::Error;
 use clap::ArgMatches;
 
-use crate::config::Config;
+use crate::config::{read_config, write_config};
 use crate::helpers;
 
 pub fn cmd(matches: &ArgMatches) -> Result<(), Error> {
-    let mut config = Config::read();
+    let mut config = read_config();
 
     let ch = match helpers::parse_channel(matches)? {
         None => {
@@ -48,7 +48,7 @@ pub fn cmd(matches: &ArgMatches) -> Result<(), Error> {
         config.channels[ch].data_bitrate = data_bitrate;
     }
 
-    config.write().unwrap();
+    write_config(&config).unwrap();
 
     print!("{}", config);
     Ok(())
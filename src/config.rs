@@ -1,12 +1,8 @@
-use crate::Error;
 use app_dirs::*;
-use cantact::{Channel, Interface};
-use log::info;
-use serde::{Deserialize, Serialize};
-use std::fmt;
+use cantact::Config;
+use log::{info, warn};
 use std::fs;
 use std::io;
-use std::default;
 use std::io::prelude::*;
 use std::fs::File;
 use std::path::Path;
@@ -16,89 +12,111 @@ const APP_INFO: AppInfo = AppInfo {
     author: "Linklayer",
 };
 const CFG_FILE: &str = "cantact.toml";
-const DEFAULT_CONFIG: Channel = Channel {
-    bitrate: 500_000,
-    data_bitrate: 500_000,
-    loopback: false,
-    monitor: false,
-    fd: false,
-    enabled: true,
-    started: true,
-};
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Config {
-    #[serde(rename = "channel")]
-    pub channels: Vec<Channel>,
+// since config files are not mandatory, this should never fail
+pub fn read_config() -> Config {
+    let dir = match get_app_root(AppDataType::UserConfig, &APP_INFO) {
+        Ok(d) => d,
+        Err(_) => return Config::default(),
+    };
+    let filename = Path::new("").join(dir).join(CFG_FILE);
+    let s = match fs::read_to_string(&filename) {
+        Ok(s) => s,
+        Err(_) => return Config::default(),
+    };
+    let result = parse(&s).unwrap_or_else(|e| {
+        warn!("ignoring invalid configuration {:?}: {}", filename, e);
+        Config::default()
+    });
+    info!("read configuration from {:?}", filename);
+    result
 }
 
-impl fmt::Display for Config {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "Channels:")?;
-        for (n, ch) in self.channels.iter().enumerate() {
-            writeln!(f, "\t{} -> {:?}", n, ch)?;
-        }
-        Ok(())
-    }
+// parse a configuration file, upgrading it to the current version
+fn parse(s: &str) -> Result<Config, toml::de::Error> {
+    let mut value: toml::Value = toml::from_str(s)?;
+    migrate(&mut value);
+    value.try_into()
 }
 
-impl default::Default for Config {
-    fn default() -> Config {
-        Config {
-            channels: vec![DEFAULT_CONFIG, DEFAULT_CONFIG],
+// upgrade a configuration file of any earlier version. channel settings
+// missing from the file take the default values, so fields added in later
+// releases do not cause older files to be rejected.
+fn migrate(value: &mut toml::Value) {
+    let table = match value.as_table_mut() {
+        Some(t) => t,
+        None => return,
+    };
+    let version = table
+        .get("version")
+        .and_then(|v| v.as_integer())
+        .unwrap_or(0);
+    let current = Config::VERSION as i64;
+    if version > current {
+        warn!(
+            "configuration version {} is newer than supported version {}",
+            version, current
+        );
+    }
+
+    let defaults = toml::Value::try_from(Config::DEFAULT_CHANNEL).unwrap();
+    let defaults = defaults.as_table().unwrap();
+    if let Some(channels) = table.get_mut("channel").and_then(|c| c.as_array_mut()) {
+        for ch in channels.iter_mut().filter_map(|c| c.as_table_mut()) {
+            for (k, v) in defaults.iter() {
+                ch.entry(k.clone()).or_insert_with(|| v.clone());
+            }
         }
     }
+    if version < current {
+        table.insert("version".to_string(), toml::Value::Integer(current));
+    }
 }
 
-impl Config {
-    // since config files are not mandatory, this should never fail
-    pub fn read() -> Config {
-        let dir = match get_app_root(AppDataType::UserConfig, &APP_INFO) {
-            Ok(d) => d,
-            Err(_) => return Config::default(),
-        };
-        let filename = Path::new("").join(dir).join(CFG_FILE);
-        let s = match fs::read_to_string(&filename) {
-            Ok(s) => s,
-            Err(_) => return Config::default(),
-        };
-        let result = toml::from_str(&s).unwrap_or_else(|_| Config::default());
-        info!("read configuration from {:?}", filename);
-        result
-    }
+pub fn write_config(config: &Config) -> io::Result<()> {
+    let dir = match get_app_root(AppDataType::UserConfig, &APP_INFO) {
+        Ok(d) => d,
+        Err(AppDirsError::NotSupported) => panic!("platform does not support configuation"),
+        Err(AppDirsError::Io(e)) => {
+            panic!("IO error determining configuration location: {:?}", e)
+        }
+        Err(AppDirsError::InvalidAppInfo) => panic!("app info struct is invalid"),
+    };
+    fs::create_dir_all(&dir)?;
+    let filename = Path::new("").join(dir).join(CFG_FILE);
+    info!("writing configuration to {:?}", filename);
 
-    pub fn write(&self) -> io::Result<()> {
-        let dir = match get_app_root(AppDataType::UserConfig, &APP_INFO) {
-            Ok(d) => d,
-            Err(AppDirsError::NotSupported) => panic!("platform does not support configuation"),
-            Err(AppDirsError::Io(e)) => {
-                panic!("IO error determining configuration location: {:?}", e)
-            }
-            Err(AppDirsError::InvalidAppInfo) => panic!("app info struct is invalid"),
-        };
-        fs::create_dir_all(&dir)?;
-        let filename = Path::new("").join(dir).join(CFG_FILE);
-        info!("writing configuration to {:?}", filename);
+    let mut file = File::create(filename)?;
+    file.write_all(toml::to_string(config).unwrap().as_bytes())
+}
 
-        let mut file = File::create(filename)?;
-        file.write_all(toml::to_string(&self).unwrap().as_bytes())
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    pub fn apply_to_interface(&self, i: &mut Interface) -> Result<(), Error> {
-        for (n, ch) in self.channels.iter().enumerate() {
-            if n > (i.channels() - 1) {
-                // device doesn't have as many channels as config, ignore the rest
-                break;
-            }
-            i.set_bitrate(n, ch.bitrate)?;
-            i.set_enabled(n, ch.enabled)?;
-            i.set_loopback(n, ch.loopback)?;
-            i.set_monitor(n, ch.monitor)?;
-            if i.supports_fd() {
-                i.set_fd(n, ch.fd)?;
-                i.set_data_bitrate(n, ch.data_bitrate)?;
-            }
-        }
-        Ok(())
+    #[test]
+    fn test_migrate() {
+        // an unversioned file written before the `fd` fields existed
+        let old = r#"
+            [[channel]]
+            bitrate = 250000
+            enabled = true
+            loopback = false
+            monitor = true
+        "#;
+        let c = parse(old).unwrap();
+        assert_eq!(c.version, Config::VERSION);
+        assert_eq!(c.channels.len(), 1);
+        assert_eq!(c.channels[0].bitrate, 250_000);
+        assert!(c.channels[0].monitor);
+        assert!(!c.channels[0].fd);
+        let defaults = Config::DEFAULT_CHANNEL;
+        assert_eq!(c.channels[0].data_bitrate, defaults.data_bitrate);
+
+        // current files round trip unchanged
+        let s = toml::to_string(&Config::default()).unwrap();
+        let c = parse(&s).unwrap();
+        assert_eq!(c.version, Config::VERSION);
+        assert_eq!(c.channels.len(), 2);
     }
 }
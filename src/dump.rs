@@ -1,22 +1,35 @@
 use crate::Error;
-use cantact::{Frame, Interface};
+use cantact::dbc::Database;
+use cantact::export;
+use cantact::{Direction, Frame, Interface};
 use clap::ArgMatches;
 use log::info;
 
-use crate::config::Config;
+use crate::config::read_config;
 use crate::helpers;
 
-fn print_frame(f: Frame) {
+fn print_frame(f: Frame, db: Option<&Database>) {
     let ts = match f.timestamp {
         Some(t) => format!("{:.6}\t", t.as_secs_f32()),
         None => String::new(),
     };
 
+    let dir = match f.direction() {
+        Direction::Rx => "Rx",
+        Direction::Tx => "Tx",
+    };
+
     if f.err {
-        println!("{}  ch:{} error frame", ts, f.channel);
+        println!("{}  ch:{} {} error frame", ts, f.channel, dir);
     }
 
-    let mut s = format!("{}  ch:{} {:03X}", ts, f.channel, f.can_id,);
+    let mut s = format!("{}  ch:{} {} {:03X}", ts, f.channel, dir, f.can_id,);
+
+    // decode known messages, falling back to hex for unknown IDs
+    if let Some(decoded) = db.and_then(|db| db.format(&f)) {
+        println!("{}   {}", s, decoded);
+        return;
+    }
 
     s = if f.fd {
         format!("{}   [{:02}]  ", s, f.data_len())
@@ -24,7 +37,7 @@ fn print_frame(f: Frame) {
         format!("{}   [{:01}]  ", s, f.data_len())
     };
 
-    for b in f.data.iter().take(f.data_len()) {
+    for b in f.payload() {
         s = format!("{}{:02X} ", s, b);
     }
     println!("{}", s)
@@ -32,7 +45,7 @@ fn print_frame(f: Frame) {
 
 pub fn cmd(matches: &ArgMatches) -> Result<(), Error> {
     let flag = helpers::initialize_ctrlc();
-    let mut config = Config::read();
+    let mut config = read_config();
 
     let ch = helpers::parse_channel(matches)?;
     match ch {
@@ -48,14 +61,29 @@ pub fn cmd(matches: &ArgMatches) -> Result<(), Error> {
     }
     info!("config: {:?}", config);
 
+    let db = match matches.value_of("dbc") {
+        Some(path) => match Database::from_file(path) {
+            Ok(db) => Some(db),
+            Err(e) => return Err(Error::InvalidArgument(format!("{:?}", e))),
+        },
+        None => None,
+    };
+
+    let format = matches.value_of("format").unwrap_or("text").to_string();
+    if format == "csv" {
+        println!("{}", export::CSV_HEADER);
+    }
+
     // initialize the interface
     let mut i = Interface::new()?;
     config.apply_to_interface(&mut i)?;
 
     // start the device
     info!("starting dump");
-    i.start(ch.unwrap(), move |f: Frame| {
-        print_frame(f);
+    i.start(ch.unwrap(), move |f: Frame| match format.as_str() {
+        "csv" => println!("{}", export::format_csv(&f)),
+        "json" => println!("{}", export::format_json(&f)),
+        _ => print_frame(f, db.as_ref()),
     })
     .expect("failed to start device");
 
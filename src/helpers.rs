@@ -36,7 +36,7 @@ pub fn parse_channel(matches: &ArgMatches) -> Result<Option<usize>, Error> {
         Err(_) => Err(Error::InvalidArgument(String::from(
             "invalid channel value",
         ))),
-        Ok(ch) if ch > MAX_CHANNELS => Err(Error::InvalidArgument(String::from(
+        Ok(ch) if ch >= MAX_CHANNELS => Err(Error::InvalidArgument(String::from(
             "channel value out of range",
         ))),
         Ok(ch) => Ok(Some(ch)),
@@ -4,9 +4,12 @@ use clap::App;
 use simplelog::*;
 
 // commands
+#[cfg(target_os = "linux")]
+mod bridge;
 mod cfg;
 mod dump;
 mod send;
+mod server;
 
 pub mod config;
 pub mod helpers;
@@ -37,6 +40,9 @@ fn main() {
         ("dump", Some(m)) => dump::cmd(m),
         ("send", Some(m)) => send::cmd(m),
         ("cfg", Some(m)) => cfg::cmd(m),
+        ("server", Some(m)) => server::cmd(m),
+        #[cfg(target_os = "linux")]
+        ("bridge", Some(m)) => bridge::cmd(m),
         _ => Ok(()),
     };
 
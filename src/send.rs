@@ -5,13 +5,13 @@ use log::info;
 use std::thread;
 use std::time::Duration;
 
-use crate::config::Config;
+use crate::config::read_config;
 use crate::helpers;
 
 pub fn cmd(matches: &ArgMatches) -> Result<(), Error> {
     let flag = helpers::initialize_ctrlc();
 
-    let mut config = Config::read();
+    let mut config = read_config();
 
     let ch = helpers::parse_channel(matches)?;
     match ch {
@@ -0,0 +1,50 @@
+use crate::Error;
+use cantact::server::{Server, ServerError};
+use cantact::{Frame, Interface};
+use clap::ArgMatches;
+use log::info;
+
+use crate::config::read_config;
+use crate::helpers;
+
+// clients are not authenticated, so only local clients are accepted unless
+// another address is given
+const DEFAULT_LISTEN: &str = "127.0.0.1:9500";
+
+pub fn cmd(matches: &ArgMatches) -> Result<(), Error> {
+    let flag = helpers::initialize_ctrlc();
+    let mut config = read_config();
+
+    let ch = helpers::parse_channel(matches)?.unwrap_or(0);
+    // only the bridged channel is used
+    for n in 0..config.channels.len() {
+        if n != ch {
+            config.channels[n].enabled = false;
+        }
+    }
+    info!("config: {:?}", config);
+
+    let addr = matches.value_of("listen").unwrap_or(DEFAULT_LISTEN);
+    let server = match Server::bind(addr) {
+        Ok(s) => s,
+        Err(e) => return Err(Error::InvalidArgument(format!("{:?}", e))),
+    };
+
+    // initialize the interface
+    let mut i = Interface::new()?;
+    config.apply_to_interface(&mut i)?;
+
+    // start the device
+    info!("starting server on {}", addr);
+    i.start(ch, move |_: Frame| {})
+        .expect("failed to start device");
+
+    let result = server.run(&mut i, &flag);
+
+    i.stop(ch).expect("failed to stop device");
+    match result {
+        Ok(_) => Ok(()),
+        Err(ServerError::Interface(e)) => Err(e.into()),
+        Err(ServerError::Io(e)) => Err(Error::InvalidArgument(format!("{:?}", e))),
+    }
+}